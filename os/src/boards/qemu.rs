@@ -21,19 +21,30 @@ use crate::drivers::block::BLOCK_DEVICE;
 use crate::drivers::chardev::{CharDevice, UART};
 use crate::drivers::plic::{IntrTargetPriority, PLIC};
 use crate::drivers::{KEYBOARD_DEVICE, MOUSE_DEVICE};
+use crate::hart::hart_id;
+
+//irq nums: 5 keyboard, 6 mouse, 8 block, 10 uart
+const IRQS: [usize; 4] = [5, 6, 8, 10];
 
 pub fn device_init() {
+    let mut plic = unsafe { PLIC::new(VIRT_PLIC) };
+    for intr_src_id in IRQS {
+        plic.set_priority(intr_src_id, 1);
+    }
+    device_init_hart();
+}
+
+/// Route the device interrupts to the running hart too, the PLIC hands each one to a single hart.
+pub fn device_init_hart() {
     use riscv::register::sie;
     let mut plic = unsafe { PLIC::new(VIRT_PLIC) };
-    let hart_id: usize = 0;
+    let hart_id = hart_id();
     let supervisor = IntrTargetPriority::Supervisor;
     let machine = IntrTargetPriority::Machine;
     plic.set_threshold(hart_id, supervisor, 0);
     plic.set_threshold(hart_id, machine, 1);
-    //irq nums: 5 keyboard, 6 mouse, 8 block, 10 uart
-    for intr_src_id in [5usize, 6, 8, 10] {
+    for intr_src_id in IRQS {
         plic.enable(hart_id, supervisor, intr_src_id);
-        plic.set_priority(intr_src_id, 1);
     }
     unsafe {
         sie::set_sext();
@@ -42,14 +53,16 @@ pub fn device_init() {
 
 pub fn irq_handler() {
     let mut plic = unsafe { PLIC::new(VIRT_PLIC) };
-    let intr_src_id = plic.claim(0, IntrTargetPriority::Supervisor);
+    let intr_src_id = plic.claim(hart_id(), IntrTargetPriority::Supervisor);
     debug!("irq_handler: {}", intr_src_id);
     match intr_src_id {
+        // another hart claimed it first
+        0 => return,
         5 => KEYBOARD_DEVICE.handle_irq(),
         6 => MOUSE_DEVICE.handle_irq(),
         8 => BLOCK_DEVICE.handle_irq(),
         10 => UART.handle_irq(),
         _ => panic!("unsupported IRQ {}", intr_src_id),
     }
-    plic.complete(0, IntrTargetPriority::Supervisor, intr_src_id);
+    plic.complete(hart_id(), IntrTargetPriority::Supervisor, intr_src_id);
 }
@@ -1,12 +1,24 @@
 #[allow(unused)]
 
 pub const USER_STACK_SIZE: usize = 4096 * 2;
+pub const USER_HEAP_LIMIT: usize = 0x1000_0000;
 pub const KERNEL_STACK_SIZE: usize = 4096 * 2;
 pub const KERNEL_HEAP_SIZE: usize = 0x100_0000;
 pub const PAGE_SIZE: usize = 0x1000;
 pub const PAGE_SIZE_BITS: usize = 0xc;
+pub const MAX_FD: usize = 1024;
+/// harts the kernel can run on, keep in sync with the boot stacks in entry.asm
+pub const MAX_HARTS: usize = 8;
 
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT_BASE: usize = TRAMPOLINE - PAGE_SIZE;
+/// where position-independent executables are loaded
+pub const PIE_BASE: usize = 0x2000_0000;
+pub const MMAP_BASE: usize = 0x10_0000_0000;
+pub const MMAP_END: usize = 0x40_0000_0000;
+/// where the dynamic loader of a program is mapped, mmap keeps clear of it
+pub const INTERP_BASE: usize = MMAP_BASE;
+/// size of the swap device, see `SWAP_IMG` in the Makefile
+pub const SWAP_SIZE: usize = 0x400_0000;
 
 pub use crate::board::{CLOCK_FREQ, MEMORY_END, MMIO};
@@ -1,6 +1,8 @@
 use crate::drivers::chardev::CharDevice;
 use crate::drivers::chardev::UART;
+use crate::sync::SpinNoIrqLock;
 use core::fmt::{self, Write};
+use lazy_static::*;
 
 struct Stdout;
 
@@ -13,7 +15,14 @@ impl Write for Stdout {
     }
 }
 
+lazy_static! {
+    /// keeps lines printed by different harts apart
+    static ref PRINT_LOCK: SpinNoIrqLock<()> = unsafe { SpinNoIrqLock::new(()) };
+}
+
 pub fn print(args: fmt::Arguments) {
+    // a panic while printing still gets its message out
+    let _guard = PRINT_LOCK.try_exclusive_access();
     Stdout.write_fmt(args).unwrap();
 }
 
@@ -7,8 +7,13 @@ use alloc::sync::Arc;
 use easy_fs::BlockDevice;
 use lazy_static::*;
 
+const VIRTIO3: usize = 0x10003000;
+
 lazy_static! {
     pub static ref BLOCK_DEVICE: Arc<dyn BlockDevice> = Arc::new(BlockDeviceImpl::new());
+    /// The second block device is used for swap if there is one.
+    pub static ref SWAP_DEVICE: Option<Arc<dyn BlockDevice>> = VirtIOBlock::probe(VIRTIO3, true)
+        .map(|device| Arc::new(device) as Arc<dyn BlockDevice>);
 }
 
 #[allow(unused)]
@@ -1,6 +1,6 @@
 use super::BlockDevice;
 use crate::drivers::bus::virtio::VirtioHal;
-use crate::sync::{Condvar, UPIntrFreeCell};
+use crate::sync::{Condvar, SpinNoIrqLock};
 use crate::task::schedule;
 use crate::DEV_NON_BLOCKING_ACCESS;
 use alloc::collections::BTreeMap;
@@ -10,13 +10,15 @@ use virtio_drivers::{BlkResp, RespStatus, VirtIOBlk, VirtIOHeader};
 const VIRTIO0: usize = 0x10008000;
 
 pub struct VirtIOBlock {
-    virtio_blk: UPIntrFreeCell<VirtIOBlk<'static, VirtioHal>>,
+    virtio_blk: SpinNoIrqLock<VirtIOBlk<'static, VirtioHal>>,
     condvars: BTreeMap<u16, Condvar>,
+    /// never wait for interrupts, even after `DEV_NON_BLOCKING_ACCESS` is set
+    polling: bool,
 }
 
 impl BlockDevice for VirtIOBlock {
     fn read_block(&self, block_id: usize, buf: &mut [u8]) {
-        let nb = *DEV_NON_BLOCKING_ACCESS.exclusive_access();
+        let nb = !self.polling && *DEV_NON_BLOCKING_ACCESS.exclusive_access();
         if nb {
             let mut resp = BlkResp::default();
             let task_cx_ptr = self.virtio_blk.exclusive_session(|blk| {
@@ -37,7 +39,7 @@ impl BlockDevice for VirtIOBlock {
         }
     }
     fn write_block(&self, block_id: usize, buf: &[u8]) {
-        let nb = *DEV_NON_BLOCKING_ACCESS.exclusive_access();
+        let nb = !self.polling && *DEV_NON_BLOCKING_ACCESS.exclusive_access();
         if nb {
             let mut resp = BlkResp::default();
             let task_cx_ptr = self.virtio_blk.exclusive_session(|blk| {
@@ -68,20 +70,27 @@ impl BlockDevice for VirtIOBlock {
 
 impl VirtIOBlock {
     pub fn new() -> Self {
-        let virtio_blk = unsafe {
-            UPIntrFreeCell::new(
-                VirtIOBlk::<VirtioHal>::new(&mut *(VIRTIO0 as *mut VirtIOHeader)).unwrap(),
-            )
-        };
+        Self::probe(VIRTIO0, false).unwrap()
+    }
+    /// Return `None` if there is no block device at `base`.
+    /// A `polling` device never blocks the current task, so it can be used
+    /// while the caller holds other cells.
+    pub fn probe(base: usize, polling: bool) -> Option<Self> {
+        let header = unsafe { &mut *(base as *mut VirtIOHeader) };
+        if !header.verify() {
+            return None;
+        }
+        let virtio_blk = unsafe { SpinNoIrqLock::new(VirtIOBlk::<VirtioHal>::new(header).ok()?) };
         let mut condvars = BTreeMap::new();
         let channels = virtio_blk.exclusive_access().virt_queue_size();
         for i in 0..channels {
             let condvar = Condvar::new();
             condvars.insert(i, condvar);
         }
-        Self {
+        Some(Self {
             virtio_blk,
             condvars,
-        }
+            polling,
+        })
     }
 }
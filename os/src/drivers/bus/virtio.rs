@@ -2,14 +2,14 @@ use crate::mm::{
     FrameTracker, PageTable, PhysAddr, PhysPageNum, StepByOne, VirtAddr, frame_alloc_more,
     frame_dealloc, kernel_token,
 };
-use crate::sync::UPIntrFreeCell;
+use crate::sync::SpinNoIrqLock;
 use alloc::vec::Vec;
 use lazy_static::*;
 use virtio_drivers::Hal;
 
 lazy_static! {
-    static ref QUEUE_FRAMES: UPIntrFreeCell<Vec<FrameTracker>> =
-        unsafe { UPIntrFreeCell::new(Vec::new()) };
+    static ref QUEUE_FRAMES: SpinNoIrqLock<Vec<FrameTracker>> =
+        unsafe { SpinNoIrqLock::new(Vec::new()) };
 }
 
 pub struct VirtioHal;
@@ -17,7 +17,7 @@ pub struct VirtioHal;
 impl Hal for VirtioHal {
     fn dma_alloc(pages: usize) -> usize {
         let trakcers = frame_alloc_more(pages);
-        let ppn_base = trakcers.as_ref().unwrap().last().unwrap().ppn;
+        let ppn_base = trakcers.as_ref().unwrap().first().unwrap().ppn;
         QUEUE_FRAMES
             .exclusive_access()
             .append(&mut trakcers.unwrap());
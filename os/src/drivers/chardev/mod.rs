@@ -1,6 +1,7 @@
 mod ns16550a;
 
 use crate::board::CharDeviceImpl;
+use crate::task::TaskControlBlock;
 use alloc::sync::Arc;
 use lazy_static::*;
 pub use ns16550a::NS16550a;
@@ -8,6 +9,12 @@ pub use ns16550a::NS16550a;
 pub trait CharDevice {
     fn init(&self);
     fn read(&self) -> u8;
+    /// Pop a received byte if there is one, never blocks.
+    fn try_read(&self) -> Option<u8>;
+    fn read_ready(&self) -> bool;
+    /// Wake `task` when new input arrives.
+    fn register_waiter(&self, task: Arc<TaskControlBlock>);
+    fn unregister_waiter(&self, task: &Arc<TaskControlBlock>);
     fn write(&self, ch: u8);
     fn handle_irq(&self);
 }
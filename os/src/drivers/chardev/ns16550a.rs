@@ -2,9 +2,10 @@
 ///! Ref: ns16550a datasheet: https://datasheetspdf.com/pdf-file/605590/NationalSemiconductor/NS16550A/1
 ///! Ref: ns16450 datasheet: https://datasheetspdf.com/pdf-file/1311818/NationalSemiconductor/NS16450/1
 use super::CharDevice;
-use crate::sync::{Condvar, UPIntrFreeCell};
-use crate::task::schedule;
+use crate::sync::{Condvar, SpinNoIrqLock, WaitQueue};
+use crate::task::{TaskControlBlock, schedule};
 use alloc::collections::VecDeque;
+use alloc::sync::Arc;
 use bitflags::*;
 use volatile::{ReadOnly, Volatile, WriteOnly};
 
@@ -125,8 +126,9 @@ struct NS16550aInner {
 }
 
 pub struct NS16550a<const BASE_ADDR: usize> {
-    inner: UPIntrFreeCell<NS16550aInner>,
+    inner: SpinNoIrqLock<NS16550aInner>,
     condvar: Condvar,
+    poll_waiters: WaitQueue,
 }
 
 impl<const BASE_ADDR: usize> NS16550a<BASE_ADDR> {
@@ -137,8 +139,9 @@ impl<const BASE_ADDR: usize> NS16550a<BASE_ADDR> {
         };
         //inner.ns16550a.init();
         Self {
-            inner: unsafe { UPIntrFreeCell::new(inner) },
+            inner: unsafe { SpinNoIrqLock::new(inner) },
             condvar: Condvar::new(),
+            poll_waiters: WaitQueue::new(),
         }
     }
 
@@ -167,6 +170,19 @@ impl<const BASE_ADDR: usize> CharDevice for NS16550a<BASE_ADDR> {
             }
         }
     }
+    fn try_read(&self) -> Option<u8> {
+        self.inner
+            .exclusive_session(|inner| inner.read_buffer.pop_front())
+    }
+    fn read_ready(&self) -> bool {
+        !self.read_buffer_is_empty()
+    }
+    fn register_waiter(&self, task: Arc<TaskControlBlock>) {
+        self.poll_waiters.add(task);
+    }
+    fn unregister_waiter(&self, task: &Arc<TaskControlBlock>) {
+        self.poll_waiters.remove(task);
+    }
     fn write(&self, ch: u8) {
         let mut inner = self.inner.exclusive_access();
         inner.ns16550a.write(ch);
@@ -181,6 +197,7 @@ impl<const BASE_ADDR: usize> CharDevice for NS16550a<BASE_ADDR> {
         });
         if count > 0 {
             self.condvar.signal();
+            self.poll_waiters.wake_all();
         }
     }
 }
@@ -1,6 +1,6 @@
 use crate::board::{VIRTGPU_XRES, VIRTGPU_YRES};
 use crate::drivers::bus::virtio::VirtioHal;
-use crate::sync::UPIntrFreeCell;
+use crate::sync::SpinNoIrqLock;
 use alloc::{sync::Arc, vec, vec::Vec};
 use core::any::Any;
 use core::cell::Cell;
@@ -26,15 +26,15 @@ lazy_static::lazy_static!(
 
 pub struct VirtIOGpuWrapper {
     /// virtio_gpu 设备功能封装
-    gpu: UPIntrFreeCell<VirtIOGpu<'static, VirtioHal>>,
+    gpu: SpinNoIrqLock<VirtIOGpu<'static, VirtioHal>>,
     /// 显示缓冲区内容
     fb: &'static [u8],
     /// 绘制光标时光标位置的原始图像信息
-    cursor_origin_buf: UPIntrFreeCell<Vec<u8>>,
+    cursor_origin_buf: SpinNoIrqLock<Vec<u8>>,
     /// 光标图像信息
     cursor_buf: Vec<u8>,
     /// 光标当前的位置
-    cursor_pos: UPIntrFreeCell<(u32, u32)>,
+    cursor_pos: SpinNoIrqLock<(u32, u32)>,
     /// 是否使用软件光标
     use_soft_cursor: bool,
     /// 光标是否可见
@@ -71,11 +71,11 @@ impl VirtIOGpuWrapper {
             }
 
             Self {
-                gpu: UPIntrFreeCell::new(virtio),
+                gpu: SpinNoIrqLock::new(virtio),
                 fb,
-                cursor_origin_buf: UPIntrFreeCell::new(vec![0; b.len()]),
+                cursor_origin_buf: SpinNoIrqLock::new(vec![0; b.len()]),
                 cursor_buf: b,
-                cursor_pos: UPIntrFreeCell::new((0, 0)),
+                cursor_pos: SpinNoIrqLock::new((0, 0)),
                 use_soft_cursor,
                 cursor_visible: true,
             }
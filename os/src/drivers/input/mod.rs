@@ -1,6 +1,6 @@
 use crate::drivers::GPU_DEVICE;
 use crate::drivers::bus::virtio::VirtioHal;
-use crate::sync::{Condvar, UPIntrFreeCell};
+use crate::sync::{Condvar, SpinNoIrqLock};
 use crate::task::schedule;
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
@@ -17,7 +17,7 @@ struct VirtIOInputInner {
 }
 
 struct VirtIOInputWrapper {
-    inner: UPIntrFreeCell<VirtIOInputInner>,
+    inner: SpinNoIrqLock<VirtIOInputInner>,
     condvar: Condvar,
 }
 
@@ -41,7 +41,7 @@ impl VirtIOInputWrapper {
             events: VecDeque::new(),
         };
         Self {
-            inner: unsafe { UPIntrFreeCell::new(inner) },
+            inner: unsafe { SpinNoIrqLock::new(inner) },
             condvar: Condvar::new(),
         }
     }
@@ -1,12 +1,28 @@
     .section .text.entry
     .globl _start
 _start:
-    la sp, boot_stack_top
+    # a0: hart id
+    call set_boot_stack
     call rust_main
 
+    .globl _start_secondary
+_start_secondary:
+    # a0: hart id
+    call set_boot_stack
+    call rust_main_secondary
+
+set_boot_stack:
+    # keep the hart id in tp and give every hart its own boot stack
+    mv tp, a0
+    la sp, boot_stack_top
+    slli t0, a0, 16
+    sub sp, sp, t0
+    ret
+
     .section .bss.stack
     .globl boot_stack_lower_bound
 boot_stack_lower_bound:
-    .space 4096 * 16
+    # 16 pages for each of MAX_HARTS harts
+    .space 4096 * 16 * 8
     .globl boot_stack_top
 boot_stack_top:
@@ -1,7 +1,8 @@
-use super::File;
+use super::{FdFlags, File};
 use crate::drivers::BLOCK_DEVICE;
 use crate::mm::UserBuffer;
-use crate::sync::UPIntrFreeCell;
+use crate::sync::SpinNoIrqLock;
+use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use bitflags::*;
@@ -11,7 +12,7 @@ use lazy_static::*;
 pub struct OSInode {
     readable: bool,
     writable: bool,
-    inner: UPIntrFreeCell<OSInodeInner>,
+    inner: SpinNoIrqLock<OSInodeInner>,
 }
 
 pub struct OSInodeInner {
@@ -24,23 +25,9 @@ impl OSInode {
         Self {
             readable,
             writable,
-            inner: unsafe { UPIntrFreeCell::new(OSInodeInner { offset: 0, inode }) },
+            inner: unsafe { SpinNoIrqLock::new(OSInodeInner { offset: 0, inode }) },
         }
     }
-    pub fn read_all(&self) -> Vec<u8> {
-        let mut inner = self.inner.exclusive_access();
-        let mut buffer = [0u8; 512];
-        let mut v: Vec<u8> = Vec::new();
-        loop {
-            let len = inner.inode.read_at(inner.offset, &mut buffer);
-            if len == 0 {
-                break;
-            }
-            inner.offset += len;
-            v.extend_from_slice(&buffer[..len]);
-        }
-        v
-    }
 }
 
 lazy_static! {
@@ -65,6 +52,8 @@ bitflags! {
         const RDWR = 1 << 1;
         const CREATE = 1 << 9;
         const TRUNC = 1 << 10;
+        const NONBLOCK = 1 << 11;
+        const CLOEXEC = 1 << 19;
     }
 }
 
@@ -72,7 +61,7 @@ impl OpenFlags {
     /// Do not check validity for simplicity
     /// Return (readable, writable)
     pub fn read_write(&self) -> (bool, bool) {
-        if self.is_empty() {
+        if !self.intersects(Self::WRONLY | Self::RDWR) {
             (true, false)
         } else if self.contains(Self::WRONLY) {
             (false, true)
@@ -80,23 +69,91 @@ impl OpenFlags {
             (true, true)
         }
     }
+
+    /// Descriptor flags requested at open time
+    pub fn fd_flags(&self) -> FdFlags {
+        let mut flags = FdFlags::empty();
+        flags.set(FdFlags::CLOEXEC, self.contains(Self::CLOEXEC));
+        flags.set(FdFlags::NONBLOCK, self.contains(Self::NONBLOCK));
+        flags
+    }
+}
+
+/// Join `path` onto `cwd` and fold `.` and `..` components.
+/// The result is always an absolute path.
+pub fn resolve_path(cwd: &str, path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+    if !path.starts_with('/') {
+        components.extend(cwd.split('/').filter(|name| !name.is_empty()));
+    }
+    for name in path.split('/') {
+        match name {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            _ => components.push(name),
+        }
+    }
+    let mut abs_path = String::from("/");
+    abs_path.push_str(components.join("/").as_str());
+    abs_path
 }
 
-pub fn open_file(name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
+/// Return (parent, name) of a path.
+fn split_path(path: &str) -> (&str, &str) {
+    match path.rfind('/') {
+        Some(idx) => (&path[..idx], &path[idx + 1..]),
+        None => ("", path),
+    }
+}
+
+/// Walk the path from the root directory.
+fn find_inode(path: &str) -> Option<Arc<Inode>> {
+    let mut inode = ROOT_INODE.clone();
+    for name in path.split('/').filter(|name| !name.is_empty()) {
+        if !inode.is_dir() {
+            return None;
+        }
+        inode = inode.find(name)?;
+    }
+    Some(inode)
+}
+
+pub fn dir_exists(path: &str) -> bool {
+    find_inode(path).is_some_and(|inode| inode.is_dir())
+}
+
+pub fn make_dir(path: &str) -> Option<Arc<Inode>> {
+    let (parent, name) = split_path(path);
+    if name.is_empty() {
+        return None;
+    }
+    find_inode(parent)
+        .filter(|dir| dir.is_dir())
+        .and_then(|dir| dir.create_dir(name))
+}
+
+pub fn open_file(path: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
     let (readable, writable) = flags.read_write();
     if flags.contains(OpenFlags::CREATE) {
-        if let Some(inode) = ROOT_INODE.find(name) {
+        if let Some(inode) = find_inode(path) {
+            if inode.is_dir() {
+                return None;
+            }
             // clear size
             inode.clear();
             Some(Arc::new(OSInode::new(readable, writable, inode)))
         } else {
             // create file
-            ROOT_INODE
-                .create(name)
+            let (parent, name) = split_path(path);
+            find_inode(parent)
+                .filter(|dir| dir.is_dir())
+                .and_then(|dir| dir.create(name))
                 .map(|inode| Arc::new(OSInode::new(readable, writable, inode)))
         }
     } else {
-        ROOT_INODE.find(name).map(|inode| {
+        find_inode(path).map(|inode| {
             if flags.contains(OpenFlags::TRUNC) {
                 inode.clear();
             }
@@ -136,4 +193,7 @@ impl File for OSInode {
         }
         total_write_size
     }
+    fn inode(&self) -> Option<Arc<Inode>> {
+        Some(self.inner.exclusive_access().inode.clone())
+    }
 }
@@ -3,14 +3,68 @@ mod pipe;
 mod stdio;
 
 use crate::mm::UserBuffer;
+use crate::task::TaskControlBlock;
+use alloc::sync::Arc;
+use bitflags::*;
+use easy_fs::Inode;
 
 pub trait File: Send + Sync {
     fn readable(&self) -> bool;
     fn writable(&self) -> bool;
     fn read(&self, buf: UserBuffer) -> usize;
     fn write(&self, buf: UserBuffer) -> usize;
+    /// Read without blocking, return `None` if no data is ready yet.
+    fn try_read(&self, buf: UserBuffer) -> Option<usize> {
+        Some(self.read(buf))
+    }
+    /// Events ready now, by default the file never blocks.
+    fn poll(&self) -> PollEvents {
+        let mut events = PollEvents::empty();
+        events.set(PollEvents::IN, self.readable());
+        events.set(PollEvents::OUT, self.writable());
+        events
+    }
+    /// Wake `task` once the result of `poll` may have changed.
+    fn register_waiter(&self, _task: Arc<TaskControlBlock>) {}
+    fn unregister_waiter(&self, _task: &Arc<TaskControlBlock>) {}
+    /// The inode behind the file, if it can be mapped by `mmap`.
+    fn inode(&self) -> Option<Arc<Inode>> {
+        None
+    }
 }
 
-pub use inode::{OpenFlags, list_apps, open_file};
+bitflags! {
+    /// Events of `ppoll`, the same bits as Linux.
+    pub struct PollEvents: u16 {
+        const IN = 1 << 0;
+        const OUT = 1 << 2;
+        const ERR = 1 << 3;
+        const HUP = 1 << 4;
+        const NVAL = 1 << 5;
+    }
+}
+
+bitflags! {
+    /// Flags owned by a file descriptor rather than the file behind it.
+    pub struct FdFlags: u32 {
+        const CLOEXEC = 1 << 0;
+        const NONBLOCK = 1 << 1;
+    }
+}
+
+/// An entry of the fd table.
+#[derive(Clone)]
+pub struct FileDescriptor {
+    pub file: Arc<dyn File + Send + Sync>,
+    pub flags: FdFlags,
+}
+
+impl FileDescriptor {
+    pub fn new(file: Arc<dyn File + Send + Sync>, flags: FdFlags) -> Self {
+        Self { file, flags }
+    }
+}
+
+pub use inode::{OpenFlags, dir_exists, list_apps, make_dir, open_file, resolve_path};
 pub use pipe::make_pipe;
 pub use stdio::{Stdin, Stdout};
@@ -1,25 +1,25 @@
-use super::File;
+use super::{File, PollEvents};
 use crate::mm::UserBuffer;
-use crate::sync::UPIntrFreeCell;
+use crate::sync::{SpinNoIrqLock, WaitQueue};
 use alloc::sync::{Arc, Weak};
 
-use crate::task::suspend_current_and_run_next;
+use crate::task::{TaskControlBlock, suspend_current_and_run_next};
 
 pub struct Pipe {
     readable: bool,
     writable: bool,
-    buffer: Arc<UPIntrFreeCell<PipeRingBuffer>>,
+    buffer: Arc<SpinNoIrqLock<PipeRingBuffer>>,
 }
 
 impl Pipe {
-    pub fn read_end_with_buffer(buffer: Arc<UPIntrFreeCell<PipeRingBuffer>>) -> Self {
+    pub fn read_end_with_buffer(buffer: Arc<SpinNoIrqLock<PipeRingBuffer>>) -> Self {
         Self {
             readable: true,
             writable: false,
             buffer,
         }
     }
-    pub fn write_end_with_buffer(buffer: Arc<UPIntrFreeCell<PipeRingBuffer>>) -> Self {
+    pub fn write_end_with_buffer(buffer: Arc<SpinNoIrqLock<PipeRingBuffer>>) -> Self {
         Self {
             readable: false,
             writable: true,
@@ -43,6 +43,7 @@ pub struct PipeRingBuffer {
     tail: usize,
     status: RingBufferStatus,
     write_end: Option<Weak<Pipe>>,
+    waiters: WaitQueue,
 }
 
 impl PipeRingBuffer {
@@ -53,6 +54,7 @@ impl PipeRingBuffer {
             tail: 0,
             status: RingBufferStatus::Empty,
             write_end: None,
+            waiters: WaitQueue::new(),
         }
     }
     pub fn set_write_end(&mut self, write_end: &Arc<Pipe>) {
@@ -98,7 +100,7 @@ impl PipeRingBuffer {
 
 /// Return (read_end, write_end)
 pub fn make_pipe() -> (Arc<Pipe>, Arc<Pipe>) {
-    let buffer = Arc::new(unsafe { UPIntrFreeCell::new(PipeRingBuffer::new()) });
+    let buffer = Arc::new(unsafe { SpinNoIrqLock::new(PipeRingBuffer::new()) });
     let read_end = Arc::new(Pipe::read_end_with_buffer(buffer.clone()));
     let write_end = Arc::new(Pipe::write_end_with_buffer(buffer.clone()));
     buffer.exclusive_access().set_write_end(&write_end);
@@ -128,6 +130,8 @@ impl File for Pipe {
                 suspend_current_and_run_next();
                 continue;
             }
+            // pollers only run after the bytes below have been consumed
+            ring_buffer.waiters.wake_all();
             for _ in 0..loop_read {
                 if let Some(byte_ref) = buf_iter.next() {
                     unsafe {
@@ -143,6 +147,24 @@ impl File for Pipe {
             }
         }
     }
+    fn try_read(&self, buf: UserBuffer) -> Option<usize> {
+        assert!(self.readable());
+        let mut ring_buffer = self.buffer.exclusive_access();
+        let loop_read = ring_buffer.available_read();
+        if loop_read == 0 {
+            // an empty pipe without writers is at EOF rather than not ready
+            return ring_buffer.all_write_ends_closed().then_some(0);
+        }
+        ring_buffer.waiters.wake_all();
+        let mut already_read = 0usize;
+        for byte_ref in buf.into_iter().take(loop_read) {
+            unsafe {
+                *byte_ref = ring_buffer.read_byte();
+            }
+            already_read += 1;
+        }
+        Some(already_read)
+    }
     fn write(&self, buf: UserBuffer) -> usize {
         assert!(self.writable());
         let want_to_write = buf.len();
@@ -156,6 +178,7 @@ impl File for Pipe {
                 suspend_current_and_run_next();
                 continue;
             }
+            ring_buffer.waiters.wake_all();
             // write at most loop_write bytes
             for _ in 0..loop_write {
                 if let Some(byte_ref) = buf_iter.next() {
@@ -170,4 +193,31 @@ impl File for Pipe {
             }
         }
     }
+    fn poll(&self) -> PollEvents {
+        let ring_buffer = self.buffer.exclusive_access();
+        let mut events = PollEvents::empty();
+        if self.readable {
+            events.set(PollEvents::IN, ring_buffer.available_read() > 0);
+            events.set(PollEvents::HUP, ring_buffer.all_write_ends_closed());
+        }
+        if self.writable {
+            events.set(PollEvents::OUT, ring_buffer.available_write() > 0);
+        }
+        events
+    }
+    fn register_waiter(&self, task: Arc<TaskControlBlock>) {
+        self.buffer.exclusive_access().waiters.add(task);
+    }
+    fn unregister_waiter(&self, task: &Arc<TaskControlBlock>) {
+        self.buffer.exclusive_access().waiters.remove(task);
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        // readers polling the pipe should see the hang up
+        if self.writable {
+            self.buffer.exclusive_access().waiters.wake_all();
+        }
+    }
 }
@@ -1,7 +1,9 @@
-use super::File;
+use super::{File, PollEvents};
 use crate::drivers::chardev::CharDevice;
 use crate::drivers::chardev::UART;
 use crate::mm::UserBuffer;
+use crate::task::TaskControlBlock;
+use alloc::sync::Arc;
 
 pub struct Stdin;
 pub struct Stdout;
@@ -22,9 +24,30 @@ impl File for Stdin {
         }
         1
     }
+    fn try_read(&self, mut user_buf: UserBuffer) -> Option<usize> {
+        assert_eq!(user_buf.len(), 1);
+        let ch = UART.try_read()?;
+        unsafe {
+            user_buf.buffers[0].as_mut_ptr().write_volatile(ch);
+        }
+        Some(1)
+    }
     fn write(&self, _user_buf: UserBuffer) -> usize {
         panic!("Cannot write to stdin!");
     }
+    fn poll(&self) -> PollEvents {
+        if UART.read_ready() {
+            PollEvents::IN
+        } else {
+            PollEvents::empty()
+        }
+    }
+    fn register_waiter(&self, task: Arc<TaskControlBlock>) {
+        UART.register_waiter(task);
+    }
+    fn unregister_waiter(&self, task: &Arc<TaskControlBlock>) {
+        UART.unregister_waiter(task);
+    }
 }
 
 impl File for Stdout {
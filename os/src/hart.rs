@@ -0,0 +1,54 @@
+use crate::config::MAX_HARTS;
+use crate::sbi::hart_start;
+use core::arch::asm;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use log::info;
+
+/// Id of the running hart, kept in `tp` while in the kernel.
+pub fn hart_id() -> usize {
+    let id: usize;
+    unsafe {
+        asm!("mv {}, tp", out(reg) id);
+    }
+    id
+}
+
+/// Harts that finished booting, one bit each.
+static ONLINE_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn set_online() {
+    ONLINE_HARTS.fetch_or(1 << hart_id(), Ordering::SeqCst);
+}
+
+pub fn online_harts() -> usize {
+    ONLINE_HARTS.load(Ordering::SeqCst)
+}
+
+/// Start all other harts at `_start_secondary`, which goes on in `rust_main_secondary`.
+pub fn start_secondary_harts() {
+    unsafe extern "C" {
+        safe fn _start_secondary();
+    }
+    for hart in (0..MAX_HARTS).filter(|&hart| hart != hart_id()) {
+        // the SBI rejects harts that do not exist
+        if hart_start(hart, _start_secondary as usize, 0) {
+            info!("KERN: start hart {}", hart);
+        }
+    }
+}
+
+/// One `T` for every hart, a hart only ever touches its own with interrupts masked.
+pub struct PerHart<T>([UnsafeCell<T>; MAX_HARTS]);
+
+unsafe impl<T> Sync for PerHart<T> {}
+
+impl<T> PerHart<T> {
+    pub fn new(mut f: impl FnMut(usize) -> T) -> Self {
+        Self(core::array::from_fn(|hart| UnsafeCell::new(f(hart))))
+    }
+    #[allow(clippy::mut_from_ref)]
+    pub fn get_mut(&self) -> &mut T {
+        unsafe { &mut *self.0[hart_id()].get() }
+    }
+}
@@ -19,6 +19,7 @@ mod console;
 mod config;
 mod drivers;
 mod fs;
+mod hart;
 mod lang_items;
 mod logging;
 mod mm;
@@ -47,11 +48,11 @@ fn clear_bss() {
 }
 
 use lazy_static::*;
-use sync::UPIntrFreeCell;
+use sync::SpinNoIrqLock;
 
 lazy_static! {
-    pub static ref DEV_NON_BLOCKING_ACCESS: UPIntrFreeCell<bool> =
-        unsafe { UPIntrFreeCell::new(false) };
+    pub static ref DEV_NON_BLOCKING_ACCESS: SpinNoIrqLock<bool> =
+        unsafe { SpinNoIrqLock::new(false) };
 }
 
 fn test_gpu() {
@@ -76,7 +77,7 @@ fn test_gpu() {
 }
 
 #[unsafe(no_mangle)]
-pub fn rust_main() -> ! {
+pub fn rust_main(hart_id: usize) -> ! {
     clear_bss();
     logging::init();
     mm::init();
@@ -87,15 +88,32 @@ pub fn rust_main() -> ! {
     let _keyboard = KEYBOARD_DEVICE.clone();
     info!("KERN: init mouse");
     let _mouse = MOUSE_DEVICE.clone();
-    info!("KERN: init trap");
+    info!("KERN: init trap on hart {}", hart_id);
     trap::init();
     trap::enable_timer_interrupt();
+    trap::enable_software_interrupt();
     timer::set_next_trigger();
     board::device_init();
     fs::list_apps();
     task::add_initproc();
     *DEV_NON_BLOCKING_ACCESS.exclusive_access() = true;
     test_gpu();
+    hart::set_online();
+    hart::start_secondary_harts();
     task::run_tasks();
     panic!("Unreachable in rust_main!");
 }
+
+#[unsafe(no_mangle)]
+pub fn rust_main_secondary(hart_id: usize) -> ! {
+    mm::init_secondary();
+    info!("KERN: init trap on hart {}", hart_id);
+    trap::init();
+    trap::enable_timer_interrupt();
+    trap::enable_software_interrupt();
+    timer::set_next_trigger();
+    board::device_init_hart();
+    hart::set_online();
+    task::run_tasks();
+    panic!("Unreachable in rust_main_secondary!");
+}
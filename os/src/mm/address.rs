@@ -168,6 +168,7 @@ impl VirtPageNum {
 }
 
 impl PhysAddr {
+    #[allow(unused)]
     pub fn get_ref<T>(&self) -> &'static T {
         unsafe { (self.0 as *const T).as_ref().unwrap() }
     }
@@ -226,6 +227,9 @@ where
     pub fn get_end(&self) -> T {
         self.r
     }
+    pub fn contains(&self, value: T) -> bool {
+        self.l <= value && value < self.r
+    }
 }
 impl<T> IntoIterator for SimpleRange<T>
 where
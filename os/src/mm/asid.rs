@@ -0,0 +1,124 @@
+use crate::hart::{hart_id, online_harts};
+use crate::sbi::remote_sfence_vma_all;
+use crate::sync::SpinNoIrqLock;
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::*;
+use riscv::register::satp;
+
+/// The kernel space uses ASID 0, it is never allocated.
+pub const KERNEL_ASID: usize = 0;
+const ASID_SHIFT: usize = 44;
+const ASID_MASK: usize = 0xffff;
+/// ASIDs the harts implement, 1 if they have none and every space uses `KERNEL_ASID`
+static ASID_COUNT: AtomicUsize = AtomicUsize::new(1);
+
+/// Find how many ASID bits satp keeps by writing all ones to them and reading them back.
+/// Run on the boot hart in the kernel space before any user space is made,
+/// the other harts are assumed to implement as many.
+pub fn init() {
+    let token = satp::read().bits();
+    unsafe {
+        satp::write(token | ASID_MASK << ASID_SHIFT);
+    }
+    let asid = satp::read().bits() >> ASID_SHIFT & ASID_MASK;
+    unsafe {
+        satp::write(token);
+        asm!("sfence.vma");
+    }
+    ASID_COUNT.store(asid + 1, Ordering::Relaxed);
+}
+
+fn has_asids() -> bool {
+    ASID_COUNT.load(Ordering::Relaxed) > 1
+}
+
+/// Whether the space of `token` shares `KERNEL_ASID`, the TLB has to be flushed
+/// whenever satp switches to or from it then.
+pub fn shares_kernel_asid(token: usize) -> bool {
+    token >> ASID_SHIFT & ASID_MASK == KERNEL_ASID
+}
+
+/// Freed ASIDs may still tag TLB entries, so they are only reused
+/// after every fresh one is used up and the TLBs of all harts are flushed.
+struct AsidAllocator {
+    current: usize,
+    end: usize,
+    /// freed since the last flush
+    freed: Vec<usize>,
+    /// free and flushed
+    recycled: Vec<usize>,
+}
+
+impl AsidAllocator {
+    fn new() -> Self {
+        Self {
+            current: KERNEL_ASID + 1,
+            end: ASID_COUNT.load(Ordering::Relaxed),
+            freed: Vec::new(),
+            recycled: Vec::new(),
+        }
+    }
+    /// Return `None` if every ASID is in use.
+    fn alloc(&mut self) -> Option<usize> {
+        if let Some(asid) = self.recycled.pop() {
+            return Some(asid);
+        }
+        if self.current < self.end {
+            self.current += 1;
+            return Some(self.current - 1);
+        }
+        // wrap around
+        if self.freed.is_empty() {
+            return None;
+        }
+        unsafe {
+            asm!("sfence.vma");
+        }
+        remote_sfence_vma_all(online_harts() & !(1 << hart_id()));
+        self.recycled = core::mem::take(&mut self.freed);
+        self.recycled.pop()
+    }
+    fn dealloc(&mut self, asid: usize) {
+        assert!(asid != KERNEL_ASID && asid < self.current);
+        assert!(
+            !self
+                .freed
+                .iter()
+                .chain(self.recycled.iter())
+                .any(|&i| i == asid),
+            "asid {} has been deallocated!",
+            asid
+        );
+        self.freed.push(asid);
+    }
+}
+
+lazy_static! {
+    static ref ASID_ALLOCATOR: SpinNoIrqLock<AsidAllocator> =
+        unsafe { SpinNoIrqLock::new(AsidAllocator::new()) };
+}
+
+pub struct AsidHandle(pub usize);
+
+/// Spaces share `KERNEL_ASID` when the harts have no ASIDs or all of them are in use.
+pub fn asid_alloc() -> AsidHandle {
+    if !has_asids() {
+        return AsidHandle(KERNEL_ASID);
+    }
+    AsidHandle(
+        ASID_ALLOCATOR
+            .exclusive_access()
+            .alloc()
+            .unwrap_or(KERNEL_ASID),
+    )
+}
+
+impl Drop for AsidHandle {
+    fn drop(&mut self) {
+        if self.0 != KERNEL_ASID {
+            ASID_ALLOCATOR.exclusive_access().dealloc(self.0);
+        }
+    }
+}
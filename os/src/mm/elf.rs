@@ -0,0 +1,180 @@
+use crate::config::PAGE_SIZE;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use easy_fs::Inode;
+use log::warn;
+use xmas_elf::ElfFile;
+use xmas_elf::program::Type;
+
+const DT_NULL: u64 = 0;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_RELAENT: u64 = 9;
+/// size of an `Elf64_Rela`, the only layout of a relocation with addend
+const RELA_ENTRY_SIZE: usize = 24;
+/// size of an `Elf64_Phdr`
+const PH_ENTRY_SIZE: usize = 56;
+/// longest path of a dynamic loader
+const INTERP_MAX: usize = 256;
+
+const R_RISCV_NONE: u64 = 0;
+const R_RISCV_RELATIVE: u64 = 3;
+
+const AT_PHDR: usize = 3;
+const AT_PHENT: usize = 4;
+const AT_PHNUM: usize = 5;
+const AT_PAGESZ: usize = 6;
+const AT_BASE: usize = 7;
+const AT_ENTRY: usize = 9;
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// File offset of the bytes linked at `va`.
+fn file_offset(elf: &ElfFile, va: usize) -> Option<usize> {
+    elf.program_iter()
+        .filter(|ph| ph.get_type() == Ok(Type::Load))
+        .find(|ph| {
+            let start = ph.virtual_addr() as usize;
+            start <= va && va < start + ph.file_size() as usize
+        })
+        .map(|ph| ph.offset() as usize + va - ph.virtual_addr() as usize)
+}
+
+/// Link address of the bytes at file offset `offset`.
+fn link_address(elf: &ElfFile, offset: usize) -> Option<usize> {
+    elf.program_iter()
+        .filter(|ph| ph.get_type() == Ok(Type::Load))
+        .find(|ph| {
+            let start = ph.offset() as usize;
+            start <= offset && offset < start + ph.file_size() as usize
+        })
+        .map(|ph| ph.virtual_addr() as usize + offset - ph.offset() as usize)
+}
+
+/// An elf file to load. Only its header and program headers are kept,
+/// everything else is read from `inode` when needed.
+pub struct ElfImage {
+    pub inode: Arc<Inode>,
+    head: Vec<u8>,
+}
+
+impl ElfImage {
+    /// Return `None` if the file does not start with an elf header and its program headers.
+    pub fn new(inode: Arc<Inode>) -> Option<Self> {
+        let mut head = vec![0u8; PAGE_SIZE];
+        let len = inode.read_at(0, &mut head);
+        head.truncate(len);
+        let elf = ElfFile::new(&head).ok()?;
+        if elf.header.pt2.ph_entry_size() as usize != PH_ENTRY_SIZE {
+            return None;
+        }
+        let ph_count = elf.header.pt2.ph_count() as usize;
+        let ph_end = (elf.header.pt2.ph_offset() as usize).checked_add(ph_count * PH_ENTRY_SIZE)?;
+        if ph_end > head.len() {
+            head = read_exact(&inode, 0, ph_end)?;
+        }
+        Some(Self { inode, head })
+    }
+    pub fn elf(&self) -> ElfFile<'_> {
+        ElfFile::new(&self.head).unwrap()
+    }
+    /// Path of the dynamic loader the program asks for, if any.
+    pub fn interpreter(&self) -> Option<String> {
+        let elf = self.elf();
+        let interp = elf
+            .program_iter()
+            .find(|ph| ph.get_type() == Ok(Type::Interp))?;
+        let len = interp.file_size() as usize;
+        if len > INTERP_MAX {
+            return None;
+        }
+        let path = read_exact(&self.inode, interp.offset() as usize, len)?;
+        let len = path.iter().position(|&b| b == 0).unwrap_or(path.len());
+        String::from_utf8(path[..len].to_vec()).ok()
+    }
+}
+
+/// Read `len` bytes of `inode` from `offset`, return `None` if the file is shorter.
+fn read_exact(inode: &Inode, offset: usize, len: usize) -> Option<Vec<u8>> {
+    if offset.checked_add(len)? > inode.size() {
+        return None;
+    }
+    let mut buf = vec![0u8; len];
+    (inode.read_at(offset, &mut buf) == len).then_some(buf)
+}
+
+/// Auxiliary vector telling the dynamic loader at `interp_base` where the
+/// program loaded at `base` is, without the terminating `AT_NULL`.
+pub fn auxiliary_vector(elf: &ElfFile, base: usize, interp_base: usize) -> Vec<(usize, usize)> {
+    let mut auxv = vec![
+        (AT_PHENT, elf.header.pt2.ph_entry_size() as usize),
+        (AT_PHNUM, elf.header.pt2.ph_count() as usize),
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_BASE, interp_base),
+        (AT_ENTRY, base + elf.header.pt2.entry_point() as usize),
+    ];
+    if let Some(phdr) = link_address(elf, elf.header.pt2.ph_offset() as usize) {
+        auxv.push((AT_PHDR, base + phdr));
+    }
+    auxv
+}
+
+/// Relocations of a position-independent executable loaded at `base`,
+/// as pairs of the address to patch and the value to store, sorted by address.
+/// Only relative relocations are supported, since no symbol is resolved.
+/// Return `None` if the dynamic section or the relocation table is malformed.
+pub fn relocations(image: &ElfImage, base: usize) -> Option<Vec<(usize, usize)>> {
+    let elf = image.elf();
+    let Some(dynamic) = elf
+        .program_iter()
+        .find(|ph| ph.get_type() == Ok(Type::Dynamic))
+    else {
+        return Some(Vec::new());
+    };
+    let dynamic = read_exact(
+        &image.inode,
+        dynamic.offset() as usize,
+        dynamic.file_size() as usize,
+    )?;
+    let mut rela = None;
+    let mut rela_size = 0;
+    let mut rela_entry_size = RELA_ENTRY_SIZE;
+    for entry in dynamic.chunks_exact(16) {
+        let value = read_u64(entry, 8) as usize;
+        match read_u64(entry, 0) {
+            DT_NULL => break,
+            DT_RELA => rela = Some(value),
+            DT_RELASZ => rela_size = value,
+            DT_RELAENT => rela_entry_size = value,
+            _ => {}
+        }
+    }
+    let Some(rela) = rela else {
+        return Some(Vec::new());
+    };
+    if rela_entry_size != RELA_ENTRY_SIZE || rela_size % RELA_ENTRY_SIZE != 0 {
+        return None;
+    }
+    let rela_start = file_offset(&elf, rela)?;
+    let mut relocations: Vec<(usize, usize)> = read_exact(&image.inode, rela_start, rela_size)?
+        .chunks_exact(RELA_ENTRY_SIZE)
+        .filter_map(|entry| {
+            let offset = read_u64(entry, 0) as usize;
+            let addend = read_u64(entry, 16) as usize;
+            match read_u64(entry, 8) & 0xffff_ffff {
+                R_RISCV_NONE => None,
+                R_RISCV_RELATIVE => Some((base.wrapping_add(offset), base.wrapping_add(addend))),
+                kind => {
+                    warn!("unsupported relocation type {}", kind);
+                    None
+                }
+            }
+        })
+        .collect();
+    relocations.sort_by_key(|&(va, _)| va);
+    Some(relocations)
+}
@@ -1,6 +1,7 @@
 use super::{PhysAddr, PhysPageNum};
 use crate::config::MEMORY_END;
-use crate::sync::UPIntrFreeCell;
+use crate::sync::SpinNoIrqLock;
+use alloc::collections::BTreeSet;
 use alloc::vec::Vec;
 use core::fmt::{self, Debug, Formatter};
 use lazy_static::*;
@@ -35,67 +36,137 @@ impl Drop for FrameTracker {
 trait FrameAllocator {
     fn new() -> Self;
     fn alloc(&mut self) -> Option<PhysPageNum>;
+    /// Allocate `pages` physically contiguous frames, in ascending order.
     fn alloc_more(&mut self, pages: usize) -> Option<Vec<PhysPageNum>>;
     fn dealloc(&mut self, ppn: PhysPageNum);
+    fn stats(&self) -> FrameStats;
 }
 
-pub struct StackFrameAllocator {
-    current: usize,
-    end: usize,
-    recycled: Vec<usize>,
+#[derive(Copy, Clone, Debug)]
+pub struct FrameStats {
+    pub total: usize,
+    pub free: usize,
+    /// order of the largest free block, `None` if no frame is free
+    pub largest_order: Option<usize>,
 }
 
-impl StackFrameAllocator {
+impl FrameStats {
+    pub fn used(&self) -> usize {
+        self.total - self.free
+    }
+}
+
+/// Blocks of `1 << MAX_ORDER` frames are never merged further.
+const MAX_ORDER: usize = 16;
+
+/// A buddy allocator, a block of order `k` holds `1 << k` frames and is
+/// aligned to its size relative to the first frame.
+pub struct BuddyFrameAllocator {
+    base: usize,
+    /// free blocks of each order, as offsets from `base`
+    free_lists: [BTreeSet<usize>; MAX_ORDER + 1],
+    total: usize,
+    free: usize,
+}
+
+impl BuddyFrameAllocator {
     pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
-        self.current = l.0;
-        self.end = r.0;
-        // println!("last {} Physical Frames.", self.end - self.current);
+        self.base = l.0;
+        self.total = r.0 - l.0;
+        // cover [l, r) with the largest aligned blocks
+        let mut offset = 0;
+        while offset < self.total {
+            let mut order = MAX_ORDER.min(floor_log2(self.total - offset));
+            if offset != 0 {
+                order = order.min(offset.trailing_zeros() as usize);
+            }
+            self.free_lists[order].insert(offset);
+            offset += 1 << order;
+        }
+        self.free = self.total;
+        // println!("last {} Physical Frames.", self.total);
+    }
+    fn alloc_block(&mut self, order: usize) -> Option<usize> {
+        let mut current = (order..=MAX_ORDER).find(|&k| !self.free_lists[k].is_empty())?;
+        let offset = self.free_lists[current].pop_first().unwrap();
+        // split and give back the upper halves
+        while current > order {
+            current -= 1;
+            self.free_lists[current].insert(offset + (1 << current));
+        }
+        self.free -= 1 << order;
+        Some(offset)
+    }
+    fn dealloc_block(&mut self, mut offset: usize, mut order: usize) {
+        self.free += 1 << order;
+        // merge with the buddy as long as it is free
+        while order < MAX_ORDER && self.free_lists[order].remove(&(offset ^ (1 << order))) {
+            offset &= !(1 << order);
+            order += 1;
+        }
+        self.free_lists[order].insert(offset);
     }
 }
-impl FrameAllocator for StackFrameAllocator {
+
+impl FrameAllocator for BuddyFrameAllocator {
     fn new() -> Self {
         Self {
-            current: 0,
-            end: 0,
-            recycled: Vec::new(),
+            base: 0,
+            free_lists: core::array::from_fn(|_| BTreeSet::new()),
+            total: 0,
+            free: 0,
         }
     }
     fn alloc(&mut self) -> Option<PhysPageNum> {
-        if let Some(ppn) = self.recycled.pop() {
-            Some(ppn.into())
-        } else if self.current == self.end {
-            None
-        } else {
-            self.current += 1;
-            Some((self.current - 1).into())
-        }
+        self.alloc_block(0)
+            .map(|offset| (self.base + offset).into())
     }
     fn alloc_more(&mut self, pages: usize) -> Option<Vec<PhysPageNum>> {
-        if self.current + pages >= self.end {
-            None
-        } else {
-            self.current += pages;
-            let arr: Vec<usize> = (1..pages + 1).collect();
-            let v = arr.iter().map(|x| (self.current - x).into()).collect();
-            Some(v)
+        let order = floor_log2(pages.next_power_of_two());
+        if pages == 0 || order > MAX_ORDER {
+            return None;
         }
+        let offset = self.alloc_block(order)?;
+        // return the frames past `pages` one by one, they merge again
+        for tail in offset + pages..offset + (1 << order) {
+            self.dealloc_block(tail, 0);
+        }
+        Some(
+            (offset..offset + pages)
+                .map(|offset| (self.base + offset).into())
+                .collect(),
+        )
     }
     fn dealloc(&mut self, ppn: PhysPageNum) {
-        let ppn = ppn.0;
         // validity check
-        if ppn >= self.current || self.recycled.iter().any(|&v| v == ppn) {
-            panic!("Frame ppn={:#x} has not been allocated!", ppn);
+        let offset = ppn.0.wrapping_sub(self.base);
+        if offset >= self.total
+            || (0..=MAX_ORDER).any(|k| self.free_lists[k].contains(&(offset & !((1 << k) - 1))))
+        {
+            panic!("Frame ppn={:#x} has not been allocated!", ppn.0);
+        }
+        self.dealloc_block(offset, 0);
+    }
+    fn stats(&self) -> FrameStats {
+        FrameStats {
+            total: self.total,
+            free: self.free,
+            largest_order: (0..=MAX_ORDER)
+                .rev()
+                .find(|&k| !self.free_lists[k].is_empty()),
         }
-        // recycle
-        self.recycled.push(ppn);
     }
 }
 
-type FrameAllocatorImpl = StackFrameAllocator;
+fn floor_log2(n: usize) -> usize {
+    (usize::BITS - 1 - n.leading_zeros()) as usize
+}
+
+type FrameAllocatorImpl = BuddyFrameAllocator;
 
 lazy_static! {
-    pub static ref FRAME_ALLOCATOR: UPIntrFreeCell<FrameAllocatorImpl> =
-        unsafe { UPIntrFreeCell::new(FrameAllocatorImpl::new()) };
+    pub static ref FRAME_ALLOCATOR: SpinNoIrqLock<FrameAllocatorImpl> =
+        unsafe { SpinNoIrqLock::new(FrameAllocatorImpl::new()) };
 }
 
 pub fn init_frame_allocator() {
@@ -122,10 +193,23 @@ pub fn frame_alloc_more(num: usize) -> Option<Vec<FrameTracker>> {
         .map(|x| x.iter().map(|&t| FrameTracker::new(t)).collect())
 }
 
+/// Like `frame_alloc_more`, but return `None` at once if any hart is using the
+/// frame allocator, which may be waiting for the heap to grow through this call.
+pub fn try_frame_alloc_more(num: usize) -> Option<Vec<FrameTracker>> {
+    FRAME_ALLOCATOR
+        .try_exclusive_access_now()?
+        .alloc_more(num)
+        .map(|x| x.iter().map(|&t| FrameTracker::new(t)).collect())
+}
+
 pub fn frame_dealloc(ppn: PhysPageNum) {
     FRAME_ALLOCATOR.exclusive_access().dealloc(ppn);
 }
 
+pub fn frame_stats() -> FrameStats {
+    FRAME_ALLOCATOR.exclusive_access().stats()
+}
+
 #[allow(unused)]
 pub fn frame_allocator_test() {
     let mut v: Vec<FrameTracker> = Vec::new();
@@ -146,17 +230,22 @@ pub fn frame_allocator_test() {
 
 #[allow(unused)]
 pub fn frame_allocator_alloc_more_test() {
+    let stats = frame_stats();
     let mut v: Vec<FrameTracker> = Vec::new();
     let frames = frame_alloc_more(5).unwrap();
     for frame in &frames {
         println!("{:?}", frame);
     }
+    assert!(frames.windows(2).all(|w| w[1].ppn.0 == w[0].ppn.0 + 1));
     v.extend(frames);
     v.clear();
     let frames = frame_alloc_more(5).unwrap();
     for frame in &frames {
         println!("{:?}", frame);
     }
-    drop(v);
+    drop(frames);
+    // freed blocks merge with their buddies again
+    assert_eq!(frame_stats().free, stats.free);
+    assert_eq!(frame_stats().largest_order, stats.largest_order);
     println!("frame_allocator_test passed!");
 }
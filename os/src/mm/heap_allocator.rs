@@ -1,12 +1,137 @@
-use crate::config::KERNEL_HEAP_SIZE;
+use super::PhysAddr;
+use super::frame_allocator::try_frame_alloc_more;
+use crate::config::{KERNEL_HEAP_SIZE, PAGE_SIZE};
+use crate::hart::hart_id;
 use buddy_system_allocator::LockedHeap;
-use core::ptr::addr_of_mut;
+use core::alloc::{GlobalAlloc, Layout};
+use core::hint::spin_loop;
+use core::ptr::{NonNull, addr_of_mut, null_mut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// the heap grows by at least this many bytes at a time
+const HEAP_GROW_SIZE: usize = 0x10_0000;
+/// free bytes kept for the frame allocator, which allocates while the heap grows
+const HEAP_RESERVE: usize = 0x4_0000;
+const NO_HART: usize = usize::MAX;
 
 #[global_allocator]
-static HEAP_ALLOCATOR: LockedHeap = LockedHeap::empty();
+static HEAP_ALLOCATOR: KernelHeap = KernelHeap {
+    heap: LockedHeap::empty(),
+    growing: AtomicUsize::new(NO_HART),
+    peak: AtomicUsize::new(0),
+    failed: AtomicUsize::new(0),
+};
+
+/// A buddy heap that starts in `.bss` and grows by frames from the frame allocator.
+/// Frames are never given back.
+struct KernelHeap {
+    heap: LockedHeap,
+    /// hart taking frames for the heap
+    growing: AtomicUsize,
+    /// most bytes ever in use
+    peak: AtomicUsize,
+    /// allocations that failed even after growing
+    failed: AtomicUsize,
+}
+
+impl KernelHeap {
+    /// Add frames for at least `size` bytes, return false if none could be taken.
+    fn grow(&self, size: usize) -> bool {
+        let hart = hart_id();
+        match self
+            .growing
+            .compare_exchange(NO_HART, hart, Ordering::Acquire, Ordering::Relaxed)
+        {
+            Ok(_) => {}
+            // the frame allocator allocates from the reserve meanwhile
+            Err(owner) if owner == hart => return false,
+            // another hart is adding frames, worth another try when it is done,
+            // which is soon since it never waits for the frame allocator
+            Err(_) => {
+                while self.growing.load(Ordering::Acquire) != NO_HART {
+                    spin_loop();
+                }
+                return true;
+            }
+        }
+        let pages = size.max(HEAP_GROW_SIZE).div_ceil(PAGE_SIZE);
+        let frames = try_frame_alloc_more(pages);
+        if let Some(frames) = &frames {
+            let start: PhysAddr = frames[0].ppn.into();
+            unsafe {
+                self.heap
+                    .lock()
+                    .add_to_heap(start.0, start.0 + pages * PAGE_SIZE);
+            }
+        }
+        self.growing.store(NO_HART, Ordering::Release);
+        let Some(frames) = frames else {
+            return false;
+        };
+        // the frames are reached through the identical mapping of physical memory
+        for frame in frames {
+            core::mem::forget(frame);
+        }
+        true
+    }
+}
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut ptr = self.heap.lock().alloc(layout).ok();
+        // blocks of the buddy heap are aligned to their size, twice that always holds one
+        let block = layout.size().max(layout.align()).next_power_of_two();
+        if ptr.is_none() && self.grow(2 * block) {
+            ptr = self.heap.lock().alloc(layout).ok();
+        }
+        let Some(ptr) = ptr else {
+            self.failed.fetch_add(1, Ordering::Relaxed);
+            return null_mut();
+        };
+        let (in_use, free) = {
+            let heap = self.heap.lock();
+            (
+                heap.stats_alloc_user(),
+                heap.stats_total_bytes() - heap.stats_alloc_actual(),
+            )
+        };
+        self.peak.fetch_max(in_use, Ordering::Relaxed);
+        if free < HEAP_RESERVE {
+            self.grow(HEAP_GROW_SIZE);
+        }
+        ptr.as_ptr()
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe {
+            self.heap
+                .lock()
+                .dealloc(NonNull::new_unchecked(ptr), layout);
+        }
+    }
+}
+
+/// Usage of the kernel heap, in bytes except `failed`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct HeapStats {
+    pub total: usize,
+    pub in_use: usize,
+    pub peak: usize,
+    pub failed: usize,
+}
+
+pub fn heap_stats() -> HeapStats {
+    let heap = HEAP_ALLOCATOR.heap.lock();
+    HeapStats {
+        total: heap.stats_total_bytes(),
+        in_use: heap.stats_alloc_user(),
+        peak: HEAP_ALLOCATOR.peak.load(Ordering::Relaxed),
+        failed: HEAP_ALLOCATOR.failed.load(Ordering::Relaxed),
+    }
+}
 
 #[alloc_error_handler]
-pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
+pub fn handle_alloc_error(layout: Layout) -> ! {
     panic!("Heap allocation error, layout = {:?}", layout);
 }
 
@@ -15,6 +140,7 @@ static mut HEAP_SPACE: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
 pub fn init_heap() {
     unsafe {
         HEAP_ALLOCATOR
+            .heap
             .lock()
             .init(addr_of_mut!(HEAP_SPACE) as usize, KERNEL_HEAP_SIZE);
     }
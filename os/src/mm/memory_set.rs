@@ -1,13 +1,21 @@
-use super::{FrameTracker, frame_alloc};
-use super::{PTEFlags, PageTable, PageTableEntry};
+use super::asid::{AsidHandle, KERNEL_ASID, asid_alloc};
+use super::elf::{ElfImage, auxiliary_vector, relocations};
+use super::shm::ShmSegment;
+use super::swap::SwapSlot;
+use super::{FrameTracker, frame_alloc, frame_stats};
+use super::{PTEFlags, PageTable, PageTableEntry, level_pages};
 use super::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 use super::{StepByOne, VPNRange};
-use crate::config::{MEMORY_END, MMIO, PAGE_SIZE, TRAMPOLINE};
-use crate::sync::UPIntrFreeCell;
+use crate::config::{
+    INTERP_BASE, MEMORY_END, MMAP_BASE, MMAP_END, MMIO, PAGE_SIZE, PIE_BASE, TRAMPOLINE,
+    USER_HEAP_LIMIT,
+};
+use crate::sync::SpinNoIrqLock;
 use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::arch::asm;
+use easy_fs::Inode;
 use lazy_static::*;
 use riscv::register::satp;
 
@@ -25,25 +33,48 @@ unsafe extern "C" {
 }
 
 lazy_static! {
-    pub static ref KERNEL_SPACE: Arc<UPIntrFreeCell<MemorySet>> =
-        Arc::new(unsafe { UPIntrFreeCell::new(MemorySet::new_kernel()) });
+    pub static ref KERNEL_SPACE: Arc<SpinNoIrqLock<MemorySet>> =
+        Arc::new(unsafe { SpinNoIrqLock::new(MemorySet::new_kernel()) });
 }
 
 pub fn kernel_token() -> usize {
     KERNEL_SPACE.exclusive_access().token()
 }
 
+/// Frames a page fault may need: one for the page and two for page tables.
+pub const FAULT_FRAMES: usize = 3;
+
+/// Why `MemorySet::from_elf` could not build an address space.
+#[derive(Debug)]
+pub enum LoadError {
+    /// a malformed elf, or a loader that is not position-independent
+    BadElf,
+    /// no frame is left for the page tables
+    OutOfMemory,
+}
+
 pub struct MemorySet {
     page_table: PageTable,
     areas: Vec<MapArea>,
+    /// where the clock algorithm looks for the next page to swap out
+    clock_hand: VirtPageNum,
+    /// `None` for the kernel space, which uses `KERNEL_ASID`
+    #[allow(unused)]
+    asid: Option<AsidHandle>,
 }
 
 impl MemorySet {
-    pub fn new_bare() -> Self {
-        Self {
-            page_table: PageTable::new(),
+    /// Return `None` if no frame is left for the page table.
+    pub fn new_bare() -> Option<Self> {
+        Self::new_bare_with_asid(Some(asid_alloc()))
+    }
+    fn new_bare_with_asid(asid: Option<AsidHandle>) -> Option<Self> {
+        Some(Self {
+            page_table: PageTable::new(asid.as_ref().map_or(KERNEL_ASID, |asid| asid.0))?,
             areas: Vec::new(),
-        }
+            clock_hand: VirtPageNum(0),
+            asid,
+        })
     }
     pub fn token(&self) -> usize {
         self.page_table.token()
@@ -60,6 +91,71 @@ impl MemorySet {
             None,
         );
     }
+    /// Like `insert_framed_area`, but each page is mapped on its first access.
+    pub fn insert_lazy_area(
+        &mut self,
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        permission: MapPermission,
+    ) {
+        self.push(
+            MapArea::new(start_va, end_va, MapType::Framed, permission).lazy(),
+            None,
+        );
+    }
+    /// Reserve `[start_va, end_va)` as a guard that is never mapped,
+    /// so running off the stack above it faults instead of reaching other memory.
+    pub fn insert_guard_area(&mut self, start_va: VirtAddr, end_va: VirtAddr) {
+        self.push(
+            MapArea::new(start_va, end_va, MapType::Framed, MapPermission::empty()).lazy(),
+            None,
+        );
+    }
+    pub fn is_guard_page(&self, vpn: VirtPageNum) -> bool {
+        self.areas
+            .iter()
+            .any(|area| area.map_perm.is_empty() && area.vpn_range.contains(vpn))
+    }
+    /// Grow the heap starting at `start` from `old_end` up to `new_end`.
+    /// `mprotect` may have split the heap, the piece that ends at `old_end` grows.
+    /// Return false and leave it unchanged when out of frames.
+    pub fn append_to(&mut self, start: VirtAddr, old_end: VirtAddr, new_end: VirtAddr) -> bool {
+        let (start, old_end) = (start.floor(), old_end.ceil());
+        if let Some(area) = self
+            .areas
+            .iter_mut()
+            .find(|area| area.vpn_range.get_start() >= start && area.vpn_range.get_end() == old_end)
+        {
+            area.append_to(&mut self.page_table, new_end.ceil())
+        } else {
+            false
+        }
+    }
+    /// Shrink the heap starting at `start` from `old_end` down to `new_end`.
+    /// Pieces split off by `mprotect` that start above `new_end` are removed,
+    /// the first one is kept even when empty.
+    pub fn shrink_to(&mut self, start: VirtAddr, old_end: VirtAddr, new_end: VirtAddr) -> bool {
+        let (start, old_end, new_end) = (start.floor(), old_end.ceil(), new_end.ceil());
+        let mut found = false;
+        let mut idx = 0;
+        while idx < self.areas.len() {
+            let area = &mut self.areas[idx];
+            let area_start = area.vpn_range.get_start();
+            if area_start < start || (area_start >= old_end && area_start != start) {
+                idx += 1;
+                continue;
+            }
+            found = true;
+            if area_start >= new_end && area_start != start {
+                let mut area = self.areas.remove(idx);
+                area.unmap(&mut self.page_table);
+                continue;
+            }
+            area.shrink_to(&mut self.page_table, new_end);
+            idx += 1;
+        }
+        found
+    }
     pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
         if let Some((idx, area)) = self
             .areas
@@ -71,6 +167,250 @@ impl MemorySet {
             self.areas.remove(idx);
         }
     }
+    /// Find `page_count` free pages in the mmap region.
+    pub fn find_mmap_range(&self, page_count: usize) -> Option<VirtPageNum> {
+        let mmap_end = VirtAddr::from(MMAP_END).floor();
+        let mut used: Vec<(VirtPageNum, VirtPageNum)> = self
+            .areas
+            .iter()
+            .map(|area| (area.vpn_range.get_start(), area.vpn_range.get_end()))
+            .filter(|&(start, end)| end > VirtAddr::from(MMAP_BASE).floor() && start < mmap_end)
+            .collect();
+        used.sort_by_key(|&(start, _)| start);
+        let mut start = VirtAddr::from(MMAP_BASE).floor();
+        for (used_start, used_end) in used {
+            if used_start.0 >= start.0 + page_count {
+                break;
+            }
+            start = start.max(used_end);
+        }
+        (start.0 + page_count <= mmap_end.0).then_some(start)
+    }
+    /// Map an area made by `mmap`, the pages of a file are read when first accessed.
+    /// Return false without mapping anything when out of frames.
+    pub fn push_mmap_area(&mut self, mut map_area: MapArea) -> bool {
+        if !map_area.try_map(&mut self.page_table) {
+            return false;
+        }
+        self.areas.push(map_area);
+        true
+    }
+    /// Unmap the shared memory segment attached at `start`, including the pieces
+    /// left by a partial `munmap` or `mprotect`.
+    /// Return false if no segment is attached there.
+    pub fn shm_detach(&mut self, start: VirtPageNum) -> bool {
+        let mut found = false;
+        let mut idx = 0;
+        while idx < self.areas.len() {
+            let area = &self.areas[idx];
+            if area
+                .shm
+                .as_ref()
+                .is_some_and(|shm| area.vpn_range.get_start().0 - shm.page == start.0)
+            {
+                let mut area = self.areas.remove(idx);
+                area.unmap(&mut self.page_table);
+                found = true;
+            } else {
+                idx += 1;
+            }
+        }
+        found
+    }
+    /// Take the parts of the areas picked by `pick` that overlap `[start, end)`
+    /// out of this set, splitting areas that are only partly covered.
+    fn take_range(
+        &mut self,
+        start: VirtPageNum,
+        end: VirtPageNum,
+        pick: impl Fn(&MapArea) -> bool,
+    ) -> Vec<MapArea> {
+        let mut taken = Vec::new();
+        let mut idx = 0;
+        while idx < self.areas.len() {
+            let area_start = self.areas[idx].vpn_range.get_start();
+            let area_end = self.areas[idx].vpn_range.get_end();
+            if !pick(&self.areas[idx]) || area_end <= start || area_start >= end {
+                idx += 1;
+                continue;
+            }
+            let mut area = self.areas.remove(idx);
+            if area_end > end {
+                self.areas.push(area.split_off(end));
+            }
+            if area_start < start {
+                let inside = area.split_off(start);
+                self.areas.push(area);
+                area = inside;
+            }
+            taken.push(area);
+        }
+        taken
+    }
+    /// Unmap the pages in `[start, end)` that belong to areas in the mmap region,
+    /// splitting areas that are only partly covered.
+    /// Return the pages of shared file mappings among them to write back.
+    pub fn munmap(&mut self, start: VirtPageNum, end: VirtPageNum) -> Vec<FileWriteback> {
+        let mmap_base = VirtAddr::from(MMAP_BASE).floor();
+        let mut writeback = Vec::new();
+        for mut area in self.take_range(start, end, |area| area.vpn_range.get_start() >= mmap_base)
+        {
+            writeback.extend(area.file_writeback());
+            area.unmap(&mut self.page_table);
+        }
+        writeback
+    }
+    /// The pages of all shared file mappings to write back.
+    pub fn file_writeback(&self) -> Vec<FileWriteback> {
+        self.areas
+            .iter()
+            .flat_map(|area| area.file_writeback())
+            .collect()
+    }
+    /// Whether every page in `[start, end)` belongs to a user area, guard pages do not.
+    pub fn is_user_range(&self, start: VirtPageNum, end: VirtPageNum) -> bool {
+        let mut used: Vec<(VirtPageNum, VirtPageNum)> = self
+            .areas
+            .iter()
+            .filter(|area| area.is_user())
+            .map(|area| (area.vpn_range.get_start(), area.vpn_range.get_end()))
+            .filter(|&(used_start, used_end)| used_end > start && used_start < end)
+            .collect();
+        used.sort_by_key(|&(used_start, _)| used_start);
+        let mut covered = start;
+        for (used_start, used_end) in used {
+            if used_start > covered {
+                break;
+            }
+            covered = covered.max(used_end);
+        }
+        covered >= end
+    }
+    /// Whether a page in `[start, end)` is a shared mapping of a file not open for writing.
+    pub fn maps_readonly_file(&self, start: VirtPageNum, end: VirtPageNum) -> bool {
+        self.areas.iter().any(|area| {
+            area.vpn_range.get_end() > start
+                && area.vpn_range.get_start() < end
+                && area
+                    .file
+                    .as_ref()
+                    .is_some_and(|file| file.shared && !file.writable)
+        })
+    }
+    /// Change the permission of the pages in `[start, end)` that belong to user areas,
+    /// splitting areas that are only partly covered.
+    /// Return false when no frame is left for the page tables, the areas
+    /// not changed by then keep their permission.
+    pub fn mprotect(&mut self, start: VirtPageNum, end: VirtPageNum, perm: MapPermission) -> bool {
+        let mut changed = true;
+        for mut area in self.take_range(start, end, MapArea::is_user) {
+            changed = changed && area.set_perm(&mut self.page_table, perm);
+            self.areas.push(area);
+        }
+        changed
+    }
+    /// Resolve a page fault at `vpn`, return false if the access is not allowed.
+    /// Only swapped out pages, first accesses to lazy pages and writes to
+    /// copy-on-write pages can be resolved.
+    /// Call `reserve_frames` first to swap out other pages for a frame.
+    /// Pages of elf segments and files are loaded by `file_page_fault` and `map_loaded_page`
+    /// instead.
+    pub fn handle_page_fault(&mut self, vpn: VirtPageNum, write: bool) -> bool {
+        let Some(area) = self
+            .areas
+            .iter_mut()
+            .find(|area| area.vpn_range.contains(vpn) && area.allows(write))
+        else {
+            return false;
+        };
+        match self.page_table.translate(vpn) {
+            Some(pte) if pte.is_valid() => {
+                write && !pte.writable() && area.handle_cow_fault(&mut self.page_table, vpn)
+            }
+            _ if area.swapped.contains_key(&vpn) => area.swap_in(&mut self.page_table, vpn),
+            _ => area.handle_lazy_fault(&mut self.page_table, vpn),
+        }
+    }
+    /// How to fill page `vpn`, if a fault at `vpn` is an allowed first access
+    /// to a page of the elf or of a mapped file.
+    /// The page is read without the lock of the process, since reading the file
+    /// may block, and mapped by `map_loaded_page` after that.
+    pub fn file_page_fault(&self, vpn: VirtPageNum, write: bool) -> Option<PageLoader> {
+        let area = self
+            .areas
+            .iter()
+            .find(|area| area.vpn_range.contains(vpn) && area.allows(write))?;
+        area.page_loader(vpn)
+    }
+    /// A page of a shared file mapping that is not loaded yet and how to fill it.
+    /// All of them are loaded before a fork, so that both processes share the frames.
+    pub fn unloaded_shared_file_page(&self) -> Option<(VirtPageNum, PageLoader)> {
+        self.areas
+            .iter()
+            .filter(|area| area.file.as_ref().is_some_and(|file| file.shared))
+            .flat_map(|area| area.vpn_range.into_iter().map(move |vpn| (area, vpn)))
+            .find_map(|(area, vpn)| Some((vpn, area.page_loader(vpn)?)))
+    }
+    /// Map `frame`, filled by `PageLoader::load_page`, at `vpn`.
+    /// Nothing is mapped if another thread loaded the page or it was unmapped meanwhile,
+    /// the access is tried again then. Return false if no frame is left for the page tables.
+    pub fn map_loaded_page(&mut self, vpn: VirtPageNum, frame: FrameTracker) -> bool {
+        let Some(area) = self.areas.iter_mut().find(|area| {
+            area.vpn_range.contains(vpn)
+                && (area.elf.is_some() || area.file.is_some())
+                && !area.data_frames.contains_key(&vpn)
+                && !area.swapped.contains_key(&vpn)
+        }) else {
+            return true;
+        };
+        if area.accessible() {
+            let pte_flags = PTEFlags::from_bits(area.map_perm.bits).unwrap();
+            if !self.page_table.try_map(vpn, frame.ppn, pte_flags) {
+                return false;
+            }
+        }
+        area.data_frames.insert(vpn, Arc::new(frame));
+        true
+    }
+    /// Swap out pages of this address space until `count` frames are free
+    /// or nothing is left to swap out.
+    /// The kernel must not hold any slice of this address space.
+    pub fn reserve_frames(&mut self, count: usize) {
+        while frame_stats().free < count && self.swap_out_one() {}
+    }
+    /// Swap out one page chosen by the clock algorithm, which passes over
+    /// and clears the accessed bit of recently used pages.
+    /// Return false if no page can be swapped out.
+    fn swap_out_one(&mut self) -> bool {
+        let mut pages: Vec<VirtPageNum> = self
+            .areas
+            .iter()
+            .flat_map(|area| area.swappable_pages())
+            .collect();
+        if pages.is_empty() {
+            return false;
+        }
+        pages.sort();
+        let start = pages.partition_point(|&vpn| vpn < self.clock_hand);
+        // after one round every accessed bit is cleared
+        for i in 0..pages.len() * 2 {
+            let vpn = pages[(start + i) % pages.len()];
+            let pte = self.page_table.translate(vpn).unwrap();
+            if pte.flags().contains(PTEFlags::A) {
+                self.page_table
+                    .remap(vpn, pte.ppn(), pte.flags() - PTEFlags::A);
+                continue;
+            }
+            self.clock_hand = VirtPageNum(vpn.0 + 1);
+            return self
+                .areas
+                .iter_mut()
+                .find(|area| area.vpn_range.contains(vpn))
+                .unwrap()
+                .swap_out(&mut self.page_table, vpn);
+        }
+        false
+    }
     /// Add a new MapArea into this MemorySet.
     /// Assuming that there are no conflicts in the virtual address
     /// space.
@@ -82,18 +422,19 @@ impl MemorySet {
         self.areas.push(map_area);
     }
     /// Mention that trampoline is not collected by areas.
-    fn map_trampoline(&mut self) {
-        self.page_table.map(
+    /// Return false if no frame is left for the page tables.
+    fn map_trampoline(&mut self) -> bool {
+        self.page_table.try_map(
             VirtAddr::from(TRAMPOLINE).into(),
             PhysAddr::from(strampoline as usize).into(),
             PTEFlags::R | PTEFlags::X,
-        );
+        )
     }
     /// Without kernel stacks.
     pub fn new_kernel() -> Self {
-        let mut memory_set = Self::new_bare();
+        let mut memory_set = Self::new_bare_with_asid(None).unwrap();
         // map trampoline
-        memory_set.map_trampoline();
+        assert!(memory_set.map_trampoline());
         // map kernel sections
         // println!(".text [{:#x}, {:#x})", stext as usize, etext as usize);
         // println!(".rodata [{:#x}, {:#x})", srodata as usize, erodata as usize);
@@ -166,24 +507,32 @@ impl MemorySet {
         }
         memory_set
     }
-    /// Include sections in elf and trampoline,
-    /// also returns user_sp_base and entry point.
-    pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
-        let mut memory_set = Self::new_bare();
-        // map trampoline
-        memory_set.map_trampoline();
+    /// Map the loadable segments of `image` to be loaded page by page when
+    /// first accessed, return where they start and end.
+    /// Position-independent ones are loaded at `pie_base` and relocated.
+    /// Return `None` for a malformed elf.
+    fn map_elf(&mut self, image: &ElfImage, pie_base: usize) -> Option<(usize, VirtPageNum)> {
         // map program headers of elf, with U flag
-        let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
+        let elf = image.elf();
         let elf_header = elf.header;
         let magic = elf_header.pt1.magic;
-        assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf!");
+        if magic != [0x7f, 0x45, 0x4c, 0x46] {
+            return None;
+        }
+        let base = match elf_header.pt2.type_().as_type() {
+            xmas_elf::header::Type::SharedObject => pie_base,
+            _ => 0,
+        };
+        let relocations = Arc::new(relocations(image, base)?);
         let ph_count = elf_header.pt2.ph_count();
         let mut max_end_vpn = VirtPageNum(0);
         for i in 0..ph_count {
-            let ph = elf.program_header(i).unwrap();
-            if ph.get_type().unwrap() == xmas_elf::program::Type::Load {
-                let start_va: VirtAddr = (ph.virtual_addr() as usize).into();
-                let end_va: VirtAddr = ((ph.virtual_addr() + ph.mem_size()) as usize).into();
+            let ph = elf.program_header(i).ok()?;
+            if ph.get_type().ok()? == xmas_elf::program::Type::Load {
+                let start = base.checked_add(ph.virtual_addr() as usize)?;
+                let end = start.checked_add(ph.mem_size() as usize)?;
+                let start_va: VirtAddr = start.into();
+                let end_va: VirtAddr = end.into();
                 let mut map_perm = MapPermission::U;
                 let ph_flags = ph.flags();
                 if ph_flags.is_read() {
@@ -195,49 +544,129 @@ impl MemorySet {
                 if ph_flags.is_execute() {
                     map_perm |= MapPermission::X;
                 }
-                let map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
-                max_end_vpn = map_area.vpn_range.get_end();
-                memory_set.push(
-                    map_area,
-                    Some(&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize]),
+                let map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm).with_elf(
+                    ElfSegment {
+                        inode: Arc::clone(&image.inode),
+                        offset: ph.offset() as usize,
+                        file_size: ph.file_size() as usize,
+                        va: start_va.into(),
+                        relocations: Arc::clone(&relocations),
+                    },
                 );
+                max_end_vpn = map_area.vpn_range.get_end();
+                self.push(map_area, None);
+            }
+        }
+        Some((base, max_end_vpn))
+    }
+    /// Include sections in elf and trampoline,
+    /// also returns user_sp_base, heap bottom, entry point and the auxiliary vector.
+    /// A dynamically linked program starts in its loader `interp`,
+    /// which must be position-independent and is mapped at `INTERP_BASE`.
+    pub fn from_elf(
+        image: &ElfImage,
+        interp: Option<&ElfImage>,
+    ) -> Result<(Self, usize, usize, usize, Vec<(usize, usize)>), LoadError> {
+        let mut memory_set = Self::new_bare().ok_or(LoadError::OutOfMemory)?;
+        // map trampoline
+        if !memory_set.map_trampoline() {
+            return Err(LoadError::OutOfMemory);
+        }
+        let (base, max_end_vpn) = memory_set
+            .map_elf(image, PIE_BASE)
+            .ok_or(LoadError::BadElf)?;
+        let elf = image.elf();
+        let mut entry_point = base + elf.header.pt2.entry_point() as usize;
+        let mut interp_base = 0;
+        if let Some(interp_image) = interp {
+            let interp = interp_image.elf();
+            // a loader linked at a fixed address would land on the program
+            if interp.header.pt2.type_().as_type() != xmas_elf::header::Type::SharedObject {
+                return Err(LoadError::BadElf);
             }
+            (interp_base, _) = memory_set
+                .map_elf(interp_image, INTERP_BASE)
+                .ok_or(LoadError::BadElf)?;
+            entry_point = interp_base + interp.header.pt2.entry_point() as usize;
         }
+        let auxv = auxiliary_vector(&elf, base, interp_base);
         let max_end_va: VirtAddr = max_end_vpn.into();
-        let mut user_stack_base: usize = max_end_va.into();
-        user_stack_base += PAGE_SIZE;
-        (
-            memory_set,
-            user_stack_base,
-            elf.header.pt2.entry_point() as usize,
-        )
+        // the heap grows from the end of the elf, it starts empty
+        let heap_bottom: usize = max_end_va.into();
+        memory_set.push(
+            MapArea::new(
+                heap_bottom.into(),
+                heap_bottom.into(),
+                MapType::Framed,
+                MapPermission::R | MapPermission::W | MapPermission::U,
+            )
+            .lazy(),
+            None,
+        );
+        // leave room for the heap, the guard page of the first user stack ends it
+        let user_stack_base = heap_bottom + USER_HEAP_LIMIT;
+        Ok((memory_set, user_stack_base, heap_bottom, entry_point, auxv))
     }
-    pub fn from_existed_user(user_space: &MemorySet) -> MemorySet {
-        let mut memory_set = Self::new_bare();
+    /// Share the user pages of `user_space` copy-on-write, which makes its
+    /// writable pages read-only until the next write fault.
+    /// Return `None` if no frame is left for the new page tables and trap contexts.
+    pub fn from_existed_user(user_space: &mut MemorySet) -> Option<MemorySet> {
+        let mut memory_set = Self::new_bare()?;
         // map trampoline
-        memory_set.map_trampoline();
-        // copy data sections/trap_context/user_stack
+        if !memory_set.map_trampoline() {
+            return None;
+        }
+        // share data sections/user_stack, copy trap_context
         for area in user_space.areas.iter() {
-            let new_area = MapArea::from_another(area);
-            memory_set.push(new_area, None);
-            // copy data from another space
-            for vpn in area.vpn_range {
-                let src_ppn = user_space.translate(vpn).unwrap().ppn();
-                let dst_ppn = memory_set.translate(vpn).unwrap().ppn();
-                dst_ppn
-                    .get_bytes_array()
-                    .copy_from_slice(src_ppn.get_bytes_array());
+            let mut new_area = MapArea::from_another(area);
+            if area.map_type == MapType::Framed && area.map_perm.contains(MapPermission::U) {
+                let mut pte_flags = PTEFlags::from_bits(area.map_perm.bits).unwrap();
+                // shared mappings stay shared and writable
+                if !area.shared() {
+                    pte_flags.remove(PTEFlags::W);
+                }
+                for (&vpn, frame) in area.data_frames.iter() {
+                    if area.accessible() {
+                        user_space.page_table.remap(vpn, frame.ppn, pte_flags);
+                        if !memory_set.page_table.try_map(vpn, frame.ppn, pte_flags) {
+                            return None;
+                        }
+                    }
+                    new_area.data_frames.insert(vpn, Arc::clone(frame));
+                }
+                new_area.swapped = area.swapped.clone();
+                memory_set.areas.push(new_area);
+            } else {
+                // the kernel writes trap contexts through physical addresses
+                if !new_area.try_map(&mut memory_set.page_table) {
+                    return None;
+                }
+                memory_set.areas.push(new_area);
+                // copy data from another space, guard pages have none
+                for &vpn in area.data_frames.keys() {
+                    let src_ppn = user_space.translate(vpn).unwrap().ppn();
+                    let dst_ppn = memory_set.translate(vpn).unwrap().ppn();
+                    dst_ppn
+                        .get_bytes_array()
+                        .copy_from_slice(src_ppn.get_bytes_array());
+                }
             }
         }
-        memory_set
+        Some(memory_set)
     }
     pub fn activate(&self) {
+        self.page_table.mark_active();
         let satp = self.page_table.token();
         unsafe {
             satp::write(satp);
             asm!("sfence.vma");
         }
     }
+    /// Token to run this space on the running hart, which may cache its translations from now on.
+    pub fn token_on_hart(&self) -> usize {
+        self.page_table.mark_active();
+        self.token()
+    }
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.page_table.translate(vpn)
     }
@@ -249,9 +678,123 @@ impl MemorySet {
 
 pub struct MapArea {
     vpn_range: VPNRange,
-    data_frames: BTreeMap<VirtPageNum, FrameTracker>,
+    /// frames shared copy-on-write with other address spaces have more than one owner
+    data_frames: BTreeMap<VirtPageNum, Arc<FrameTracker>>,
+    /// pages on the swap device, slots are shared like frames
+    swapped: BTreeMap<VirtPageNum, Arc<SwapSlot>>,
     map_type: MapType,
     map_perm: MapPermission,
+    file: Option<FileMapping>,
+    /// pages are mapped on their first access
+    lazy: bool,
+    elf: Option<ElfSegment>,
+    shm: Option<ShmMapping>,
+}
+
+/// The shared memory segment whose frames an area maps.
+#[derive(Clone)]
+struct ShmMapping {
+    segment: Arc<ShmSegment>,
+    /// page of the segment mapped at the first page of the area
+    page: usize,
+}
+
+/// The file behind an area made by `mmap`.
+#[derive(Clone)]
+pub struct FileMapping {
+    pub inode: Arc<Inode>,
+    /// file offset of the first page of the area
+    pub offset: usize,
+    /// write the pages back to the file when they are unmapped
+    pub shared: bool,
+    /// the file is open for writing
+    pub writable: bool,
+}
+
+/// The part of an elf file that fills a lazily loaded segment.
+#[derive(Clone)]
+pub struct ElfSegment {
+    pub inode: Arc<Inode>,
+    /// file offset of the segment
+    pub offset: usize,
+    /// bytes of the segment in the file, the rest is zeroed
+    pub file_size: usize,
+    /// start address of the segment, not always page aligned
+    pub va: usize,
+    /// addresses to patch and their values, sorted by address and shared by all segments
+    pub relocations: Arc<Vec<(usize, usize)>>,
+}
+
+impl ElfSegment {
+    /// Read the bytes of the segment that fall into the zeroed page `vpn` and relocate them.
+    /// Reading the file may block, so the caller must not hold any lock.
+    pub fn load_page(&self, vpn: VirtPageNum, page: &mut [u8]) {
+        let page_start: usize = VirtAddr::from(vpn).into();
+        let page_end = page_start + PAGE_SIZE;
+        let start = page_start.max(self.va);
+        let end = page_end.min(self.va + self.file_size);
+        if start < end {
+            // a file shorter than the segment leaves the rest zeroed
+            self.inode.read_at(
+                self.offset + start - self.va,
+                &mut page[start - page_start..end - page_start],
+            );
+        }
+        // a patched word may cross into the next page
+        let size = core::mem::size_of::<usize>();
+        let first = self
+            .relocations
+            .partition_point(|&(va, _)| va + size <= page_start);
+        for &(va, value) in self.relocations[first..]
+            .iter()
+            .take_while(|&&(va, _)| va < page_end)
+        {
+            for (i, byte) in value.to_le_bytes().into_iter().enumerate() {
+                if (page_start..page_end).contains(&(va + i)) {
+                    page[va + i - page_start] = byte;
+                }
+            }
+        }
+    }
+}
+
+/// How to fill a page of the elf or of a mapped file on its first access.
+/// Reading the file may block, so the caller must not hold any lock.
+pub enum PageLoader {
+    Elf(ElfSegment),
+    File { inode: Arc<Inode>, offset: usize },
+}
+
+impl PageLoader {
+    /// Fill the zeroed page `vpn`, the part past the end of the file stays zeroed.
+    pub fn load_page(&self, vpn: VirtPageNum, page: &mut [u8]) {
+        match self {
+            Self::Elf(segment) => segment.load_page(vpn, page),
+            Self::File { inode, offset } => {
+                inode.read_at(*offset, page);
+            }
+        }
+    }
+}
+
+/// A page of a shared file mapping that is written back once it is unmapped.
+/// Writing the file may block, so the caller must not hold any lock.
+pub struct FileWriteback {
+    inode: Arc<Inode>,
+    offset: usize,
+    frame: Arc<FrameTracker>,
+}
+
+impl FileWriteback {
+    /// Write the page back, without growing the file.
+    pub fn write(&self) {
+        let size = self.inode.size();
+        if self.offset < size {
+            let len = PAGE_SIZE.min(size - self.offset);
+            self.inode
+                .write_at(self.offset, &self.frame.ppn.get_bytes_array()[..len]);
+        }
+    }
 }
 
 impl MapArea {
@@ -266,54 +809,369 @@ impl MapArea {
         Self {
             vpn_range: VPNRange::new(start_vpn, end_vpn),
             data_frames: BTreeMap::new(),
+            swapped: BTreeMap::new(),
             map_type,
             map_perm,
+            file: None,
+            lazy: false,
+            elf: None,
+            shm: None,
         }
     }
+    /// Fill the pages from `file` when they are first accessed.
+    pub fn with_file(mut self, file: FileMapping) -> Self {
+        self.file = Some(file);
+        self.lazy()
+    }
+    /// Map no page until it is first accessed, then map a zeroed one.
+    pub fn lazy(mut self) -> Self {
+        self.lazy = true;
+        self
+    }
+    /// Map the frames of `shm` instead of new ones.
+    pub fn with_shm(mut self, segment: Arc<ShmSegment>) -> Self {
+        self.shm = Some(ShmMapping { segment, page: 0 });
+        self
+    }
+    /// Guard pages and the areas only the kernel uses have no U.
+    fn is_user(&self) -> bool {
+        self.map_perm.contains(MapPermission::U)
+    }
+    /// Shared memory and shared file mappings are neither copied on write nor swapped.
+    fn shared(&self) -> bool {
+        self.shm.is_some() || self.file.as_ref().is_some_and(|file| file.shared)
+    }
+    /// Pages without any of R, W and X keep their frames but have no page table
+    /// entry, riscv would take such an entry for a pointer to the next level.
+    fn accessible(&self) -> bool {
+        self.map_perm
+            .intersects(MapPermission::R | MapPermission::W | MapPermission::X)
+    }
+    /// Fill the pages from `elf` when they are first accessed.
+    pub fn with_elf(mut self, elf: ElfSegment) -> Self {
+        self.elf = Some(elf);
+        self.lazy()
+    }
     pub fn from_another(another: &MapArea) -> Self {
         Self {
             vpn_range: VPNRange::new(another.vpn_range.get_start(), another.vpn_range.get_end()),
             data_frames: BTreeMap::new(),
+            swapped: BTreeMap::new(),
             map_type: another.map_type,
             map_perm: another.map_perm,
+            file: another.file.clone(),
+            lazy: another.lazy,
+            elf: another.elf.clone(),
+            shm: another.shm.clone(),
+        }
+    }
+    /// Cut this area at `vpn` and return the part from `vpn` on.
+    pub fn split_off(&mut self, vpn: VirtPageNum) -> Self {
+        let start = self.vpn_range.get_start();
+        let end = self.vpn_range.get_end();
+        assert!(start < vpn && vpn < end);
+        self.vpn_range = VPNRange::new(start, vpn);
+        let file = self.file.clone().map(|mut file| {
+            file.offset += (vpn.0 - start.0) * PAGE_SIZE;
+            file
+        });
+        let shm = self.shm.clone().map(|mut shm| {
+            shm.page += vpn.0 - start.0;
+            shm
+        });
+        Self {
+            vpn_range: VPNRange::new(vpn, end),
+            data_frames: self.data_frames.split_off(&vpn),
+            swapped: self.swapped.split_off(&vpn),
+            map_type: self.map_type,
+            map_perm: self.map_perm,
+            file,
+            lazy: self.lazy,
+            elf: self.elf.clone(),
+            shm,
         }
     }
     pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
+        self.try_map_one(page_table, vpn).expect("out of frames");
+    }
+    /// Return `None` if no frame is left.
+    fn try_map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> Option<()> {
+        let mut frame = None;
         let ppn: PhysPageNum;
-        match self.map_type {
-            MapType::Identical => {
-                ppn = PhysPageNum(vpn.0);
-            }
-            MapType::Framed => {
-                let frame = frame_alloc().unwrap();
-                ppn = frame.ppn;
-                self.data_frames.insert(vpn, frame);
+        if self.map_type == MapType::Framed {
+            let data_frame = match &self.shm {
+                Some(shm) => {
+                    Arc::clone(&shm.segment.frames[shm.page + vpn.0 - self.vpn_range.get_start().0])
+                }
+                None => Arc::new(frame_alloc()?),
+            };
+            ppn = data_frame.ppn;
+            frame = Some(data_frame);
+        } else {
+            ppn = self.linear_ppn(vpn);
+        }
+        if self.accessible() {
+            let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+            if !page_table.try_map(vpn, ppn, pte_flags) {
+                return None;
             }
+        }
+        if let Some(frame) = frame {
+            self.data_frames.insert(vpn, frame);
+        }
+        Some(())
+    }
+    /// The page an identical or linear area maps `vpn` to.
+    fn linear_ppn(&self, vpn: VirtPageNum) -> PhysPageNum {
+        match self.map_type {
+            MapType::Identical => PhysPageNum(vpn.0),
             MapType::Linear(pn_offset) => {
                 // check for sv39
                 assert!(vpn.0 < (1usize << 27));
-                ppn = PhysPageNum((vpn.0 as isize + pn_offset) as usize);
+                PhysPageNum((vpn.0 as isize + pn_offset) as usize)
             }
+            MapType::Framed => unreachable!(),
         }
+    }
+    /// Map an identical or linear area with the largest leaves that fit,
+    /// which saves page tables and TLB entries.
+    fn map_linear(&self, page_table: &mut PageTable) {
         let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
-        page_table.map(vpn, ppn, pte_flags);
+        let end = self.vpn_range.get_end();
+        let mut vpn = self.vpn_range.get_start();
+        while vpn < end {
+            let ppn = self.linear_ppn(vpn);
+            let level = (0..=2)
+                .rev()
+                .find(|&level| {
+                    let pages = level_pages(level);
+                    vpn.0 % pages == 0 && ppn.0 % pages == 0 && vpn.0 + pages <= end.0
+                })
+                .unwrap();
+            page_table.map_leaf(vpn, ppn, pte_flags, level);
+            vpn = VirtPageNum(vpn.0 + level_pages(level));
+        }
+    }
+    fn unmap_linear(&self, page_table: &mut PageTable) {
+        let end = self.vpn_range.get_end();
+        let mut vpn = self.vpn_range.get_start();
+        while vpn < end {
+            let level = page_table.unmap_leaf(vpn);
+            vpn = VirtPageNum(vpn.0 + level_pages(level));
+        }
     }
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
-        if self.map_type == MapType::Framed {
-            self.data_frames.remove(&vpn);
+        self.swapped.remove(&vpn);
+        // lazy pages that were never accessed and swapped out pages are not mapped
+        if self.map_type == MapType::Framed && self.data_frames.remove(&vpn).is_none() {
+            return;
+        }
+        if self.accessible() {
+            page_table.unmap(vpn);
         }
+    }
+    /// Resident pages that only this area owns.
+    fn swappable_pages(&self) -> impl Iterator<Item = VirtPageNum> + '_ {
+        let swappable = self.map_type == MapType::Framed
+            && self.map_perm.contains(MapPermission::U)
+            && self.accessible()
+            && !self.shared();
+        self.data_frames
+            .iter()
+            .filter(move |(_, frame)| swappable && Arc::strong_count(frame) == 1)
+            .map(|(&vpn, _)| vpn)
+    }
+    /// Write `vpn` to swap and unmap it, return false if swap is unavailable or full.
+    fn swap_out(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> bool {
+        let Some(slot) = SwapSlot::swap_out(self.data_frames[&vpn].ppn) else {
+            return false;
+        };
+        self.data_frames.remove(&vpn);
+        self.swapped.insert(vpn, Arc::new(slot));
         page_table.unmap(vpn);
+        true
+    }
+    /// Read the swapped out `vpn` into a new frame, return false if no frame is left.
+    fn swap_in(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> bool {
+        let Some(frame) = frame_alloc() else {
+            return false;
+        };
+        self.swapped[&vpn].swap_in(frame.ppn);
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+        if !page_table.try_map(vpn, frame.ppn, pte_flags) {
+            return false;
+        }
+        // the slot is freed once no other address space shares it
+        self.swapped.remove(&vpn);
+        self.data_frames.insert(vpn, Arc::new(frame));
+        true
+    }
+    /// Whether a read or `write` of the area may be resolved by a page fault.
+    fn allows(&self, write: bool) -> bool {
+        let allowed = if write {
+            MapPermission::W
+        } else {
+            MapPermission::R | MapPermission::X
+        };
+        self.map_perm.intersects(allowed)
+    }
+    /// Map `vpn` zeroed on its first access.
+    /// Return false if the area is not lazy, is filled from an elf, or no frame is left.
+    fn handle_lazy_fault(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> bool {
+        self.lazy
+            && self.elf.is_none()
+            && self.file.is_none()
+            && self.try_map_one(page_table, vpn).is_some()
+    }
+    /// Give `vpn` a frame of its own and make it writable again.
+    /// Return false if the area is not writable or no frame is left.
+    fn handle_cow_fault(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> bool {
+        if self.map_type != MapType::Framed || !self.map_perm.contains(MapPermission::W) {
+            return false;
+        }
+        let Some(frame) = self.data_frames.get_mut(&vpn) else {
+            return false;
+        };
+        // the last owner keeps the frame without copying
+        if Arc::strong_count(frame) > 1 {
+            let Some(new_frame) = frame_alloc() else {
+                return false;
+            };
+            new_frame
+                .ppn
+                .get_bytes_array()
+                .copy_from_slice(frame.ppn.get_bytes_array());
+            *frame = Arc::new(new_frame);
+        }
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+        page_table.remap(vpn, frame.ppn, pte_flags);
+        true
+    }
+    /// Change the permission of the area and of its resident pages,
+    /// pages shared copy-on-write stay read-only.
+    /// Return false and keep the old permission when no frame is left for the page tables.
+    fn set_perm(&mut self, page_table: &mut PageTable, perm: MapPermission) -> bool {
+        let old_perm = self.map_perm;
+        let was_accessible = self.accessible();
+        self.map_perm = perm;
+        let accessible = self.accessible();
+        let shared = self.shared();
+        let mut mapped = Vec::new();
+        for (&vpn, frame) in self.data_frames.iter() {
+            let mut pte_flags = PTEFlags::from_bits(perm.bits).unwrap();
+            if !shared && Arc::strong_count(frame) > 1 {
+                pte_flags.remove(PTEFlags::W);
+            }
+            match (was_accessible, accessible) {
+                (true, true) => page_table.remap(vpn, frame.ppn, pte_flags),
+                (true, false) => page_table.unmap(vpn),
+                (false, true) => {
+                    if !page_table.try_map(vpn, frame.ppn, pte_flags) {
+                        for vpn in mapped {
+                            page_table.unmap(vpn);
+                        }
+                        self.map_perm = old_perm;
+                        return false;
+                    }
+                    mapped.push(vpn);
+                }
+                (false, false) => {}
+            }
+        }
+        true
     }
     pub fn map(&mut self, page_table: &mut PageTable) {
+        if self.lazy {
+            return;
+        }
+        if self.map_type != MapType::Framed {
+            self.map_linear(page_table);
+            return;
+        }
         for vpn in self.vpn_range {
             self.map_one(page_table, vpn);
         }
     }
     pub fn unmap(&mut self, page_table: &mut PageTable) {
+        if self.map_type != MapType::Framed {
+            self.unmap_linear(page_table);
+            return;
+        }
         for vpn in self.vpn_range {
             self.unmap_one(page_table, vpn);
         }
     }
+    /// Map the pages up to `new_end`, or undo that and return false when out of frames.
+    pub fn append_to(&mut self, page_table: &mut PageTable, new_end: VirtPageNum) -> bool {
+        let start = self.vpn_range.get_start();
+        let end = self.vpn_range.get_end();
+        if self.lazy {
+            self.vpn_range = VPNRange::new(start, end.max(new_end));
+            return true;
+        }
+        for vpn in VPNRange::new(end, new_end) {
+            if self.try_map_one(page_table, vpn).is_none() {
+                for mapped in VPNRange::new(end, vpn) {
+                    self.unmap_one(page_table, mapped);
+                }
+                return false;
+            }
+        }
+        self.vpn_range = VPNRange::new(start, end.max(new_end));
+        true
+    }
+    pub fn shrink_to(&mut self, page_table: &mut PageTable, new_end: VirtPageNum) {
+        let start = self.vpn_range.get_start();
+        let end = self.vpn_range.get_end();
+        for vpn in VPNRange::new(new_end, end) {
+            self.unmap_one(page_table, vpn);
+        }
+        self.vpn_range = VPNRange::new(start, new_end.min(end));
+    }
+    /// Map all pages, or undo the mapping and return false when out of frames.
+    pub fn try_map(&mut self, page_table: &mut PageTable) -> bool {
+        if self.lazy {
+            return true;
+        }
+        for vpn in self.vpn_range {
+            if self.try_map_one(page_table, vpn).is_none() {
+                for mapped in VPNRange::new(self.vpn_range.get_start(), vpn) {
+                    self.unmap_one(page_table, mapped);
+                }
+                return false;
+            }
+        }
+        true
+    }
+    /// How to fill `vpn` if it is an unloaded page of the elf or of a mapped file.
+    fn page_loader(&self, vpn: VirtPageNum) -> Option<PageLoader> {
+        if self.data_frames.contains_key(&vpn) || self.swapped.contains_key(&vpn) {
+            return None;
+        }
+        if let Some(segment) = &self.elf {
+            return Some(PageLoader::Elf(segment.clone()));
+        }
+        let file = self.file.as_ref()?;
+        Some(PageLoader::File {
+            inode: Arc::clone(&file.inode),
+            offset: file.offset + (vpn.0 - self.vpn_range.get_start().0) * PAGE_SIZE,
+        })
+    }
+    /// The loaded pages of a shared file mapping, to be written back.
+    fn file_writeback(&self) -> Vec<FileWriteback> {
+        let Some(file) = self.file.as_ref().filter(|file| file.shared) else {
+            return Vec::new();
+        };
+        let start = self.vpn_range.get_start();
+        self.data_frames
+            .iter()
+            .map(|(vpn, frame)| FileWriteback {
+                inode: Arc::clone(&file.inode),
+                offset: file.offset + (vpn.0 - start.0) * PAGE_SIZE,
+                frame: Arc::clone(frame),
+            })
+            .collect()
+    }
     /// data: start-aligned but maybe with shorter length
     /// assume that all frames were cleared before
     pub fn copy_data(&mut self, page_table: &PageTable, data: &[u8]) {
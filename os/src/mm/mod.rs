@@ -1,21 +1,49 @@
 mod address;
+mod asid;
+mod elf;
 mod frame_allocator;
 mod heap_allocator;
 mod memory_set;
 mod page_table;
+mod shm;
+mod swap;
+
+use log::info;
 
 pub use address::VPNRange;
 pub use address::{PhysAddr, PhysPageNum, StepByOne, VirtAddr, VirtPageNum};
-pub use frame_allocator::{FrameTracker, frame_alloc, frame_alloc_more, frame_dealloc};
-pub use memory_set::{KERNEL_SPACE, MapArea, MapPermission, MapType, MemorySet, kernel_token};
-use page_table::PTEFlags;
+pub use asid::shares_kernel_asid;
+pub use elf::ElfImage;
+pub use frame_allocator::{
+    FrameStats, FrameTracker, frame_alloc, frame_alloc_more, frame_dealloc, frame_stats,
+};
+pub use heap_allocator::{HeapStats, heap_stats};
+pub use memory_set::{
+    FAULT_FRAMES, FileMapping, KERNEL_SPACE, LoadError, MapArea, MapPermission, MapType, MemorySet,
+    PageLoader, kernel_token,
+};
+use page_table::{PTEFlags, level_pages};
 pub use page_table::{
-    PageTable, PageTableEntry, UserBuffer, translated_byte_buffer, translated_ref,
-    translated_refmut, translated_str,
+    PageTable, PageTableEntry, UserBuffer, copy_from_user, copy_to_user, translated_byte_buffer,
+    translated_byte_buffer_mut, translated_object_mut, translated_str,
 };
+pub use shm::{ShmSegment, shm_create, shm_find, shm_get, shm_remove};
 
 pub fn init() {
     heap_allocator::init_heap();
     frame_allocator::init_frame_allocator();
+    let stats = frame_stats();
+    info!(
+        "physical frames: {} used, {} free, largest free order {:?}",
+        stats.used(),
+        stats.free,
+        stats.largest_order
+    );
+    KERNEL_SPACE.exclusive_access().activate();
+    asid::init();
+}
+
+/// Switch a secondary hart to the kernel space set up by the boot hart.
+pub fn init_secondary() {
     KERNEL_SPACE.exclusive_access().activate();
 }
@@ -1,8 +1,15 @@
 use super::{FrameTracker, PhysAddr, PhysPageNum, StepByOne, VirtAddr, VirtPageNum, frame_alloc};
+use crate::config::PAGE_SIZE;
+use crate::hart::{hart_id, online_harts};
+use crate::sbi::remote_sfence_vma_asid;
+use crate::task::handle_page_fault_of_current;
 use alloc::string::String;
 use alloc::vec;
 use alloc::vec::Vec;
 use bitflags::*;
+use core::arch::asm;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 bitflags! {
     pub struct PTEFlags: u8 {
@@ -50,56 +57,99 @@ impl PageTableEntry {
     pub fn executable(&self) -> bool {
         (self.flags() & PTEFlags::X) != PTEFlags::empty()
     }
+    /// A valid entry without any of R, W and X points to the next level.
+    pub fn is_leaf(&self) -> bool {
+        self.is_valid()
+            && self
+                .flags()
+                .intersects(PTEFlags::R | PTEFlags::W | PTEFlags::X)
+    }
+}
+
+/// Pages mapped by a leaf at `level`, 4 KiB at level 0, 2 MiB at level 1 and 1 GiB at level 2.
+pub fn level_pages(level: usize) -> usize {
+    1 << (9 * level)
 }
 
 pub struct PageTable {
     root_ppn: PhysPageNum,
+    /// tags the TLB entries of this table, so switching to it needs no flush
+    asid: usize,
+    /// harts that may cache translations of this table, one bit each
+    harts: AtomicUsize,
     frames: Vec<FrameTracker>,
 }
 
-/// Assume that it won't oom when creating/mapping.
 impl PageTable {
-    pub fn new() -> Self {
-        let frame = frame_alloc().unwrap();
-        PageTable {
+    /// Return `None` if no frame is left for the root table.
+    pub fn new(asid: usize) -> Option<Self> {
+        let frame = frame_alloc()?;
+        Some(PageTable {
             root_ppn: frame.ppn,
+            asid,
+            harts: AtomicUsize::new(0),
             frames: vec![frame],
-        }
+        })
     }
     /// Temporarily used to get arguments from user space.
     pub fn from_token(satp: usize) -> Self {
         Self {
             root_ppn: PhysPageNum::from(satp & ((1usize << 44) - 1)),
+            asid: (satp >> 44) & 0xffff,
+            harts: AtomicUsize::new(usize::MAX),
             frames: Vec::new(),
         }
     }
-    fn find_pte_create(&mut self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+    /// Note that the running hart is about to translate through this table.
+    pub fn mark_active(&self) {
+        self.harts.fetch_or(1 << hart_id(), Ordering::Relaxed);
+    }
+    /// Drop the cached translation of `vpn` after its entry changed,
+    /// even a new entry since riscv may cache invalid ones.
+    /// Other harts that used this table are asked to do the same.
+    fn flush(&self, vpn: VirtPageNum) {
+        let va: VirtAddr = vpn.into();
+        unsafe {
+            asm!("sfence.vma {}, {}", in(reg) va.0, in(reg) self.asid);
+        }
+        let others = self.harts.load(Ordering::Relaxed) & online_harts() & !(1 << hart_id());
+        if others != 0 {
+            remote_sfence_vma_asid(others, va.0, PAGE_SIZE, self.asid);
+        }
+    }
+    /// Find the entry of `vpn` at `level`, return `None` if a huge page covers it
+    /// or no frame is left for a page table on the way.
+    fn find_pte_create(&mut self, vpn: VirtPageNum, level: usize) -> Option<&mut PageTableEntry> {
         let idxs = vpn.indexes();
         let mut ppn = self.root_ppn;
         let mut result: Option<&mut PageTableEntry> = None;
         for (i, idx) in idxs.iter().enumerate() {
             let pte = &mut ppn.get_pte_array()[*idx];
-            if i == 2 {
+            if i == 2 - level {
                 result = Some(pte);
                 break;
             }
             if !pte.is_valid() {
-                let frame = frame_alloc().unwrap();
+                let frame = frame_alloc()?;
                 *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
                 self.frames.push(frame);
+            } else if pte.is_leaf() {
+                return None;
             }
             ppn = pte.ppn();
         }
         result
     }
-    fn find_pte(&self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+    /// Find the leaf that maps `vpn` and its level,
+    /// or the empty entry at level 0 if `vpn` is not mapped.
+    fn find_pte(&self, vpn: VirtPageNum) -> Option<(&mut PageTableEntry, usize)> {
         let idxs = vpn.indexes();
         let mut ppn = self.root_ppn;
-        let mut result: Option<&mut PageTableEntry> = None;
+        let mut result: Option<(&mut PageTableEntry, usize)> = None;
         for (i, idx) in idxs.iter().enumerate() {
             let pte = &mut ppn.get_pte_array()[*idx];
-            if i == 2 {
-                result = Some(pte);
+            if i == 2 || pte.is_leaf() {
+                result = Some((pte, 2 - i));
                 break;
             }
             if !pte.is_valid() {
@@ -111,21 +161,63 @@ impl PageTable {
     }
     #[allow(unused)]
     pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
-        let pte = self.find_pte_create(vpn).unwrap();
+        self.map_leaf(vpn, ppn, flags, 0);
+    }
+    /// Like `map`, but return false without mapping when no frame is left for a page table.
+    pub fn try_map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) -> bool {
+        let Some(pte) = self.find_pte_create(vpn, 0) else {
+            return false;
+        };
         assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
         *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        self.flush(vpn);
+        true
+    }
+    /// Map `level_pages(level)` pages from `vpn` to those from `ppn` with a single leaf,
+    /// both must be aligned to its size.
+    pub fn map_leaf(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags, level: usize) {
+        assert!(vpn.0 % level_pages(level) == 0 && ppn.0 % level_pages(level) == 0);
+        let pte = self
+            .find_pte_create(vpn, level)
+            .unwrap_or_else(|| panic!("vpn {:?} is in a huge page or out of frames", vpn));
+        assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
+        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        self.flush(vpn);
     }
     #[allow(unused)]
     pub fn unmap(&mut self, vpn: VirtPageNum) {
-        let pte = self.find_pte(vpn).unwrap();
+        self.unmap_leaf(vpn);
+    }
+    /// Unmap the leaf that maps `vpn` from its first page on, return its level.
+    pub fn unmap_leaf(&mut self, vpn: VirtPageNum) -> usize {
+        let (pte, level) = self.find_pte(vpn).unwrap();
         assert!(pte.is_valid(), "vpn {:?} is invalid before unmapping", vpn);
+        assert!(
+            vpn.0 % level_pages(level) == 0,
+            "vpn {:?} is in a huge page",
+            vpn
+        );
         *pte = PageTableEntry::empty();
+        self.flush(vpn);
+        level
     }
+    /// Point the mapped `vpn` at `ppn` with new flags.
+    pub fn remap(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
+        let (pte, level) = self.find_pte(vpn).unwrap();
+        assert!(pte.is_valid(), "vpn {:?} is invalid before remapping", vpn);
+        assert!(level == 0, "vpn {:?} is in a huge page", vpn);
+        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        self.flush(vpn);
+    }
+    /// The entry of a page inside a huge page points at that page.
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
-        self.find_pte(vpn).map(|pte| *pte)
+        self.find_pte(vpn).map(|(pte, level)| {
+            let offset = vpn.0 % level_pages(level);
+            PageTableEntry::new(PhysPageNum(pte.ppn().0 + offset), pte.flags())
+        })
     }
     pub fn translate_va(&self, va: VirtAddr) -> Option<PhysAddr> {
-        self.find_pte(va.clone().floor()).map(|pte| {
+        self.translate(va.clone().floor()).map(|pte| {
             let aligned_pa: PhysAddr = pte.ppn().into();
             let offset = va.page_offset();
             let aligned_pa_usize: usize = aligned_pa.into();
@@ -133,19 +225,44 @@ impl PageTable {
         })
     }
     pub fn token(&self) -> usize {
-        8usize << 60 | self.root_ppn.0
+        8usize << 60 | self.asid << 44 | self.root_ppn.0
     }
 }
 
-pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&'static mut [u8]> {
+/// Return the physical page of `vpn` only if it is valid and accessible
+/// from user mode with all of `flags`.
+fn translate_user_vpn(
+    page_table: &PageTable,
+    vpn: VirtPageNum,
+    flags: PTEFlags,
+) -> Option<PhysPageNum> {
+    let accessible = |pte: &PageTableEntry| pte.flags().contains(flags | PTEFlags::V | PTEFlags::U);
+    let mut pte = page_table.translate(vpn);
+    // the kernel accesses user memory through physical addresses and never faults,
+    // so resolve lazy and copy-on-write pages of the current process here,
+    // without swapping out pages that earlier translations may point to
+    if !pte.as_ref().is_some_and(accessible)
+        && handle_page_fault_of_current(page_table.token(), vpn, flags.contains(PTEFlags::W), false)
+    {
+        pte = page_table.translate(vpn);
+    }
+    pte.filter(accessible).map(|pte| pte.ppn())
+}
+
+fn translated_byte_buffer_with(
+    token: usize,
+    ptr: usize,
+    len: usize,
+    flags: PTEFlags,
+) -> Option<Vec<&'static mut [u8]>> {
     let page_table = PageTable::from_token(token);
-    let mut start = ptr as usize;
-    let end = start + len;
+    let mut start = ptr;
+    let end = start.checked_add(len)?;
     let mut v = Vec::new();
     while start < end {
         let start_va = VirtAddr::from(start);
         let mut vpn = start_va.floor();
-        let ppn = page_table.translate(vpn).unwrap().ppn();
+        let ppn = translate_user_vpn(&page_table, vpn, flags)?;
         vpn.step();
         let mut end_va: VirtAddr = vpn.into();
         end_va = end_va.min(VirtAddr::from(end));
@@ -156,43 +273,76 @@ pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&
         }
         start = end_va.into();
     }
-    v
+    Some(v)
+}
+
+/// Translate a user buffer that the kernel is going to read from.
+/// Return `None` if any page of it is not readable by the user.
+pub fn translated_byte_buffer(
+    token: usize,
+    ptr: *const u8,
+    len: usize,
+) -> Option<Vec<&'static mut [u8]>> {
+    translated_byte_buffer_with(token, ptr as usize, len, PTEFlags::R)
+}
+
+/// Translate a user buffer that the kernel is going to write to.
+/// Return `None` if any page of it is not writable by the user.
+pub fn translated_byte_buffer_mut(
+    token: usize,
+    ptr: *mut u8,
+    len: usize,
+) -> Option<Vec<&'static mut [u8]>> {
+    translated_byte_buffer_with(token, ptr as usize, len, PTEFlags::W)
 }
 
 /// Load a string from other address spaces into kernel space without an end `\0`.
-pub fn translated_str(token: usize, ptr: *const u8) -> String {
+/// Return `None` if the string runs into a page not readable by the user.
+pub fn translated_str(token: usize, ptr: *const u8) -> Option<String> {
     let page_table = PageTable::from_token(token);
     let mut string = String::new();
-    let mut va = ptr as usize;
+    let mut va = VirtAddr::from(ptr as usize);
     loop {
-        let ch: u8 = *(page_table
-            .translate_va(VirtAddr::from(va))
-            .unwrap()
-            .get_mut());
+        let ppn = translate_user_vpn(&page_table, va.floor(), PTEFlags::R)?;
+        let ch = ppn.get_bytes_array()[va.page_offset()];
         if ch == 0 {
             break;
         }
         string.push(ch as char);
-        va += 1;
+        va = VirtAddr::from(va.0 + 1);
     }
-    string
+    Some(string)
 }
 
-pub fn translated_ref<T>(token: usize, ptr: *const T) -> &'static T {
-    let page_table = PageTable::from_token(token);
-    page_table
-        .translate_va(VirtAddr::from(ptr as usize))
-        .unwrap()
-        .get_ref()
+/// Copy a user object into the kernel, it may cross pages and need not be aligned.
+/// Any bit pattern must be a valid `T`.
+/// Return `None` if any page of it is not readable by the user.
+pub fn copy_from_user<T: Copy>(token: usize, ptr: *const T) -> Option<T> {
+    let mut value = MaybeUninit::<T>::uninit();
+    let bytes = unsafe {
+        core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, core::mem::size_of::<T>())
+    };
+    let mut copied = 0;
+    for buffer in translated_byte_buffer(token, ptr as *const u8, core::mem::size_of::<T>())? {
+        bytes[copied..copied + buffer.len()].copy_from_slice(buffer);
+        copied += buffer.len();
+    }
+    Some(unsafe { value.assume_init() })
 }
 
-pub fn translated_refmut<T>(token: usize, ptr: *mut T) -> &'static mut T {
-    let page_table = PageTable::from_token(token);
-    let va = ptr as usize;
-    page_table
-        .translate_va(VirtAddr::from(va))
-        .unwrap()
-        .get_mut()
+/// Translate a user object that the kernel is going to write with `UserBuffer::write_value`,
+/// so that it can be written without page faults later. It may cross pages and need not
+/// be aligned. Return `None` if any page of it is not writable by the user.
+pub fn translated_object_mut<T>(token: usize, ptr: *mut T) -> Option<UserBuffer> {
+    translated_byte_buffer_mut(token, ptr as *mut u8, core::mem::size_of::<T>())
+        .map(UserBuffer::new)
+}
+
+/// Copy a kernel object to the user, it may cross pages and need not be aligned.
+/// Return `None` if any page of it is not writable by the user.
+pub fn copy_to_user<T>(token: usize, ptr: *mut T, value: &T) -> Option<()> {
+    translated_object_mut(token, ptr)?.write_value(value);
+    Some(())
 }
 
 pub struct UserBuffer {
@@ -203,6 +353,16 @@ impl UserBuffer {
     pub fn new(buffers: Vec<&'static mut [u8]>) -> Self {
         Self { buffers }
     }
+    /// Copy the bytes of `value` to the start of the buffer.
+    pub fn write_value<T>(&mut self, value: &T) {
+        let bytes = unsafe {
+            core::slice::from_raw_parts(value as *const T as *const u8, core::mem::size_of::<T>())
+        };
+        let dsts = self.buffers.iter_mut().flat_map(|buffer| buffer.iter_mut());
+        for (dst, &src) in dsts.zip(bytes) {
+            *dst = src;
+        }
+    }
     pub fn len(&self) -> usize {
         let mut total: usize = 0;
         for b in self.buffers.iter() {
@@ -0,0 +1,64 @@
+use super::{FrameTracker, frame_alloc};
+use crate::sync::SpinNoIrqLock;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+/// Frames shared by every address space the segment is attached to.
+/// They are freed once the segment is removed and the last area mapping it is gone.
+pub struct ShmSegment {
+    /// 0 for a private segment that can not be looked up
+    pub key: usize,
+    pub frames: Vec<Arc<FrameTracker>>,
+}
+
+struct ShmTable {
+    next_id: usize,
+    segments: BTreeMap<usize, Arc<ShmSegment>>,
+}
+
+lazy_static! {
+    static ref SHM_TABLE: SpinNoIrqLock<ShmTable> = unsafe {
+        SpinNoIrqLock::new(ShmTable {
+            next_id: 1,
+            segments: BTreeMap::new(),
+        })
+    };
+}
+
+/// Return the id and the segment named `key`.
+pub fn shm_find(key: usize) -> Option<(usize, Arc<ShmSegment>)> {
+    if key == 0 {
+        return None;
+    }
+    SHM_TABLE
+        .exclusive_access()
+        .segments
+        .iter()
+        .find(|(_, segment)| segment.key == key)
+        .map(|(&id, segment)| (id, Arc::clone(segment)))
+}
+
+/// Create a zeroed segment of `pages` pages, return `None` when out of frames.
+pub fn shm_create(key: usize, pages: usize) -> Option<usize> {
+    let frames = (0..pages)
+        .map(|_| frame_alloc().map(Arc::new))
+        .collect::<Option<Vec<_>>>()?;
+    let mut table = SHM_TABLE.exclusive_access();
+    let id = table.next_id;
+    table.next_id += 1;
+    table
+        .segments
+        .insert(id, Arc::new(ShmSegment { key, frames }));
+    Some(id)
+}
+
+pub fn shm_get(id: usize) -> Option<Arc<ShmSegment>> {
+    SHM_TABLE.exclusive_access().segments.get(&id).cloned()
+}
+
+/// Forget segment `id`, it stays mapped where it is attached.
+pub fn shm_remove(id: usize) -> bool {
+    SHM_TABLE.exclusive_access().segments.remove(&id).is_some()
+}
@@ -0,0 +1,80 @@
+use super::PhysPageNum;
+use crate::config::{PAGE_SIZE, SWAP_SIZE};
+use crate::drivers::block::SWAP_DEVICE;
+use crate::sync::SpinNoIrqLock;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+const BLOCK_SIZE: usize = 512;
+const BLOCKS_PER_PAGE: usize = PAGE_SIZE / BLOCK_SIZE;
+
+struct SwapAllocator {
+    current: usize,
+    end: usize,
+    recycled: Vec<usize>,
+}
+
+impl SwapAllocator {
+    fn new(slots: usize) -> Self {
+        Self {
+            current: 0,
+            end: slots,
+            recycled: Vec::new(),
+        }
+    }
+    fn alloc(&mut self) -> Option<usize> {
+        if let Some(id) = self.recycled.pop() {
+            Some(id)
+        } else if self.current == self.end {
+            None
+        } else {
+            self.current += 1;
+            Some(self.current - 1)
+        }
+    }
+    fn dealloc(&mut self, id: usize) {
+        assert!(id < self.current);
+        assert!(
+            !self.recycled.iter().any(|&i| i == id),
+            "swap slot {} has been deallocated!",
+            id
+        );
+        self.recycled.push(id);
+    }
+}
+
+lazy_static! {
+    static ref SWAP_ALLOCATOR: SpinNoIrqLock<SwapAllocator> =
+        unsafe { SpinNoIrqLock::new(SwapAllocator::new(SWAP_SIZE / PAGE_SIZE)) };
+}
+
+/// A page on the swap device, freed when dropped.
+pub struct SwapSlot {
+    id: usize,
+}
+
+impl SwapSlot {
+    /// Write the page `ppn` to swap.
+    /// Return `None` if there is no swap device or it is full.
+    pub fn swap_out(ppn: PhysPageNum) -> Option<Self> {
+        let device = SWAP_DEVICE.as_ref()?;
+        let id = SWAP_ALLOCATOR.exclusive_access().alloc()?;
+        for (i, block) in ppn.get_bytes_array().chunks(BLOCK_SIZE).enumerate() {
+            device.write_block(id * BLOCKS_PER_PAGE + i, block);
+        }
+        Some(Self { id })
+    }
+    /// Read the page back into `ppn`, the slot stays allocated.
+    pub fn swap_in(&self, ppn: PhysPageNum) {
+        let device = SWAP_DEVICE.as_ref().unwrap();
+        for (i, block) in ppn.get_bytes_array().chunks_mut(BLOCK_SIZE).enumerate() {
+            device.read_block(self.id * BLOCKS_PER_PAGE + i, block);
+        }
+    }
+}
+
+impl Drop for SwapSlot {
+    fn drop(&mut self) {
+        SWAP_ALLOCATOR.exclusive_access().dealloc(self.id);
+    }
+}
@@ -2,8 +2,8 @@ use alloc::{sync::Arc, vec::Vec};
 use lazy_static::lazy_static;
 use lose_net_stack::packets::tcp::TCPPacket;
 
-use crate::fs::File;
-use crate::sync::UPIntrFreeCell;
+use crate::fs::{FdFlags, File, FileDescriptor, PollEvents};
+use crate::sync::{SpinNoIrqLock, WaitQueue};
 use crate::task::TaskControlBlock;
 
 use super::tcp::TCP;
@@ -12,11 +12,14 @@ pub struct Port {
     pub port: u16,
     pub receivable: bool,
     pub schedule: Option<Arc<TaskControlBlock>>,
+    // a client knocked while nobody was accepting, it will retransmit the SYN
+    pub pending: bool,
+    pub waiters: WaitQueue,
 }
 
 lazy_static! {
-    static ref LISTEN_TABLE: UPIntrFreeCell<Vec<Option<Port>>> =
-        unsafe { UPIntrFreeCell::new(Vec::new()) };
+    static ref LISTEN_TABLE: SpinNoIrqLock<Vec<Option<Port>>> =
+        unsafe { SpinNoIrqLock::new(Vec::new()) };
 }
 
 pub fn listen(port: u16) -> Option<usize> {
@@ -33,6 +36,8 @@ pub fn listen(port: u16) -> Option<usize> {
         port,
         receivable: false,
         schedule: None,
+        pending: false,
+        waiters: WaitQueue::new(),
     };
 
     if index == usize::MAX {
@@ -53,6 +58,7 @@ pub fn accept(listen_index: usize, task: Arc<TaskControlBlock>) {
     let listen_port = listen_port.unwrap();
     listen_port.receivable = true;
     listen_port.schedule = Some(task);
+    listen_port.pending = false;
 }
 
 pub fn port_acceptable(listen_index: usize) -> bool {
@@ -74,6 +80,13 @@ pub fn check_accept(port: u16, tcp_packet: &TCPPacket) -> Option<()> {
             })
             .collect();
         if listen_ports.len() == 0 {
+            // let the pollers of this port know that a client is waiting
+            for listen_port in listen_table.iter_mut().flatten() {
+                if listen_port.port == port {
+                    listen_port.pending = true;
+                    listen_port.waiters.wake_all();
+                }
+            }
             None
         } else {
             let listen_port = listen_ports[0].as_mut().unwrap();
@@ -101,7 +114,7 @@ pub fn accept_connection(_port: u16, tcp_packet: &TCPPacket, task: Arc<TaskContr
         tcp_packet.ack,
     );
 
-    inner.fd_table[fd] = Some(Arc::new(tcp_socket));
+    inner.fd_table[fd] = Some(FileDescriptor::new(Arc::new(tcp_socket), FdFlags::empty()));
 
     let cx = task.inner_exclusive_access().get_trap_cx();
     cx.x[10] = fd;
@@ -138,4 +151,24 @@ impl File for PortFd {
     fn write(&self, _buf: crate::mm::UserBuffer) -> usize {
         0
     }
+
+    fn poll(&self) -> PollEvents {
+        let listen_table = LISTEN_TABLE.exclusive_access();
+        match &listen_table[self.0] {
+            Some(port) if port.pending => PollEvents::IN,
+            _ => PollEvents::empty(),
+        }
+    }
+
+    fn register_waiter(&self, task: Arc<TaskControlBlock>) {
+        if let Some(port) = &LISTEN_TABLE.exclusive_access()[self.0] {
+            port.waiters.add(task);
+        }
+    }
+
+    fn unregister_waiter(&self, task: &Arc<TaskControlBlock>) {
+        if let Some(port) = &LISTEN_TABLE.exclusive_access()[self.0] {
+            port.waiters.remove(task);
+        }
+    }
 }
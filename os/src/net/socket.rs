@@ -1,9 +1,12 @@
 use alloc::collections::VecDeque;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use lazy_static::lazy_static;
 use lose_net_stack::IPv4;
 
-use crate::sync::UPIntrFreeCell;
+use crate::mm::UserBuffer;
+use crate::sync::{SpinNoIrqLock, WaitQueue};
+use crate::task::TaskControlBlock;
 
 // TODO: specify the protocol, TCP or UDP
 pub struct Socket {
@@ -13,11 +16,12 @@ pub struct Socket {
     pub buffers: VecDeque<Vec<u8>>, // datas
     pub seq: u32,
     pub ack: u32,
+    pub waiters: WaitQueue, // tasks polling this socket
 }
 
 lazy_static! {
-    static ref SOCKET_TABLE: UPIntrFreeCell<Vec<Option<Socket>>> =
-        unsafe { UPIntrFreeCell::new(Vec::new()) };
+    static ref SOCKET_TABLE: SpinNoIrqLock<Vec<Option<Socket>>> =
+        unsafe { SpinNoIrqLock::new(Vec::new()) };
 }
 
 /// get the seq and ack by socket index
@@ -81,6 +85,7 @@ pub fn add_socket(raddr: IPv4, lport: u16, rport: u16) -> Option<usize> {
         buffers: VecDeque::new(),
         seq: 0,
         ack: 0,
+        waiters: WaitQueue::new(),
     };
 
     if index == usize::MAX {
@@ -106,11 +111,9 @@ pub fn push_data(index: usize, data: Vec<u8>) {
     assert!(socket_table.len() > index);
     assert!(socket_table[index].is_some());
 
-    socket_table[index]
-        .as_mut()
-        .unwrap()
-        .buffers
-        .push_back(data);
+    let sock = socket_table[index].as_mut().unwrap();
+    sock.buffers.push_back(data);
+    sock.waiters.wake_all();
 }
 
 pub fn pop_data(index: usize) -> Option<Vec<u8>> {
@@ -121,3 +124,48 @@ pub fn pop_data(index: usize) -> Option<Vec<u8>> {
 
     socket_table[index].as_mut().unwrap().buffers.pop_front()
 }
+
+pub fn has_data(index: usize) -> bool {
+    let socket_table = SOCKET_TABLE.exclusive_access();
+
+    assert!(socket_table.len() > index);
+    assert!(socket_table[index].is_some());
+
+    !socket_table[index].as_ref().unwrap().buffers.is_empty()
+}
+
+/// wake the task when data is pushed to the socket
+pub fn add_waiter(index: usize, task: Arc<TaskControlBlock>) {
+    let socket_table = SOCKET_TABLE.exclusive_access();
+
+    assert!(socket_table.len() > index);
+    assert!(socket_table[index].is_some());
+
+    socket_table[index].as_ref().unwrap().waiters.add(task);
+}
+
+pub fn remove_waiter(index: usize, task: &Arc<TaskControlBlock>) {
+    let socket_table = SOCKET_TABLE.exclusive_access();
+
+    assert!(socket_table.len() > index);
+    assert!(socket_table[index].is_some());
+
+    socket_table[index].as_ref().unwrap().waiters.remove(task);
+}
+
+/// copy a received packet into the user buffer, return the copied length
+pub fn copy_data(data: &[u8], buf: &mut UserBuffer) -> usize {
+    let data_len = data.len();
+    let mut left = 0;
+    for i in 0..buf.buffers.len() {
+        let buffer_i_len = buf.buffers[i].len().min(data_len - left);
+
+        buf.buffers[i][..buffer_i_len].copy_from_slice(&data[left..(left + buffer_i_len)]);
+
+        left += buffer_i_len;
+        if left == data_len {
+            break;
+        }
+    }
+    left
+}
@@ -1,15 +1,18 @@
+use alloc::sync::Arc;
 use alloc::vec;
 use lose_net_stack::IPv4;
 use lose_net_stack::MacAddress;
 use lose_net_stack::TcpFlags;
 use lose_net_stack::packets::tcp::TCPPacket;
 
-use crate::{drivers::NET_DEVICE, fs::File};
+use crate::drivers::NET_DEVICE;
+use crate::fs::{File, PollEvents};
+use crate::task::TaskControlBlock;
 
 use super::socket::get_s_a_by_index;
 use super::{
     LOSE_NET_STACK, net_interrupt_handler,
-    socket::{add_socket, pop_data, remove_socket},
+    socket::{add_socket, add_waiter, copy_data, has_data, pop_data, remove_socket, remove_waiter},
 };
 
 // add tcp packet info to this structure
@@ -51,26 +54,18 @@ impl File for TCP {
     fn read(&self, mut buf: crate::mm::UserBuffer) -> usize {
         loop {
             if let Some(data) = pop_data(self.socket_index) {
-                let data_len = data.len();
-                let mut left = 0;
-                for i in 0..buf.buffers.len() {
-                    let buffer_i_len = buf.buffers[i].len().min(data_len - left);
-
-                    buf.buffers[i][..buffer_i_len]
-                        .copy_from_slice(&data[left..(left + buffer_i_len)]);
-
-                    left += buffer_i_len;
-                    if left == data_len {
-                        break;
-                    }
-                }
-                return left;
+                return copy_data(&data, &mut buf);
             } else {
                 net_interrupt_handler();
             }
         }
     }
 
+    // only data already queued on the socket, see `UDP::try_read`
+    fn try_read(&self, mut buf: crate::mm::UserBuffer) -> Option<usize> {
+        pop_data(self.socket_index).map(|data| copy_data(&data, &mut buf))
+    }
+
     fn write(&self, buf: crate::mm::UserBuffer) -> usize {
         let lose_net_stack = LOSE_NET_STACK.0.exclusive_access();
 
@@ -105,6 +100,20 @@ impl File for TCP {
         NET_DEVICE.transmit(&tcp_packet.build_data());
         len
     }
+
+    fn poll(&self) -> PollEvents {
+        let mut events = PollEvents::OUT;
+        events.set(PollEvents::IN, has_data(self.socket_index));
+        events
+    }
+
+    fn register_waiter(&self, task: Arc<TaskControlBlock>) {
+        add_waiter(self.socket_index, task);
+    }
+
+    fn unregister_waiter(&self, task: &Arc<TaskControlBlock>) {
+        remove_waiter(self.socket_index, task);
+    }
 }
 
 impl Drop for TCP {
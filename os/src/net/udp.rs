@@ -1,8 +1,12 @@
 use super::LOSE_NET_STACK;
 use super::NET_DEVICE;
 use super::net_interrupt_handler;
-use super::socket::{add_socket, pop_data, remove_socket};
-use crate::fs::File;
+use super::socket::{
+    add_socket, add_waiter, copy_data, has_data, pop_data, remove_socket, remove_waiter,
+};
+use crate::fs::{File, PollEvents};
+use crate::task::TaskControlBlock;
+use alloc::sync::Arc;
 use alloc::vec;
 use lose_net_stack::IPv4;
 use lose_net_stack::MacAddress;
@@ -40,26 +44,19 @@ impl File for UDP {
     fn read(&self, mut buf: crate::mm::UserBuffer) -> usize {
         loop {
             if let Some(data) = pop_data(self.socket_index) {
-                let data_len = data.len();
-                let mut left = 0;
-                for i in 0..buf.buffers.len() {
-                    let buffer_i_len = buf.buffers[i].len().min(data_len - left);
-
-                    buf.buffers[i][..buffer_i_len]
-                        .copy_from_slice(&data[left..(left + buffer_i_len)]);
-
-                    left += buffer_i_len;
-                    if left == data_len {
-                        break;
-                    }
-                }
-                return left;
+                return copy_data(&data, &mut buf);
             } else {
                 net_interrupt_handler();
             }
         }
     }
 
+    // NOTICE: packets are only pulled from the device by blocking reads,
+    // a non-blocking read just takes what has been queued on the socket.
+    fn try_read(&self, mut buf: crate::mm::UserBuffer) -> Option<usize> {
+        pop_data(self.socket_index).map(|data| copy_data(&data, &mut buf))
+    }
+
     fn write(&self, buf: crate::mm::UserBuffer) -> usize {
         let lose_net_stack = LOSE_NET_STACK.0.exclusive_access();
 
@@ -86,6 +83,21 @@ impl File for UDP {
         NET_DEVICE.transmit(&udp_packet.build_data());
         len
     }
+
+    fn poll(&self) -> PollEvents {
+        // sending never blocks
+        let mut events = PollEvents::OUT;
+        events.set(PollEvents::IN, has_data(self.socket_index));
+        events
+    }
+
+    fn register_waiter(&self, task: Arc<TaskControlBlock>) {
+        add_waiter(self.socket_index, task);
+    }
+
+    fn unregister_waiter(&self, task: &Arc<TaskControlBlock>) {
+        remove_waiter(self.socket_index, task);
+    }
 }
 
 impl Drop for UDP {
@@ -3,6 +3,27 @@ pub fn set_timer(timer: usize) {
     sbi_rt::set_timer(timer as _);
 }
 
+/// use sbi call to start hart `hart_id` at `start_addr`, return false if it can not be started
+pub fn hart_start(hart_id: usize, start_addr: usize, opaque: usize) -> bool {
+    sbi_rt::hart_start(hart_id, start_addr, opaque).error == 0
+}
+
+/// use sbi call to send a supervisor software interrupt to the harts in `hart_mask`
+pub fn send_ipi(hart_mask: usize) {
+    sbi_rt::send_ipi(hart_mask, 0);
+}
+
+/// use sbi call to drop all tlb entries on the harts in `hart_mask`
+pub fn remote_sfence_vma_all(hart_mask: usize) {
+    sbi_rt::remote_sfence_vma(hart_mask, 0, 0, usize::MAX);
+}
+
+/// use sbi call to drop the tlb entries of `asid` in `[start_addr, start_addr + size)`
+/// on the harts in `hart_mask`
+pub fn remote_sfence_vma_asid(hart_mask: usize, start_addr: usize, size: usize, asid: usize) {
+    sbi_rt::remote_sfence_vma_asid(hart_mask, 0, start_addr, size, asid);
+}
+
 /// use sbi call to shutdown the kernel
 pub fn shutdown(failure: bool) -> ! {
     use sbi_rt::{NoReason, Shutdown, SystemFailure, system_reset};
@@ -1,12 +1,11 @@
-use crate::sync::{Mutex, UPIntrFreeCell};
+use crate::sync::{Mutex, SpinNoIrqLock};
 use crate::task::{
-    TaskContext, TaskControlBlock, block_current_and_run_next, block_current_task, current_task,
-    wakeup_task,
+    TaskContext, TaskControlBlock, block_current_task, current_task, schedule, wakeup_task,
 };
 use alloc::{collections::VecDeque, sync::Arc};
 
 pub struct Condvar {
-    pub inner: UPIntrFreeCell<CondvarInner>,
+    pub inner: SpinNoIrqLock<CondvarInner>,
 }
 
 pub struct CondvarInner {
@@ -17,7 +16,7 @@ impl Condvar {
     pub fn new() -> Self {
         Self {
             inner: unsafe {
-                UPIntrFreeCell::new(CondvarInner {
+                SpinNoIrqLock::new(CondvarInner {
                     wait_queue: VecDeque::new(),
                 })
             },
@@ -43,16 +42,15 @@ impl Condvar {
     pub fn wait_no_sched(&self) -> *mut TaskContext {
         self.inner.exclusive_session(|inner| {
             inner.wait_queue.push_back(current_task().unwrap());
-        });
-        block_current_task()
+            block_current_task()
+        })
     }
 
     pub fn wait_with_mutex(&self, mutex: Arc<dyn Mutex>) {
+        // queue up before unlocking so a signal on another hart is not missed
+        let task_cx_ptr = self.wait_no_sched();
         mutex.unlock();
-        self.inner.exclusive_session(|inner| {
-            inner.wait_queue.push_back(current_task().unwrap());
-        });
-        block_current_and_run_next();
+        schedule(task_cx_ptr);
         mutex.lock();
     }
 }
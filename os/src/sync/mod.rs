@@ -1,9 +1,11 @@
 mod condvar;
 mod mutex;
 mod semaphore;
-mod up;
+mod spin;
+mod wait_queue;
 
 pub use condvar::Condvar;
 pub use mutex::{Mutex, MutexBlocking, MutexSpin};
 pub use semaphore::Semaphore;
-pub use up::{UPIntrFreeCell, UPIntrRefMut};
+pub use spin::{SpinNoIrqGuard, SpinNoIrqLock, intr_free};
+pub use wait_queue::WaitQueue;
@@ -1,6 +1,6 @@
-use super::UPIntrFreeCell;
+use super::SpinNoIrqLock;
 use crate::task::TaskControlBlock;
-use crate::task::{block_current_and_run_next, suspend_current_and_run_next};
+use crate::task::{block_current_task, schedule, suspend_current_and_run_next};
 use crate::task::{current_task, wakeup_task};
 use alloc::{collections::VecDeque, sync::Arc};
 
@@ -10,13 +10,13 @@ pub trait Mutex: Sync + Send {
 }
 
 pub struct MutexSpin {
-    locked: UPIntrFreeCell<bool>,
+    locked: SpinNoIrqLock<bool>,
 }
 
 impl MutexSpin {
     pub fn new() -> Self {
         Self {
-            locked: unsafe { UPIntrFreeCell::new(false) },
+            locked: unsafe { SpinNoIrqLock::new(false) },
         }
     }
 }
@@ -43,7 +43,7 @@ impl Mutex for MutexSpin {
 }
 
 pub struct MutexBlocking {
-    inner: UPIntrFreeCell<MutexBlockingInner>,
+    inner: SpinNoIrqLock<MutexBlockingInner>,
 }
 
 pub struct MutexBlockingInner {
@@ -55,7 +55,7 @@ impl MutexBlocking {
     pub fn new() -> Self {
         Self {
             inner: unsafe {
-                UPIntrFreeCell::new(MutexBlockingInner {
+                SpinNoIrqLock::new(MutexBlockingInner {
                     locked: false,
                     wait_queue: VecDeque::new(),
                 })
@@ -69,8 +69,9 @@ impl Mutex for MutexBlocking {
         let mut mutex_inner = self.inner.exclusive_access();
         if mutex_inner.locked {
             mutex_inner.wait_queue.push_back(current_task().unwrap());
+            let task_cx_ptr = block_current_task();
             drop(mutex_inner);
-            block_current_and_run_next();
+            schedule(task_cx_ptr);
         } else {
             mutex_inner.locked = true;
         }
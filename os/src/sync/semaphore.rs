@@ -1,9 +1,9 @@
-use crate::sync::UPIntrFreeCell;
-use crate::task::{TaskControlBlock, block_current_and_run_next, current_task, wakeup_task};
+use crate::sync::SpinNoIrqLock;
+use crate::task::{TaskControlBlock, block_current_task, current_task, schedule, wakeup_task};
 use alloc::{collections::VecDeque, sync::Arc};
 
 pub struct Semaphore {
-    pub inner: UPIntrFreeCell<SemaphoreInner>,
+    pub inner: SpinNoIrqLock<SemaphoreInner>,
 }
 
 pub struct SemaphoreInner {
@@ -15,7 +15,7 @@ impl Semaphore {
     pub fn new(res_count: usize) -> Self {
         Self {
             inner: unsafe {
-                UPIntrFreeCell::new(SemaphoreInner {
+                SpinNoIrqLock::new(SemaphoreInner {
                     count: res_count as isize,
                     wait_queue: VecDeque::new(),
                 })
@@ -38,8 +38,9 @@ impl Semaphore {
         inner.count -= 1;
         if inner.count < 0 {
             inner.wait_queue.push_back(current_task().unwrap());
+            let task_cx_ptr = block_current_task();
             drop(inner);
-            block_current_and_run_next();
+            schedule(task_cx_ptr);
         }
     }
 }
@@ -0,0 +1,165 @@
+use crate::hart::{PerHart, hart_id};
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::*;
+use riscv::register::sstatus;
+
+pub struct IntrMaskingInfo {
+    nested_level: usize,
+    sie_before_masking: bool,
+}
+
+lazy_static! {
+    static ref INTR_MASKING_INFO: PerHart<IntrMaskingInfo> =
+        PerHart::new(|_| IntrMaskingInfo::new());
+}
+
+impl IntrMaskingInfo {
+    pub fn new() -> Self {
+        Self {
+            nested_level: 0,
+            sie_before_masking: false,
+        }
+    }
+
+    pub fn enter(&mut self) {
+        let sie = sstatus::read().sie();
+        unsafe {
+            sstatus::clear_sie();
+        }
+        if self.nested_level == 0 {
+            self.sie_before_masking = sie;
+        }
+        self.nested_level += 1;
+    }
+
+    pub fn exit(&mut self) {
+        self.nested_level -= 1;
+        if self.nested_level == 0 && self.sie_before_masking {
+            unsafe {
+                sstatus::set_sie();
+            }
+        }
+    }
+}
+
+/// Run `f` with interrupts of this hart masked, e.g. to block the current task
+/// without missing a wakeup from an interrupt handler.
+pub fn intr_free<F, V>(f: F) -> V
+where
+    F: FnOnce() -> V,
+{
+    INTR_MASKING_INFO.get_mut().enter();
+    let ret = f();
+    INTR_MASKING_INFO.get_mut().exit();
+    ret
+}
+
+const NO_OWNER: usize = usize::MAX;
+
+/// A spinlock that masks interrupts of the hart holding it,
+/// so an interrupt handler never spins on a lock its own hart holds.
+///
+/// Locks are taken in one global order so two harts never wait for each other:
+/// the inner of a process before the inner of any of its threads.
+pub struct SpinNoIrqLock<T> {
+    /// hart holding the lock
+    owner: AtomicUsize,
+    inner: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for SpinNoIrqLock<T> {}
+
+pub struct SpinNoIrqGuard<'a, T> {
+    lock: &'a SpinNoIrqLock<T>,
+}
+
+impl<T> SpinNoIrqLock<T> {
+    pub unsafe fn new(value: T) -> Self {
+        Self {
+            owner: AtomicUsize::new(NO_OWNER),
+            inner: UnsafeCell::new(value),
+        }
+    }
+
+    /// Return `false` at once if this hart holds the lock, spin while another one does.
+    fn lock(&self) -> bool {
+        let hart = hart_id();
+        loop {
+            match self.owner.compare_exchange_weak(
+                NO_OWNER,
+                hart,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(owner) if owner == hart => return false,
+                Err(_) => spin_loop(),
+            }
+        }
+    }
+
+    /// Panic if this hart holds the lock already.
+    pub fn exclusive_access(&self) -> SpinNoIrqGuard<'_, T> {
+        INTR_MASKING_INFO.get_mut().enter();
+        let locked = self.lock();
+        assert!(locked, "lock already held by hart {}", hart_id());
+        SpinNoIrqGuard { lock: self }
+    }
+
+    /// Return `None` instead of panicking if this hart holds the lock already.
+    pub fn try_exclusive_access(&self) -> Option<SpinNoIrqGuard<'_, T>> {
+        INTR_MASKING_INFO.get_mut().enter();
+        if self.lock() {
+            Some(SpinNoIrqGuard { lock: self })
+        } else {
+            INTR_MASKING_INFO.get_mut().exit();
+            None
+        }
+    }
+
+    /// Return `None` at once if any hart holds the lock, so a hart holding
+    /// another lock of the same kind cannot deadlock with its holder.
+    pub fn try_exclusive_access_now(&self) -> Option<SpinNoIrqGuard<'_, T>> {
+        INTR_MASKING_INFO.get_mut().enter();
+        if self
+            .owner
+            .compare_exchange(NO_OWNER, hart_id(), Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            Some(SpinNoIrqGuard { lock: self })
+        } else {
+            INTR_MASKING_INFO.get_mut().exit();
+            None
+        }
+    }
+
+    pub fn exclusive_session<F, V>(&self, f: F) -> V
+    where
+        F: FnOnce(&mut T) -> V,
+    {
+        let mut inner = self.exclusive_access();
+        f(inner.deref_mut())
+    }
+}
+
+impl<'a, T> Drop for SpinNoIrqGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.owner.store(NO_OWNER, Ordering::Release);
+        INTR_MASKING_INFO.get_mut().exit();
+    }
+}
+
+impl<'a, T> Deref for SpinNoIrqGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.inner.get() }
+    }
+}
+impl<'a, T> DerefMut for SpinNoIrqGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.inner.get() }
+    }
+}
@@ -0,0 +1,35 @@
+use crate::sync::SpinNoIrqLock;
+use crate::task::{TaskControlBlock, wakeup_task};
+use alloc::{collections::VecDeque, sync::Arc};
+
+/// Tasks waiting for some state to change, e.g. a file becoming ready.
+/// A task may sit in several queues at once, only the first wakeup counts.
+pub struct WaitQueue {
+    inner: SpinNoIrqLock<VecDeque<Arc<TaskControlBlock>>>,
+}
+
+impl WaitQueue {
+    pub fn new() -> Self {
+        Self {
+            inner: unsafe { SpinNoIrqLock::new(VecDeque::new()) },
+        }
+    }
+
+    pub fn add(&self, task: Arc<TaskControlBlock>) {
+        self.inner.exclusive_access().push_back(task);
+    }
+
+    pub fn remove(&self, task: &Arc<TaskControlBlock>) {
+        self.inner
+            .exclusive_access()
+            .retain(|waiter| !Arc::ptr_eq(waiter, task));
+    }
+
+    pub fn wake_all(&self) {
+        self.inner.exclusive_session(|queue| {
+            for task in queue.drain(..) {
+                wakeup_task(task);
+            }
+        });
+    }
+}
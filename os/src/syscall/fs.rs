@@ -1,99 +1,390 @@
-use crate::fs::{OpenFlags, make_pipe, open_file};
-use crate::mm::{UserBuffer, translated_byte_buffer, translated_refmut, translated_str};
-use crate::task::{current_process, current_user_token};
+use crate::config::MAX_FD;
+use crate::fs::{
+    FdFlags, File, FileDescriptor, OpenFlags, PollEvents, dir_exists, make_dir, make_pipe,
+    open_file, resolve_path,
+};
+use crate::mm::{
+    UserBuffer, copy_from_user, copy_to_user, translated_byte_buffer, translated_byte_buffer_mut,
+    translated_object_mut, translated_str,
+};
+use crate::sync::intr_free;
+use crate::task::{
+    block_current_task, cancel_block_current, current_process, current_task, current_user_token,
+    prepare_block_current, schedule,
+};
+use crate::timer::{add_timer, get_time_ms, remove_timer};
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 
-pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
-    let token = current_user_token();
+use super::{SysError, SysResult};
+
+/// Look up an open file of the current process that supports `access`.
+fn get_file(fd: usize, access: fn(&dyn File) -> bool) -> Result<FileDescriptor, SysError> {
     let process = current_process();
     let inner = process.inner_exclusive_access();
-    if fd >= inner.fd_table.len() {
-        return -1;
+    match inner.fd_table.get(fd) {
+        Some(Some(fd)) if access(fd.file.as_ref()) => Ok(fd.clone()),
+        _ => Err(SysError::EBADF),
     }
-    if let Some(file) = &inner.fd_table[fd] {
-        if !file.writable() {
-            return -1;
-        }
-        let file = file.clone();
-        // release current task TCB manually to avoid multi-borrow
-        drop(inner);
-        file.write(UserBuffer::new(translated_byte_buffer(token, buf, len))) as isize
+}
+
+fn read_file(fd: &FileDescriptor, buf: UserBuffer) -> SysResult {
+    if fd.flags.contains(FdFlags::NONBLOCK) {
+        fd.file
+            .try_read(buf)
+            .map(|len| len as isize)
+            .ok_or(SysError::EAGAIN)
     } else {
-        -1
+        Ok(fd.file.read(buf) as isize)
     }
 }
 
-pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
+pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> SysResult {
     let token = current_user_token();
-    let process = current_process();
-    let inner = process.inner_exclusive_access();
-    if fd >= inner.fd_table.len() {
-        return -1;
+    let fd = get_file(fd, |file| file.writable())?;
+    let buffers = translated_byte_buffer(token, buf, len).ok_or(SysError::EFAULT)?;
+    Ok(fd.file.write(UserBuffer::new(buffers)) as isize)
+}
+
+pub fn sys_read(fd: usize, buf: *mut u8, len: usize) -> SysResult {
+    let token = current_user_token();
+    let fd = get_file(fd, |file| file.readable())?;
+    let buffers = translated_byte_buffer_mut(token, buf, len).ok_or(SysError::EFAULT)?;
+    read_file(&fd, UserBuffer::new(buffers))
+}
+
+const IOV_MAX: usize = 1024;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct IoVec {
+    base: *mut u8,
+    len: usize,
+}
+
+/// Gather the buffers of an iovec array into one list of slices.
+/// `writable` asks for buffers the kernel is going to write into.
+fn translated_iovec(
+    token: usize,
+    iov: *const IoVec,
+    iovcnt: usize,
+    writable: bool,
+) -> Result<Vec<&'static mut [u8]>, SysError> {
+    if iovcnt > IOV_MAX {
+        return Err(SysError::EINVAL);
     }
-    if let Some(file) = &inner.fd_table[fd] {
-        let file = file.clone();
-        if !file.readable() {
-            return -1;
-        }
-        // release current task TCB manually to avoid multi-borrow
-        drop(inner);
-        file.read(UserBuffer::new(translated_byte_buffer(token, buf, len))) as isize
-    } else {
-        -1
+    let mut buffers = Vec::new();
+    let mut total_len = 0usize;
+    for i in 0..iovcnt {
+        let iovec = copy_from_user(token, iov.wrapping_add(i)).ok_or(SysError::EFAULT)?;
+        total_len = total_len
+            .checked_add(iovec.len)
+            .filter(|&len| len <= isize::MAX as usize)
+            .ok_or(SysError::EINVAL)?;
+        let slices = if writable {
+            translated_byte_buffer_mut(token, iovec.base, iovec.len)
+        } else {
+            translated_byte_buffer(token, iovec.base, iovec.len)
+        };
+        buffers.extend(slices.ok_or(SysError::EFAULT)?);
     }
+    Ok(buffers)
 }
 
-pub fn sys_open(path: *const u8, flags: u32) -> isize {
+pub fn sys_writev(fd: usize, iov: *const IoVec, iovcnt: usize) -> SysResult {
+    let token = current_user_token();
+    let fd = get_file(fd, |file| file.writable())?;
+    let buffers = translated_iovec(token, iov, iovcnt, false)?;
+    Ok(fd.file.write(UserBuffer::new(buffers)) as isize)
+}
+
+pub fn sys_readv(fd: usize, iov: *const IoVec, iovcnt: usize) -> SysResult {
+    let token = current_user_token();
+    let fd = get_file(fd, |file| file.readable())?;
+    let buffers = translated_iovec(token, iov, iovcnt, true)?;
+    read_file(&fd, UserBuffer::new(buffers))
+}
+
+pub fn sys_open(path: *const u8, flags: u32) -> SysResult {
     let process = current_process();
     let token = current_user_token();
-    let path = translated_str(token, path);
-    if let Some(inode) = open_file(path.as_str(), OpenFlags::from_bits(flags).unwrap()) {
+    let path = translated_str(token, path).ok_or(SysError::EFAULT)?;
+    let path = resolve_path(&process.inner_exclusive_access().cwd, &path);
+    let flags = OpenFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+    if let Some(inode) = open_file(path.as_str(), flags) {
         let mut inner = process.inner_exclusive_access();
         let fd = inner.alloc_fd();
-        inner.fd_table[fd] = Some(inode);
-        fd as isize
+        inner.fd_table[fd] = Some(FileDescriptor::new(inode, flags.fd_flags()));
+        Ok(fd as isize)
     } else {
-        -1
+        Err(SysError::ENOENT)
     }
 }
 
-pub fn sys_close(fd: usize) -> isize {
+pub fn sys_close(fd: usize) -> SysResult {
     let process = current_process();
     let mut inner = process.inner_exclusive_access();
     if fd >= inner.fd_table.len() {
-        return -1;
+        return Err(SysError::EBADF);
     }
     if inner.fd_table[fd].is_none() {
-        return -1;
+        return Err(SysError::EBADF);
     }
     inner.fd_table[fd].take();
-    0
+    Ok(0)
 }
 
-pub fn sys_pipe(pipe: *mut usize) -> isize {
+pub fn sys_pipe(pipe: *mut usize) -> SysResult {
     let process = current_process();
     let token = current_user_token();
+    // translate before taking the PCB, a copy-on-write fault needs it
+    let mut fds_buffer =
+        translated_object_mut(token, pipe as *mut [usize; 2]).ok_or(SysError::EFAULT)?;
     let mut inner = process.inner_exclusive_access();
     let (pipe_read, pipe_write) = make_pipe();
     let read_fd = inner.alloc_fd();
-    inner.fd_table[read_fd] = Some(pipe_read);
+    inner.fd_table[read_fd] = Some(FileDescriptor::new(pipe_read, FdFlags::empty()));
     let write_fd = inner.alloc_fd();
-    inner.fd_table[write_fd] = Some(pipe_write);
-    *translated_refmut(token, pipe) = read_fd;
-    *translated_refmut(token, unsafe { pipe.add(1) }) = write_fd;
-    0
+    inner.fd_table[write_fd] = Some(FileDescriptor::new(pipe_write, FdFlags::empty()));
+    fds_buffer.write_value(&[read_fd, write_fd]);
+    Ok(0)
 }
 
-pub fn sys_dup(fd: usize) -> isize {
+pub fn sys_dup(fd: usize) -> SysResult {
     let process = current_process();
     let mut inner = process.inner_exclusive_access();
     if fd >= inner.fd_table.len() {
-        return -1;
+        return Err(SysError::EBADF);
     }
     if inner.fd_table[fd].is_none() {
-        return -1;
+        return Err(SysError::EBADF);
     }
     let new_fd = inner.alloc_fd();
-    inner.fd_table[new_fd] = Some(Arc::clone(inner.fd_table[fd].as_ref().unwrap()));
-    new_fd as isize
+    // the duplicate keeps O_NONBLOCK but not close-on-exec
+    let old = inner.fd_table[fd].as_ref().unwrap();
+    let dup = FileDescriptor::new(Arc::clone(&old.file), old.flags - FdFlags::CLOEXEC);
+    inner.fd_table[new_fd] = Some(dup);
+    Ok(new_fd as isize)
+}
+
+/// Make `new_fd` refer to the file of `old_fd`, closing whatever `new_fd` held.
+/// Only O_CLOEXEC is accepted in `flags`.
+pub fn sys_dup3(old_fd: usize, new_fd: usize, flags: u32) -> SysResult {
+    let flags = OpenFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+    if old_fd == new_fd || !(flags - OpenFlags::CLOEXEC).is_empty() {
+        return Err(SysError::EINVAL);
+    }
+    if new_fd >= MAX_FD {
+        return Err(SysError::EBADF);
+    }
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let old = inner
+        .fd_table
+        .get(old_fd)
+        .and_then(Option::as_ref)
+        .ok_or(SysError::EBADF)?;
+    let mut dup_flags = old.flags - FdFlags::CLOEXEC;
+    dup_flags.set(FdFlags::CLOEXEC, flags.contains(OpenFlags::CLOEXEC));
+    let dup = FileDescriptor::new(Arc::clone(&old.file), dup_flags);
+    if new_fd >= inner.fd_table.len() {
+        inner.fd_table.resize(new_fd + 1, None);
+    }
+    inner.fd_table[new_fd] = Some(dup);
+    Ok(new_fd as isize)
+}
+
+pub fn sys_mkdir(path: *const u8) -> SysResult {
+    let process = current_process();
+    let token = current_user_token();
+    let path = translated_str(token, path).ok_or(SysError::EFAULT)?;
+    let path = resolve_path(&process.inner_exclusive_access().cwd, &path);
+    if make_dir(path.as_str()).is_some() {
+        Ok(0)
+    } else if dir_exists(path.as_str()) {
+        Err(SysError::EEXIST)
+    } else {
+        Err(SysError::ENOENT)
+    }
+}
+
+pub fn sys_chdir(path: *const u8) -> SysResult {
+    let process = current_process();
+    let token = current_user_token();
+    let path = translated_str(token, path).ok_or(SysError::EFAULT)?;
+    if path.is_empty() {
+        return Err(SysError::ENOENT);
+    }
+    let path = resolve_path(&process.inner_exclusive_access().cwd, &path);
+    if dir_exists(path.as_str()) {
+        process.inner_exclusive_access().cwd = path;
+        Ok(0)
+    } else {
+        Err(SysError::ENOENT)
+    }
+}
+
+/// Copy the current working directory with an end `\0` into `buf`.
+/// Return the length of the path with its `\0`, or ERANGE if `buf` is too small.
+pub fn sys_getcwd(buf: *mut u8, len: usize) -> SysResult {
+    let process = current_process();
+    let token = current_user_token();
+    let cwd = process.inner_exclusive_access().cwd.clone();
+    if cwd.len() + 1 > len {
+        return Err(SysError::ERANGE);
+    }
+    let buffers = translated_byte_buffer_mut(token, buf, cwd.len() + 1).ok_or(SysError::EFAULT)?;
+    let user_buf = UserBuffer::new(buffers);
+    for (dst, src) in user_buf.into_iter().zip(cwd.bytes().chain(Some(0))) {
+        unsafe {
+            *dst = src;
+        }
+    }
+    Ok(cwd.len() as isize + 1)
+}
+
+const F_GETFD: usize = 1;
+const F_SETFD: usize = 2;
+const F_GETFL: usize = 3;
+const F_SETFL: usize = 4;
+const FD_CLOEXEC: usize = 1;
+
+/// Get or set the flags of a file descriptor.
+/// F_GETFD/F_SETFD handle FD_CLOEXEC, F_GETFL/F_SETFL the access mode and O_NONBLOCK.
+pub fn sys_fcntl(fd: usize, cmd: usize, arg: usize) -> SysResult {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let fd = inner
+        .fd_table
+        .get_mut(fd)
+        .and_then(Option::as_mut)
+        .ok_or(SysError::EBADF)?;
+    match cmd {
+        F_GETFD => Ok(if fd.flags.contains(FdFlags::CLOEXEC) {
+            FD_CLOEXEC as isize
+        } else {
+            0
+        }),
+        F_SETFD => {
+            fd.flags.set(FdFlags::CLOEXEC, arg & FD_CLOEXEC != 0);
+            Ok(0)
+        }
+        F_GETFL => {
+            let mut flags = match (fd.file.readable(), fd.file.writable()) {
+                (true, true) => OpenFlags::RDWR,
+                (false, true) => OpenFlags::WRONLY,
+                _ => OpenFlags::RDONLY,
+            };
+            flags.set(OpenFlags::NONBLOCK, fd.flags.contains(FdFlags::NONBLOCK));
+            Ok(flags.bits() as isize)
+        }
+        F_SETFL => {
+            let flags = OpenFlags::from_bits_truncate(arg as u32);
+            fd.flags
+                .set(FdFlags::NONBLOCK, flags.contains(OpenFlags::NONBLOCK));
+            Ok(0)
+        }
+        _ => Err(SysError::EINVAL),
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PollFd {
+    fd: i32,
+    events: i16,
+    revents: i16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct TimeSpec {
+    sec: usize,
+    nsec: usize,
+}
+
+/// Wait until one of `fds` is ready or `timeout` expires, a null or overlong `timeout`
+/// waits forever.
+/// Return the number of entries with non-zero `revents`. Signal masks are not supported.
+pub fn sys_ppoll(fds: *mut PollFd, nfds: usize, timeout: *const TimeSpec) -> SysResult {
+    let token = current_user_token();
+    let mut pollfds = (0..nfds)
+        .map(|i| copy_from_user(token, fds.wrapping_add(i)))
+        .collect::<Option<Vec<PollFd>>>()
+        .ok_or(SysError::EFAULT)?;
+    let expire_ms = if timeout.is_null() {
+        None
+    } else {
+        let timeout = copy_from_user(token, timeout).ok_or(SysError::EFAULT)?;
+        if timeout.nsec >= 1_000_000_000 {
+            return Err(SysError::EINVAL);
+        }
+        // a timeout too long to count is infinite
+        timeout
+            .sec
+            .checked_mul(1000)
+            .and_then(|ms| ms.checked_add(timeout.nsec / 1_000_000))
+            .and_then(|ms| ms.checked_add(get_time_ms()))
+    };
+    let files: Vec<_> = {
+        let process = current_process();
+        let inner = process.inner_exclusive_access();
+        pollfds
+            .iter()
+            .map(|pollfd| {
+                let fd = usize::try_from(pollfd.fd).ok()?;
+                inner.fd_table.get(fd)?.as_ref().map(|fd| fd.file.clone())
+            })
+            .collect()
+    };
+    let task = current_task().unwrap();
+    loop {
+        // register before checking so that no wakeup in between gets lost,
+        // interrupts stay masked until the task is blocked
+        let (count, blocked, task_cx_ptr) = intr_free(|| {
+            prepare_block_current();
+            for file in files.iter().flatten() {
+                file.register_waiter(task.clone());
+            }
+            let mut count = 0;
+            for (pollfd, file) in pollfds.iter_mut().zip(files.iter()) {
+                let revents = match file {
+                    Some(file) => {
+                        let events = PollEvents::from_bits_truncate(pollfd.events as u16);
+                        file.poll() & (events | PollEvents::ERR | PollEvents::HUP)
+                    }
+                    // negative fds are ignored
+                    None if pollfd.fd < 0 => PollEvents::empty(),
+                    None => PollEvents::NVAL,
+                };
+                pollfd.revents = revents.bits() as i16;
+                if !revents.is_empty() {
+                    count += 1;
+                }
+            }
+            let timed_out = expire_ms.is_some_and(|expire_ms| get_time_ms() >= expire_ms);
+            if count > 0 || timed_out {
+                return (count, false, cancel_block_current());
+            }
+            if let Some(expire_ms) = expire_ms {
+                add_timer(expire_ms, task.clone());
+            }
+            (count, true, Some(block_current_task()))
+        });
+        if let Some(task_cx_ptr) = task_cx_ptr {
+            schedule(task_cx_ptr);
+        }
+        if blocked {
+            remove_timer(&task);
+        }
+        for file in files.iter().flatten() {
+            file.unregister_waiter(&task);
+        }
+        if !blocked {
+            for (i, pollfd) in pollfds.iter().enumerate() {
+                copy_to_user(token, fds.wrapping_add(i), pollfd).ok_or(SysError::EFAULT)?;
+            }
+            return Ok(count);
+        }
+    }
 }
@@ -2,45 +2,51 @@ use crate::drivers::GPU_DEVICE;
 use crate::mm::{MapArea, MapPermission, MapType, PhysAddr, VirtAddr};
 use crate::task::current_process;
 
-const FB_VADDR: usize = 0x10000000;
+use super::{SysError, SysResult};
 
-pub fn sys_framebuffer() -> isize {
+pub fn sys_framebuffer() -> SysResult {
     let fb = GPU_DEVICE.get_framebuffer();
     let len = fb.len();
     // println!("[kernel] FrameBuffer: addr 0x{:X}, len {}", fb.as_ptr() as usize , len);
     let fb_start_pa = PhysAddr::from(fb.as_ptr() as usize);
     assert!(fb_start_pa.aligned());
     let fb_start_ppn = fb_start_pa.floor();
-    let fb_start_vpn = VirtAddr::from(FB_VADDR).floor();
-    let pn_offset = fb_start_ppn.0 as isize - fb_start_vpn.0 as isize;
 
     let current_process = current_process();
     let mut inner = current_process.inner_exclusive_access();
+    // put it in the mmap region, clear of the program, its heap and its stacks
+    let page_count = VirtAddr::from(len).ceil().0;
+    let fb_start_vpn = inner
+        .memory_set
+        .find_mmap_range(page_count)
+        .ok_or(SysError::ENOMEM)?;
+    let fb_vaddr = VirtAddr::from(fb_start_vpn).0;
+    let pn_offset = fb_start_ppn.0 as isize - fb_start_vpn.0 as isize;
     inner.memory_set.push(
         MapArea::new(
-            (FB_VADDR as usize).into(),
-            (FB_VADDR + len as usize).into(),
+            fb_vaddr.into(),
+            (fb_vaddr + len).into(),
             MapType::Linear(pn_offset),
             MapPermission::R | MapPermission::W | MapPermission::U,
         ),
         None,
     );
-    FB_VADDR as isize
+    Ok(fb_vaddr as isize)
 }
 
-pub fn sys_framebuffer_flush() -> isize {
+pub fn sys_framebuffer_flush() -> SysResult {
     GPU_DEVICE.flush();
-    0
+    Ok(0)
 }
 
-pub fn sys_cursor_update(pos_x: u32, pos_y: u32) -> isize {
+pub fn sys_cursor_update(pos_x: u32, pos_y: u32) -> SysResult {
     GPU_DEVICE.update_cursor(pos_x, pos_y);
-    0
+    Ok(0)
 }
 
-pub fn sys_cursor_pos_get() -> isize {
+pub fn sys_cursor_pos_get() -> SysResult {
     let cursor_pos = GPU_DEVICE.get_cursor_pos();
     // 将两个 u32 合并为 u64
     let pos = (cursor_pos.0 as u64) << 32 | cursor_pos.1 as u64;
-    pos as isize
+    Ok(pos as isize)
 }
@@ -0,0 +1,259 @@
+use crate::config::{PAGE_SIZE, USER_HEAP_LIMIT};
+use crate::mm::{
+    FileMapping, HeapStats, MapArea, MapPermission, MapType, VirtAddr, copy_to_user, heap_stats,
+    shm_create, shm_find, shm_get, shm_remove,
+};
+use crate::task::{current_process, current_user_token};
+use bitflags::*;
+
+use super::{SysError, SysResult};
+
+bitflags! {
+    struct MmapProt: usize {
+        const READ = 1 << 0;
+        const WRITE = 1 << 1;
+        const EXEC = 1 << 2;
+    }
+}
+
+bitflags! {
+    struct MmapFlags: usize {
+        const SHARED = 1 << 0;
+        const PRIVATE = 1 << 1;
+        const FIXED = 1 << 4;
+        const ANONYMOUS = 1 << 5;
+    }
+}
+
+/// No mapping is writable without being readable on riscv.
+fn map_permission(prot: MmapProt) -> MapPermission {
+    let mut map_perm = MapPermission::U;
+    if prot.intersects(MmapProt::READ | MmapProt::WRITE) {
+        map_perm |= MapPermission::R;
+    }
+    if prot.contains(MmapProt::WRITE) {
+        map_perm |= MapPermission::W;
+    }
+    if prot.contains(MmapProt::EXEC) {
+        map_perm |= MapPermission::X;
+    }
+    map_perm
+}
+
+/// Map `len` bytes of anonymous memory or of the file `fd` from `offset`.
+/// `addr` is only a hint and is ignored, MAP_FIXED is not supported.
+/// Anonymous shared mappings behave like private ones.
+pub fn sys_mmap(
+    _addr: usize,
+    len: usize,
+    prot: usize,
+    flags: usize,
+    fd: usize,
+    offset: usize,
+) -> SysResult {
+    let prot = MmapProt::from_bits(prot).ok_or(SysError::EINVAL)?;
+    let flags = MmapFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+    let shared = flags.contains(MmapFlags::SHARED);
+    if len == 0
+        || offset % PAGE_SIZE != 0
+        || shared == flags.contains(MmapFlags::PRIVATE)
+        || flags.contains(MmapFlags::FIXED)
+    {
+        return Err(SysError::EINVAL);
+    }
+    let page_count = len.div_ceil(PAGE_SIZE);
+    let map_perm = map_permission(prot);
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let file = if flags.contains(MmapFlags::ANONYMOUS) {
+        None
+    } else {
+        let file = &inner
+            .fd_table
+            .get(fd)
+            .and_then(Option::as_ref)
+            .ok_or(SysError::EBADF)?
+            .file;
+        let inode = file.inode().ok_or(SysError::ENODEV)?;
+        if !file.readable() || (shared && prot.contains(MmapProt::WRITE) && !file.writable()) {
+            return Err(SysError::EACCES);
+        }
+        Some(FileMapping {
+            inode,
+            offset,
+            shared,
+            writable: file.writable(),
+        })
+    };
+    let start_vpn = inner
+        .memory_set
+        .find_mmap_range(page_count)
+        .ok_or(SysError::ENOMEM)?;
+    let start_va: VirtAddr = start_vpn.into();
+    let end_va: VirtAddr = (start_va.0 + page_count * PAGE_SIZE).into();
+    let mut map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
+    // anonymous memory is only allocated when touched
+    map_area = match file {
+        Some(file) => map_area.with_file(file),
+        None => map_area.lazy(),
+    };
+    if !inner.memory_set.push_mmap_area(map_area) {
+        return Err(SysError::ENOMEM);
+    }
+    Ok(start_va.0 as isize)
+}
+
+const IPC_PRIVATE: usize = 0;
+const IPC_RMID: usize = 0;
+
+bitflags! {
+    struct ShmFlags: usize {
+        const IPC_CREAT = 0o1000;
+        const IPC_EXCL = 0o2000;
+        const SHM_RDONLY = 0o10000;
+    }
+}
+
+/// Return the id of the shared memory segment named `key` of at least `size` bytes,
+/// creating it with IPC_CREAT. `IPC_PRIVATE` always creates a new segment.
+/// Permission bits in `flags` are ignored.
+pub fn sys_shmget(key: usize, size: usize, flags: usize) -> SysResult {
+    let flags = ShmFlags::from_bits_truncate(flags);
+    let pages = size.div_ceil(PAGE_SIZE);
+    if let Some((id, segment)) = shm_find(key) {
+        if flags.contains(ShmFlags::IPC_CREAT | ShmFlags::IPC_EXCL) {
+            return Err(SysError::EEXIST);
+        }
+        if pages > segment.frames.len() {
+            return Err(SysError::EINVAL);
+        }
+        return Ok(id as isize);
+    }
+    if key != IPC_PRIVATE && !flags.contains(ShmFlags::IPC_CREAT) {
+        return Err(SysError::ENOENT);
+    }
+    if pages == 0 {
+        return Err(SysError::EINVAL);
+    }
+    let id = shm_create(key, pages).ok_or(SysError::ENOMEM)?;
+    Ok(id as isize)
+}
+
+/// Map the whole segment `id` and return its address, `addr` is only a hint and is ignored.
+pub fn sys_shmat(id: usize, _addr: usize, flags: usize) -> SysResult {
+    let flags = ShmFlags::from_bits_truncate(flags);
+    let segment = shm_get(id).ok_or(SysError::EINVAL)?;
+    let page_count = segment.frames.len();
+    let mut map_perm = MapPermission::U | MapPermission::R;
+    if !flags.contains(ShmFlags::SHM_RDONLY) {
+        map_perm |= MapPermission::W;
+    }
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let start_vpn = inner
+        .memory_set
+        .find_mmap_range(page_count)
+        .ok_or(SysError::ENOMEM)?;
+    let start_va: VirtAddr = start_vpn.into();
+    let end_va: VirtAddr = (start_va.0 + page_count * PAGE_SIZE).into();
+    let map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm).with_shm(segment);
+    if !inner.memory_set.push_mmap_area(map_area) {
+        return Err(SysError::ENOMEM);
+    }
+    Ok(start_va.0 as isize)
+}
+
+/// Unmap the segment attached at `addr`.
+pub fn sys_shmdt(addr: usize) -> SysResult {
+    if addr % PAGE_SIZE != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    if !inner.memory_set.shm_detach(VirtAddr::from(addr).floor()) {
+        return Err(SysError::EINVAL);
+    }
+    Ok(0)
+}
+
+/// Only `IPC_RMID` is supported, it removes the segment once it is no longer attached.
+pub fn sys_shmctl(id: usize, cmd: usize, _buf: usize) -> SysResult {
+    if cmd != IPC_RMID || !shm_remove(id) {
+        return Err(SysError::EINVAL);
+    }
+    Ok(0)
+}
+
+/// Unmap the pages of `[addr, addr + len)` mapped by `mmap`, others are left alone.
+pub fn sys_munmap(addr: usize, len: usize) -> SysResult {
+    if addr % PAGE_SIZE != 0 || len == 0 {
+        return Err(SysError::EINVAL);
+    }
+    let end = addr.checked_add(len).ok_or(SysError::EINVAL)?;
+    let process = current_process();
+    let writeback = process
+        .inner_exclusive_access()
+        .memory_set
+        .munmap(VirtAddr::from(addr).floor(), VirtAddr::from(end).ceil());
+    // writing the file may block, the process is no longer locked
+    for page in writeback {
+        page.write();
+    }
+    Ok(0)
+}
+
+/// Change the protection of the pages of `[addr, addr + len)`, which must all be mapped
+/// in user space. Guard pages can not be changed.
+pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> SysResult {
+    let prot = MmapProt::from_bits(prot).ok_or(SysError::EINVAL)?;
+    if addr % PAGE_SIZE != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let end = addr.checked_add(len).ok_or(SysError::EINVAL)?;
+    let (start, end) = (VirtAddr::from(addr).floor(), VirtAddr::from(end).ceil());
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    if !inner.memory_set.is_user_range(start, end) {
+        return Err(SysError::ENOMEM);
+    }
+    if prot.contains(MmapProt::WRITE) && inner.memory_set.maps_readonly_file(start, end) {
+        return Err(SysError::EACCES);
+    }
+    if !inner.memory_set.mprotect(start, end, map_permission(prot)) {
+        return Err(SysError::ENOMEM);
+    }
+    Ok(0)
+}
+
+/// Move the program break to `addr` and return the new break.
+/// The current break is returned when `addr` is 0 or the break can not be moved there.
+pub fn sys_brk(addr: usize) -> SysResult {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let heap_bottom = inner.heap_bottom;
+    let old_brk = inner.program_brk;
+    if addr < heap_bottom || addr > heap_bottom + USER_HEAP_LIMIT {
+        return Ok(old_brk as isize);
+    }
+    let (bottom, old_end, new_end) = (
+        VirtAddr::from(heap_bottom),
+        VirtAddr::from(old_brk),
+        VirtAddr::from(addr),
+    );
+    let moved = if addr < old_brk {
+        inner.memory_set.shrink_to(bottom, old_end, new_end)
+    } else {
+        inner.memory_set.append_to(bottom, old_end, new_end)
+    };
+    if moved {
+        inner.program_brk = addr;
+    }
+    Ok(inner.program_brk as isize)
+}
+
+/// Copy the usage of the kernel heap to `stats`.
+pub fn sys_heap_stats(stats: *mut HeapStats) -> SysResult {
+    let heap_stats = heap_stats();
+    copy_to_user(current_user_token(), stats, &heap_stats).ok_or(SysError::EFAULT)?;
+    Ok(0)
+}
@@ -1,35 +1,45 @@
-use crate::fs::{OpenFlags, open_file};
-use crate::mm::{translated_ref, translated_refmut, translated_str};
+use crate::fs::{File, OpenFlags, open_file, resolve_path};
+use crate::hart::{hart_id, online_harts};
+use crate::mm::{
+    ElfImage, LoadError, copy_from_user, copy_to_user, translated_object_mut, translated_str,
+};
 use crate::task::{
-    SignalFlags, current_process, current_task, current_user_token, exit_current_and_run_next,
-    pid2process, suspend_current_and_run_next,
+    ALL_HARTS, CpuTime, NICE_MAX, NICE_MIN, ProcessControlBlock, RT_PRIORITY_MAX, RT_PRIORITY_MIN,
+    SchedPolicy, SignalFlags, TaskControlBlock, current_process, current_task, current_user_token,
+    exit_current_and_run_next, load_shared_file_pages_of_current, pid2process,
+    suspend_current_and_run_next,
 };
-use crate::timer::get_time_ms;
+use crate::timer::{cycles_to_ticks, cycles_to_us, get_time, get_time_ms};
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
+use super::{SysError, SysResult};
+
 pub fn sys_exit(exit_code: i32) -> ! {
     exit_current_and_run_next(exit_code);
     panic!("Unreachable in sys_exit!");
 }
 
-pub fn sys_yield() -> isize {
+pub fn sys_yield() -> SysResult {
     suspend_current_and_run_next();
-    0
+    Ok(0)
 }
 
-pub fn sys_get_time() -> isize {
-    get_time_ms() as isize
+pub fn sys_get_time() -> SysResult {
+    Ok(get_time_ms() as isize)
 }
 
-pub fn sys_getpid() -> isize {
-    current_task().unwrap().process.upgrade().unwrap().getpid() as isize
+pub fn sys_getpid() -> SysResult {
+    Ok(current_task().unwrap().process.upgrade().unwrap().getpid() as isize)
 }
 
-pub fn sys_fork() -> isize {
+pub fn sys_fork() -> SysResult {
+    if !load_shared_file_pages_of_current() {
+        return Err(SysError::ENOMEM);
+    }
     let current_process = current_process();
-    let new_process = current_process.fork();
+    let new_process = current_process.fork().ok_or(SysError::ENOMEM)?;
     let new_pid = new_process.getpid();
     // modify trap context of new_task, because it returns immediately after switching
     let new_process_inner = new_process.inner_exclusive_access();
@@ -38,53 +48,93 @@ pub fn sys_fork() -> isize {
     // we do not have to move to next instruction since we have done it before
     // for child process, fork returns 0
     trap_cx.x[10] = 0;
-    new_pid as isize
+    Ok(new_pid as isize)
 }
 
-pub fn sys_exec(path: *const u8, mut args: *const usize) -> isize {
+pub fn sys_exec(path: *const u8, mut args: *const usize) -> SysResult {
     let token = current_user_token();
-    let path = translated_str(token, path);
+    let path = translated_str(token, path).ok_or(SysError::EFAULT)?;
     let mut args_vec: Vec<String> = Vec::new();
     loop {
-        let arg_str_ptr = *translated_ref(token, args);
+        let arg_str_ptr = copy_from_user(token, args).ok_or(SysError::EFAULT)?;
         if arg_str_ptr == 0 {
             break;
         }
-        args_vec.push(translated_str(token, arg_str_ptr as *const u8));
+        args_vec.push(translated_str(token, arg_str_ptr as *const u8).ok_or(SysError::EFAULT)?);
         unsafe {
             args = args.add(1);
         }
     }
+    let process = current_process();
+    let path = resolve_path(&process.inner_exclusive_access().cwd, &path);
     if let Some(app_inode) = open_file(path.as_str(), OpenFlags::RDONLY) {
-        let all_data = app_inode.read_all();
-        let process = current_process();
+        // segments are read from the file when first accessed
+        let image = app_inode
+            .inode()
+            .and_then(ElfImage::new)
+            .ok_or(SysError::ENOEXEC)?;
+        // a dynamically linked program is started by its loader
+        let interp = match image.interpreter() {
+            Some(interp) => {
+                let interp = resolve_path(&process.inner_exclusive_access().cwd, &interp);
+                let interp_inode =
+                    open_file(interp.as_str(), OpenFlags::RDONLY).ok_or(SysError::ENOENT)?;
+                let interp_image = interp_inode
+                    .inode()
+                    .and_then(ElfImage::new)
+                    .ok_or(SysError::ENOEXEC)?;
+                Some(interp_image)
+            }
+            None => None,
+        };
         let argc = args_vec.len();
-        process.exec(all_data.as_slice(), args_vec);
+        process
+            .exec(image, interp, args_vec)
+            .map_err(|err| match err {
+                LoadError::BadElf => SysError::ENOEXEC,
+                LoadError::OutOfMemory => SysError::ENOMEM,
+            })?;
         // return argc because cx.x[10] will be covered with it later
-        argc as isize
+        Ok(argc as isize)
     } else {
-        -1
+        Err(SysError::ENOENT)
     }
 }
 
-/// If there is not a child process whose pid is same as given, return -1.
-/// Else if there is a child process but it is still running, return -2.
-pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
+/// If there is not a child process whose pid is same as given, return ECHILD.
+/// Else if there is a child process but it is still running, return EAGAIN.
+/// Otherwise reap it, and fill `exit_code_ptr` and `rusage` unless they are null.
+pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32, rusage: *mut RUsage) -> SysResult {
     let process = current_process();
     // find a child process
 
+    // translate before taking the PCB, a copy-on-write fault needs it
+    let token = current_user_token();
+    let exit_code_buffer = if exit_code_ptr.is_null() {
+        None
+    } else {
+        Some(translated_object_mut(token, exit_code_ptr).ok_or(SysError::EFAULT)?)
+    };
+    let rusage_buffer = if rusage.is_null() {
+        None
+    } else {
+        Some(translated_object_mut(token, rusage).ok_or(SysError::EFAULT)?)
+    };
     let mut inner = process.inner_exclusive_access();
     if !inner
         .children
         .iter()
         .any(|p| pid == -1 || pid as usize == p.getpid())
     {
-        return -1;
+        return Err(SysError::ECHILD);
         // ---- release current PCB
     }
     let pair = inner.children.iter().enumerate().find(|(_, p)| {
         // ++++ temporarily access child PCB exclusively
-        p.inner_exclusive_access().is_zombie && (pid == -1 || pid as usize == p.getpid())
+        // the hart running its exit may still hold the child for a moment
+        p.inner_exclusive_access().is_zombie
+            && Arc::strong_count(p) == 1
+            && (pid == -1 || pid as usize == p.getpid())
         // ++++ release child PCB
     });
     if let Some((idx, _)) = pair {
@@ -93,25 +143,251 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
         assert_eq!(Arc::strong_count(&child), 1);
         let found_pid = child.getpid();
         // ++++ temporarily access child PCB exclusively
-        let exit_code = child.inner_exclusive_access().exit_code;
+        let child_inner = child.inner_exclusive_access();
+        let exit_code = child_inner.exit_code;
+        let mut child_time = child_inner.cpu_time();
+        child_time += child_inner.children_cpu_time;
+        drop(child_inner);
         // ++++ release child PCB
-        *translated_refmut(inner.memory_set.token(), exit_code_ptr) = exit_code;
-        found_pid as isize
+        inner.children_cpu_time += child_time;
+        if let Some(mut exit_code_buffer) = exit_code_buffer {
+            exit_code_buffer.write_value(&exit_code);
+        }
+        if let Some(mut rusage_buffer) = rusage_buffer {
+            rusage_buffer.write_value(&RUsage::from_cpu_time(child_time));
+        }
+        Ok(found_pid as isize)
     } else {
-        -2
+        Err(SysError::EAGAIN)
     }
     // ---- release current PCB automatically
 }
 
-pub fn sys_kill(pid: usize, signal: u32) -> isize {
+#[repr(C)]
+#[allow(unused)]
+pub struct Tms {
+    tms_utime: usize,
+    tms_stime: usize,
+    tms_cutime: usize,
+    tms_cstime: usize,
+}
+
+/// Fill `buf` with the CPU time of the process and its reaped children in clock ticks,
+/// return the clock ticks since boot.
+pub fn sys_times(buf: *mut Tms) -> SysResult {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let (time, children_time) = (inner.cpu_time(), inner.children_cpu_time);
+    drop(inner);
+    let tms = Tms {
+        tms_utime: cycles_to_ticks(time.user),
+        tms_stime: cycles_to_ticks(time.kernel),
+        tms_cutime: cycles_to_ticks(children_time.user),
+        tms_cstime: cycles_to_ticks(children_time.kernel),
+    };
+    copy_to_user(current_user_token(), buf, &tms).ok_or(SysError::EFAULT)?;
+    Ok(cycles_to_ticks(get_time()) as isize)
+}
+
+#[repr(C)]
+#[allow(unused)]
+pub struct TimeVal {
+    sec: usize,
+    usec: usize,
+}
+
+impl TimeVal {
+    fn from_cycles(cycles: usize) -> Self {
+        let usec = cycles_to_us(cycles);
+        Self {
+            sec: usec / 1_000_000,
+            usec: usec % 1_000_000,
+        }
+    }
+}
+
+/// `struct rusage` of Linux, only the CPU times are counted.
+#[repr(C)]
+#[allow(unused)]
+pub struct RUsage {
+    ru_utime: TimeVal,
+    ru_stime: TimeVal,
+    /// `ru_maxrss` to `ru_nivcsw`, always 0
+    ru_others: [isize; 14],
+}
+
+impl RUsage {
+    fn from_cpu_time(time: CpuTime) -> Self {
+        Self {
+            ru_utime: TimeVal::from_cycles(time.user),
+            ru_stime: TimeVal::from_cycles(time.kernel),
+            ru_others: [0; 14],
+        }
+    }
+}
+
+/// `who` of `getrusage(2)`.
+const RUSAGE_SELF: isize = 0;
+const RUSAGE_CHILDREN: isize = -1;
+const RUSAGE_THREAD: isize = 1;
+
+/// Fill `usage` with the CPU time of the process, its reaped children or the calling thread.
+pub fn sys_getrusage(who: isize, usage: *mut RUsage) -> SysResult {
+    let time = match who {
+        RUSAGE_SELF => current_process().inner_exclusive_access().cpu_time(),
+        RUSAGE_CHILDREN => current_process().inner_exclusive_access().children_cpu_time,
+        RUSAGE_THREAD => current_task()
+            .unwrap()
+            .inner_exclusive_access()
+            .cpu_clock
+            .time(),
+        _ => return Err(SysError::EINVAL),
+    };
+    copy_to_user(current_user_token(), usage, &RUsage::from_cpu_time(time))
+        .ok_or(SysError::EFAULT)?;
+    Ok(0)
+}
+
+/// `which` of `setpriority(2)`, process groups and users do not exist here.
+const PRIO_PROCESS: usize = 0;
+
+/// The process `who`, or the current one if it is 0.
+fn priority_target(which: usize, who: usize) -> Result<Arc<ProcessControlBlock>, SysError> {
+    if which != PRIO_PROCESS {
+        return Err(SysError::EINVAL);
+    }
+    if who == 0 {
+        Ok(current_process())
+    } else {
+        pid2process(who).ok_or(SysError::ESRCH)
+    }
+}
+
+/// Set the niceness of every thread of a process, out of range values are clamped.
+pub fn sys_setpriority(which: usize, who: usize, nice: isize) -> SysResult {
+    let process = priority_target(which, who)?;
+    let nice = nice.clamp(NICE_MIN, NICE_MAX);
+    let process_inner = process.inner_exclusive_access();
+    for task in process_inner.tasks.iter().flatten() {
+        task.inner_exclusive_access().sched.nice = nice;
+    }
+    Ok(0)
+}
+
+/// Like Linux, return `20 - nice` to keep the result positive.
+pub fn sys_getpriority(which: usize, who: usize) -> SysResult {
+    let process = priority_target(which, who)?;
+    let process_inner = process.inner_exclusive_access();
+    let task = process_inner
+        .tasks
+        .iter()
+        .flatten()
+        .next()
+        .ok_or(SysError::ESRCH)?;
+    let nice = task.inner_exclusive_access().sched.nice;
+    Ok(20 - nice)
+}
+
+/// The thread `tid` of the current process.
+fn thread_target(tid: usize) -> Result<Arc<TaskControlBlock>, SysError> {
+    let process = current_process();
+    let process_inner = process.inner_exclusive_access();
+    process_inner
+        .tasks
+        .get(tid)
+        .and_then(Option::clone)
+        .ok_or(SysError::ESRCH)
+}
+
+/// Pin thread `tid` to the harts in `*mask`, one bit each. At least one must be online.
+pub fn sys_sched_setaffinity(tid: usize, size: usize, mask: *const usize) -> SysResult {
+    if size < core::mem::size_of::<usize>() {
+        return Err(SysError::EINVAL);
+    }
+    let mask = copy_from_user(current_user_token(), mask).ok_or(SysError::EFAULT)? & ALL_HARTS;
+    if mask & online_harts() == 0 {
+        return Err(SysError::EINVAL);
+    }
+    let task = thread_target(tid)?;
+    task.inner_exclusive_access().sched.affinity = mask;
+    // leave a hart that is no longer allowed at once, other threads move on their next tick
+    if Arc::ptr_eq(&task, &current_task().unwrap()) && mask & (1 << hart_id()) == 0 {
+        suspend_current_and_run_next();
+    }
+    Ok(0)
+}
+
+/// Write the harts thread `tid` may run on to `*mask`, return the size of the mask.
+pub fn sys_sched_getaffinity(tid: usize, size: usize, mask: *mut usize) -> SysResult {
+    let mask_size = core::mem::size_of::<usize>();
+    if size < mask_size {
+        return Err(SysError::EINVAL);
+    }
+    let affinity = thread_target(tid)?.inner_exclusive_access().sched.affinity;
+    copy_to_user(current_user_token(), mask, &affinity).ok_or(SysError::EFAULT)?;
+    Ok(mask_size as isize)
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SchedParam {
+    sched_priority: i32,
+}
+
+/// Set the policy of thread `tid`, with a priority in 1..=99 for real-time policies and 0
+/// for the normal one. A queued thread is moved when it is queued the next time.
+pub fn sys_sched_setscheduler(tid: usize, policy: usize, param: *const SchedParam) -> SysResult {
+    let policy = SchedPolicy::from_raw(policy).ok_or(SysError::EINVAL)?;
+    let param = copy_from_user(current_user_token(), param).ok_or(SysError::EFAULT)?;
+    let priority = usize::try_from(param.sched_priority).map_err(|_| SysError::EINVAL)?;
+    let valid = match policy {
+        SchedPolicy::Normal => priority == 0,
+        _ => (RT_PRIORITY_MIN..=RT_PRIORITY_MAX).contains(&priority),
+    };
+    if !valid {
+        return Err(SysError::EINVAL);
+    }
+    let task = thread_target(tid)?;
+    let mut task_inner = task.inner_exclusive_access();
+    task_inner.sched.policy = policy;
+    task_inner.sched.rt_priority = priority;
+    drop(task_inner);
+    // requeue the calling thread in its new class
+    if Arc::ptr_eq(&task, &current_task().unwrap()) {
+        suspend_current_and_run_next();
+    }
+    Ok(0)
+}
+
+pub fn sys_sched_getscheduler(tid: usize) -> SysResult {
+    let policy = thread_target(tid)?.inner_exclusive_access().sched.policy;
+    Ok(policy as isize)
+}
+
+pub fn sys_sched_getparam(tid: usize, param: *mut SchedParam) -> SysResult {
+    let priority = thread_target(tid)?
+        .inner_exclusive_access()
+        .sched
+        .rt_priority;
+    let value = SchedParam {
+        sched_priority: priority as i32,
+    };
+    copy_to_user(current_user_token(), param, &value).ok_or(SysError::EFAULT)?;
+    Ok(0)
+}
+
+pub fn sys_kill(pid: usize, signal: u32) -> SysResult {
     if let Some(process) = pid2process(pid) {
-        if let Some(flag) = SignalFlags::from_bits(signal) {
+        // the cause of a fault can not be sent
+        let flag = SignalFlags::from_bits(signal)
+            .filter(|flag| !flag.contains(SignalFlags::STACK_OVERFLOW));
+        if let Some(flag) = flag {
             process.inner_exclusive_access().signals |= flag;
-            0
+            Ok(0)
         } else {
-            -1
+            Err(SysError::EINVAL)
         }
     } else {
-        -1
+        Err(SysError::ESRCH)
     }
 }
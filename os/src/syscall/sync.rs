@@ -1,17 +1,21 @@
 use crate::sync::{Condvar, Mutex, MutexBlocking, MutexSpin, Semaphore};
-use crate::task::{block_current_and_run_next, current_process, current_task};
+use crate::task::{block_current_task, current_process, current_task, schedule};
 use crate::timer::{add_timer, get_time_ms};
 use alloc::sync::Arc;
 
-pub fn sys_sleep(ms: usize) -> isize {
+use super::{SysError, SysResult};
+
+pub fn sys_sleep(ms: usize) -> SysResult {
     let expire_ms = get_time_ms() + ms;
     let task = current_task().unwrap();
+    // block before the timer exists, so that whichever hart fires it finds the task blocked
+    let task_cx_ptr = block_current_task();
     add_timer(expire_ms, task);
-    block_current_and_run_next();
-    0
+    schedule(task_cx_ptr);
+    Ok(0)
 }
 
-pub fn sys_mutex_create(blocking: bool) -> isize {
+pub fn sys_mutex_create(blocking: bool) -> SysResult {
     let process = current_process();
     let mutex: Option<Arc<dyn Mutex>> = if !blocking {
         Some(Arc::new(MutexSpin::new()))
@@ -27,34 +31,46 @@ pub fn sys_mutex_create(blocking: bool) -> isize {
         .map(|(id, _)| id)
     {
         process_inner.mutex_list[id] = mutex;
-        id as isize
+        Ok(id as isize)
     } else {
         process_inner.mutex_list.push(mutex);
-        process_inner.mutex_list.len() as isize - 1
+        Ok(process_inner.mutex_list.len() as isize - 1)
     }
 }
 
-pub fn sys_mutex_lock(mutex_id: usize) -> isize {
+pub fn sys_mutex_lock(mutex_id: usize) -> SysResult {
     let process = current_process();
     let process_inner = process.inner_exclusive_access();
-    let mutex = Arc::clone(process_inner.mutex_list[mutex_id].as_ref().unwrap());
+    let mutex = Arc::clone(
+        process_inner
+            .mutex_list
+            .get(mutex_id)
+            .and_then(Option::as_ref)
+            .ok_or(SysError::EINVAL)?,
+    );
     drop(process_inner);
     drop(process);
     mutex.lock();
-    0
+    Ok(0)
 }
 
-pub fn sys_mutex_unlock(mutex_id: usize) -> isize {
+pub fn sys_mutex_unlock(mutex_id: usize) -> SysResult {
     let process = current_process();
     let process_inner = process.inner_exclusive_access();
-    let mutex = Arc::clone(process_inner.mutex_list[mutex_id].as_ref().unwrap());
+    let mutex = Arc::clone(
+        process_inner
+            .mutex_list
+            .get(mutex_id)
+            .and_then(Option::as_ref)
+            .ok_or(SysError::EINVAL)?,
+    );
     drop(process_inner);
     drop(process);
     mutex.unlock();
-    0
+    Ok(0)
 }
 
-pub fn sys_semaphore_create(res_count: usize) -> isize {
+pub fn sys_semaphore_create(res_count: usize) -> SysResult {
     let process = current_process();
     let mut process_inner = process.inner_exclusive_access();
     let id = if let Some(id) = process_inner
@@ -72,28 +88,40 @@ pub fn sys_semaphore_create(res_count: usize) -> isize {
             .push(Some(Arc::new(Semaphore::new(res_count))));
         process_inner.semaphore_list.len() - 1
     };
-    id as isize
+    Ok(id as isize)
 }
 
-pub fn sys_semaphore_up(sem_id: usize) -> isize {
+pub fn sys_semaphore_up(sem_id: usize) -> SysResult {
     let process = current_process();
     let process_inner = process.inner_exclusive_access();
-    let sem = Arc::clone(process_inner.semaphore_list[sem_id].as_ref().unwrap());
+    let sem = Arc::clone(
+        process_inner
+            .semaphore_list
+            .get(sem_id)
+            .and_then(Option::as_ref)
+            .ok_or(SysError::EINVAL)?,
+    );
     drop(process_inner);
     sem.up();
-    0
+    Ok(0)
 }
 
-pub fn sys_semaphore_down(sem_id: usize) -> isize {
+pub fn sys_semaphore_down(sem_id: usize) -> SysResult {
     let process = current_process();
     let process_inner = process.inner_exclusive_access();
-    let sem = Arc::clone(process_inner.semaphore_list[sem_id].as_ref().unwrap());
+    let sem = Arc::clone(
+        process_inner
+            .semaphore_list
+            .get(sem_id)
+            .and_then(Option::as_ref)
+            .ok_or(SysError::EINVAL)?,
+    );
     drop(process_inner);
     sem.down();
-    0
+    Ok(0)
 }
 
-pub fn sys_condvar_create() -> isize {
+pub fn sys_condvar_create() -> SysResult {
     let process = current_process();
     let mut process_inner = process.inner_exclusive_access();
     let id = if let Some(id) = process_inner
@@ -111,24 +139,42 @@ pub fn sys_condvar_create() -> isize {
             .push(Some(Arc::new(Condvar::new())));
         process_inner.condvar_list.len() - 1
     };
-    id as isize
+    Ok(id as isize)
 }
 
-pub fn sys_condvar_signal(condvar_id: usize) -> isize {
+pub fn sys_condvar_signal(condvar_id: usize) -> SysResult {
     let process = current_process();
     let process_inner = process.inner_exclusive_access();
-    let condvar = Arc::clone(process_inner.condvar_list[condvar_id].as_ref().unwrap());
+    let condvar = Arc::clone(
+        process_inner
+            .condvar_list
+            .get(condvar_id)
+            .and_then(Option::as_ref)
+            .ok_or(SysError::EINVAL)?,
+    );
     drop(process_inner);
     condvar.signal();
-    0
+    Ok(0)
 }
 
-pub fn sys_condvar_wait(condvar_id: usize, mutex_id: usize) -> isize {
+pub fn sys_condvar_wait(condvar_id: usize, mutex_id: usize) -> SysResult {
     let process = current_process();
     let process_inner = process.inner_exclusive_access();
-    let condvar = Arc::clone(process_inner.condvar_list[condvar_id].as_ref().unwrap());
-    let mutex = Arc::clone(process_inner.mutex_list[mutex_id].as_ref().unwrap());
+    let condvar = Arc::clone(
+        process_inner
+            .condvar_list
+            .get(condvar_id)
+            .and_then(Option::as_ref)
+            .ok_or(SysError::EINVAL)?,
+    );
+    let mutex = Arc::clone(
+        process_inner
+            .mutex_list
+            .get(mutex_id)
+            .and_then(Option::as_ref)
+            .ok_or(SysError::EINVAL)?,
+    );
     drop(process_inner);
     condvar.wait_with_mutex(mutex);
-    0
+    Ok(0)
 }
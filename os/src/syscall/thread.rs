@@ -5,7 +5,9 @@ use crate::{
 };
 use alloc::sync::Arc;
 
-pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
+use super::{SysError, SysResult};
+
+pub fn sys_thread_create(entry: usize, arg: usize) -> SysResult {
     let task = current_task().unwrap();
     let process = task.process.upgrade().unwrap();
     // create a new thread
@@ -18,8 +20,8 @@ pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
             .ustack_base,
         true,
     ));
-    // add new task to scheduler
-    add_task(Arc::clone(&new_task));
+    // a new thread starts with the niceness and affinity of its creator
+    new_task.inner_exclusive_access().sched = task.inner_exclusive_access().sched.inherit();
     let new_task_inner = new_task.inner_exclusive_access();
     let new_task_res = new_task_inner.res.as_ref().unwrap();
     let new_task_tid = new_task_res.tid;
@@ -39,47 +41,52 @@ pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
         trap_handler as usize,
     );
     (*new_task_trap_cx).x[10] = arg;
-    new_task_tid as isize
+    drop(process_inner);
+    drop(new_task_inner);
+    // add new task to scheduler once its trap context is ready, another hart may run it at once
+    add_task(Arc::clone(&new_task));
+    Ok(new_task_tid as isize)
 }
 
-pub fn sys_gettid() -> isize {
-    current_task()
+pub fn sys_gettid() -> SysResult {
+    Ok(current_task()
         .unwrap()
         .inner_exclusive_access()
         .res
         .as_ref()
         .unwrap()
-        .tid as isize
+        .tid as isize)
 }
 
-/// thread does not exist, return -1
-/// thread has not exited yet, return -2
+/// thread is current thread, return EDEADLK
+/// thread does not exist, return ESRCH
+/// thread has not exited yet, return EAGAIN
 /// otherwise, return thread's exit code
-pub fn sys_waittid(tid: usize) -> i32 {
+pub fn sys_waittid(tid: usize) -> SysResult {
     let task = current_task().unwrap();
     let process = task.process.upgrade().unwrap();
-    let task_inner = task.inner_exclusive_access();
-    let mut process_inner = process.inner_exclusive_access();
     // a thread cannot wait for itself
-    if task_inner.res.as_ref().unwrap().tid == tid {
-        return -1;
+    if task.inner_exclusive_access().res.as_ref().unwrap().tid == tid {
+        return Err(SysError::EDEADLK);
     }
+    // the process is locked before any of its threads
+    let mut process_inner = process.inner_exclusive_access();
     let mut exit_code: Option<i32> = None;
-    let waited_task = process_inner.tasks[tid].as_ref();
+    let waited_task = process_inner.tasks.get(tid).and_then(Option::as_ref);
     if let Some(waited_task) = waited_task {
         if let Some(waited_exit_code) = waited_task.inner_exclusive_access().exit_code {
             exit_code = Some(waited_exit_code);
         }
     } else {
         // waited thread does not exist
-        return -1;
+        return Err(SysError::ESRCH);
     }
     if let Some(exit_code) = exit_code {
         // dealloc the exited thread
-        process_inner.tasks[tid] = None;
-        exit_code
+        process_inner.remove_task(tid);
+        Ok(exit_code as isize)
     } else {
         // waited thread has not exited
-        -2
+        Err(SysError::EAGAIN)
     }
 }
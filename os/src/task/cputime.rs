@@ -0,0 +1,58 @@
+use crate::timer::get_time;
+use core::ops::AddAssign;
+
+/// CPU time spent in user space and in the kernel, in timer cycles.
+#[derive(Copy, Clone, Default)]
+pub struct CpuTime {
+    pub user: usize,
+    pub kernel: usize,
+}
+
+impl AddAssign for CpuTime {
+    fn add_assign(&mut self, other: Self) {
+        self.user += other.user;
+        self.kernel += other.kernel;
+    }
+}
+
+/// CPU time of a task, charged whenever it crosses between user space and the kernel
+/// and when a hart switches to or away from it.
+#[derive(Default)]
+pub struct CpuClock {
+    time: CpuTime,
+    /// when the time up to now was charged last
+    since: usize,
+}
+
+impl CpuClock {
+    /// Charge the time since the last boundary to user or kernel time.
+    fn charge(&mut self, user: bool) {
+        let now = get_time();
+        let delta = now - self.since;
+        if user {
+            self.time.user += delta;
+        } else {
+            self.time.kernel += delta;
+        }
+        self.since = now;
+    }
+    /// A hart switches to the task, which goes on in the kernel.
+    pub fn switch_in(&mut self) {
+        self.since = get_time();
+    }
+    /// A hart switched away from the task.
+    pub fn switch_out(&mut self) {
+        self.charge(false);
+    }
+    /// The task trapped from user space.
+    pub fn enter_kernel(&mut self) {
+        self.charge(true);
+    }
+    /// The task returns to user space.
+    pub fn leave_kernel(&mut self) {
+        self.charge(false);
+    }
+    pub fn time(&self) -> CpuTime {
+        self.time
+    }
+}
@@ -1,7 +1,7 @@
 use super::ProcessControlBlock;
 use crate::config::{KERNEL_STACK_SIZE, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT_BASE, USER_STACK_SIZE};
 use crate::mm::{KERNEL_SPACE, MapPermission, PhysPageNum, VirtAddr};
-use crate::sync::UPIntrFreeCell;
+use crate::sync::SpinNoIrqLock;
 use alloc::{
     sync::{Arc, Weak},
     vec::Vec,
@@ -40,10 +40,10 @@ impl RecycleAllocator {
 }
 
 lazy_static! {
-    static ref PID_ALLOCATOR: UPIntrFreeCell<RecycleAllocator> =
-        unsafe { UPIntrFreeCell::new(RecycleAllocator::new()) };
-    static ref KSTACK_ALLOCATOR: UPIntrFreeCell<RecycleAllocator> =
-        unsafe { UPIntrFreeCell::new(RecycleAllocator::new()) };
+    static ref PID_ALLOCATOR: SpinNoIrqLock<RecycleAllocator> =
+        unsafe { SpinNoIrqLock::new(RecycleAllocator::new()) };
+    static ref KSTACK_ALLOCATOR: SpinNoIrqLock<RecycleAllocator> =
+        unsafe { SpinNoIrqLock::new(RecycleAllocator::new()) };
 }
 
 pub const IDLE_PID: usize = 0;
@@ -120,10 +120,15 @@ fn trap_cx_bottom_from_tid(tid: usize) -> usize {
     TRAP_CONTEXT_BASE - tid * PAGE_SIZE
 }
 
-fn ustack_bottom_from_tid(ustack_base: usize, tid: usize) -> usize {
+/// Each user stack sits right above its own unmapped guard page.
+fn ustack_guard_from_tid(ustack_base: usize, tid: usize) -> usize {
     ustack_base + tid * (PAGE_SIZE + USER_STACK_SIZE)
 }
 
+fn ustack_bottom_from_tid(ustack_base: usize, tid: usize) -> usize {
+    ustack_guard_from_tid(ustack_base, tid) + PAGE_SIZE
+}
+
 impl TaskUserRes {
     pub fn new(
         process: Arc<ProcessControlBlock>,
@@ -146,9 +151,13 @@ impl TaskUserRes {
         let process = self.process.upgrade().unwrap();
         let mut process_inner = process.inner_exclusive_access();
         // alloc user stack
+        let ustack_guard = ustack_guard_from_tid(self.ustack_base, self.tid);
+        process_inner
+            .memory_set
+            .insert_guard_area(ustack_guard.into(), (ustack_guard + PAGE_SIZE).into());
         let ustack_bottom = ustack_bottom_from_tid(self.ustack_base, self.tid);
         let ustack_top = ustack_bottom + USER_STACK_SIZE;
-        process_inner.memory_set.insert_framed_area(
+        process_inner.memory_set.insert_lazy_area(
             ustack_bottom.into(),
             ustack_top.into(),
             MapPermission::R | MapPermission::W | MapPermission::U,
@@ -172,6 +181,10 @@ impl TaskUserRes {
         process_inner
             .memory_set
             .remove_area_with_start_vpn(ustack_bottom_va.into());
+        let ustack_guard_va: VirtAddr = ustack_guard_from_tid(self.ustack_base, self.tid).into();
+        process_inner
+            .memory_set
+            .remove_area_with_start_vpn(ustack_guard_va.into());
         // dealloc trap_cx manually
         let trap_cx_bottom_va: VirtAddr = trap_cx_bottom_from_tid(self.tid).into();
         process_inner
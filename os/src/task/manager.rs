@@ -1,33 +1,132 @@
+use super::scheduler::{RtScheduler, SchedEntity, Scheduler, new_scheduler};
 use super::{ProcessControlBlock, TaskControlBlock, TaskStatus};
-use crate::sync::UPIntrFreeCell;
-use alloc::collections::{BTreeMap, VecDeque};
+use crate::config::MAX_HARTS;
+use crate::hart::{hart_id, online_harts};
+use crate::sbi::send_ipi;
+use crate::sync::SpinNoIrqLock;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use core::sync::atomic::{AtomicBool, Ordering};
 use lazy_static::*;
 
 pub struct TaskManager {
-    ready_queue: VecDeque<Arc<TaskControlBlock>>,
+    /// real-time tasks of every hart, which run before its other ones
+    rt_queues: [RtScheduler; MAX_HARTS],
+    /// one run queue per hart
+    run_queues: [Box<dyn Scheduler>; MAX_HARTS],
+    /// rank of the task every hart runs
+    running: [usize; MAX_HARTS],
 }
 
-/// A simple FIFO scheduler.
+/// Ready tasks, in the order of the scheduler picked at build time.
 impl TaskManager {
     pub fn new() -> Self {
         Self {
-            ready_queue: VecDeque::new(),
+            rt_queues: core::array::from_fn(|_| RtScheduler::new()),
+            run_queues: core::array::from_fn(|_| new_scheduler()),
+            running: [0; MAX_HARTS],
         }
     }
-    pub fn add(&mut self, task: Arc<TaskControlBlock>) {
-        self.ready_queue.push_back(task);
+    fn load(&self, hart: usize) -> usize {
+        self.rt_queues[hart].len() + self.run_queues[hart].len()
     }
-    pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
-        self.ready_queue.pop_front()
+    /// Pick the run queue of a task. A real-time task goes to the hart running the lowest rank,
+    /// others stay on their last hart unless another allowed hart has at least two tasks less queued.
+    fn select_hart(&self, sched: &SchedEntity) -> usize {
+        let allowed = sched.affinity & online_harts();
+        if allowed == 0 {
+            // the allowed harts are still booting
+            return sched.affinity.trailing_zeros() as usize;
+        }
+        let harts = (0..MAX_HARTS).filter(|&hart| allowed & (1 << hart) != 0);
+        if sched.is_rt() {
+            return harts
+                .min_by_key(|&hart| (self.running[hart], hart != sched.hart, self.load(hart)))
+                .unwrap();
+        }
+        let least = harts.min_by_key(|&hart| self.load(hart)).unwrap();
+        if allowed & (1 << sched.hart) != 0 && self.load(sched.hart) <= self.load(least) + 1 {
+            sched.hart
+        } else {
+            least
+        }
+    }
+    /// Queue `task`, return the hart that should give up its task to it.
+    pub fn add(&mut self, task: Arc<TaskControlBlock>) -> Option<usize> {
+        let sched = task.inner_exclusive_access().sched;
+        let hart = self.select_hart(&sched);
+        if sched.is_rt() {
+            self.rt_queues[hart].add(task);
+        } else {
+            self.run_queues[hart].add(task);
+        }
+        (sched.rank() > self.running[hart]).then_some(hart)
+    }
+    /// Take a task allowed on the busiest other run queue, real-time ones first.
+    fn steal(&mut self, hart: usize) -> Option<Arc<TaskControlBlock>> {
+        let mut victims: [usize; MAX_HARTS] = core::array::from_fn(|victim| victim);
+        victims.sort_unstable_by_key(|&victim| Reverse(self.load(victim)));
+        let victims = victims.into_iter().filter(|&victim| victim != hart);
+        victims
+            .clone()
+            .find_map(|victim| self.rt_queues[victim].steal(hart))
+            .or_else(|| victims.find_map(|victim| self.run_queues[victim].steal(hart)))
+    }
+    pub fn fetch(&mut self, hart: usize) -> Option<Arc<TaskControlBlock>> {
+        loop {
+            let Some(task) = self.rt_queues[hart]
+                .fetch()
+                .or_else(|| self.run_queues[hart].fetch())
+                .or_else(|| self.steal(hart))
+            else {
+                self.running[hart] = 0;
+                return None;
+            };
+            let mut task_inner = task.inner_exclusive_access();
+            if task_inner.sched.allows(hart) {
+                task_inner.sched.hart = hart;
+                self.running[hart] = task_inner.sched.rank();
+                drop(task_inner);
+                return Some(task);
+            }
+            // the affinity changed while the task was queued
+            drop(task_inner);
+            self.add(task);
+        }
+    }
+    pub fn tick(&mut self, hart: usize, sched: &mut SchedEntity) -> bool {
+        if sched.is_rt() {
+            return self.rt_queues[hart].tick(sched);
+        }
+        // a queued real-time task may have found no lower rank to preempt on wakeup
+        self.run_queues[hart].tick(sched) || !self.rt_queues[hart].is_empty()
+    }
+}
+
+/// Harts asked to give up their task on the way back to user space.
+static NEED_RESCHED: [AtomicBool; MAX_HARTS] = [const { AtomicBool::new(false) }; MAX_HARTS];
+
+/// Make `hart` give up its task, through an IPI if it is another hart.
+fn preempt_hart(hart: usize) {
+    NEED_RESCHED[hart].store(true, Ordering::Release);
+    if hart != hart_id() {
+        send_ipi(1 << hart);
     }
 }
 
+/// Whether this hart was asked to give up its task, e.g. for a woken real-time task.
+pub fn take_need_resched() -> bool {
+    NEED_RESCHED[hart_id()].swap(false, Ordering::Acquire)
+}
+
 lazy_static! {
-    pub static ref TASK_MANAGER: UPIntrFreeCell<TaskManager> =
-        unsafe { UPIntrFreeCell::new(TaskManager::new()) };
-    pub static ref PID2PCB: UPIntrFreeCell<BTreeMap<usize, Arc<ProcessControlBlock>>> =
-        unsafe { UPIntrFreeCell::new(BTreeMap::new()) };
+    pub static ref TASK_MANAGER: SpinNoIrqLock<TaskManager> =
+        unsafe { SpinNoIrqLock::new(TaskManager::new()) };
+    pub static ref PID2PCB: SpinNoIrqLock<BTreeMap<usize, Arc<ProcessControlBlock>>> =
+        unsafe { SpinNoIrqLock::new(BTreeMap::new()) };
 }
 
 pub fn add_task(task: Arc<TaskControlBlock>) {
@@ -36,13 +135,36 @@ pub fn add_task(task: Arc<TaskControlBlock>) {
 
 pub fn wakeup_task(task: Arc<TaskControlBlock>) {
     let mut task_inner = task.inner_exclusive_access();
+    // a task waiting on several queues may be woken more than once
+    if task_inner.task_status != TaskStatus::Blocked {
+        return;
+    }
     task_inner.task_status = TaskStatus::Ready;
     drop(task_inner);
-    add_task(task);
+    // a real-time task preempts a task of lower rank at once
+    if let Some(hart) = TASK_MANAGER.exclusive_access().add(task) {
+        preempt_hart(hart);
+    }
 }
 
 pub fn fetch_task() -> Option<Arc<TaskControlBlock>> {
-    TASK_MANAGER.exclusive_access().fetch()
+    let hart = hart_id();
+    let mut manager = TASK_MANAGER.exclusive_access();
+    // the hart picks the most urgent task anyway
+    NEED_RESCHED[hart].store(false, Ordering::Relaxed);
+    manager.fetch(hart)
+}
+
+/// Charge a timer tick to the running `task`, return whether it should be preempted.
+pub fn tick_task(task: &TaskControlBlock) -> bool {
+    let hart = hart_id();
+    // the manager locks queued tasks, never hold a task while taking it
+    let mut sched = task.inner_exclusive_access().sched;
+    let preempt = TASK_MANAGER.exclusive_access().tick(hart, &mut sched);
+    let mut task_inner = task.inner_exclusive_access();
+    task_inner.sched.update_from(&sched);
+    // move off a hart the affinity no longer allows
+    preempt || !task_inner.sched.allows(hart)
 }
 
 pub fn pid2process(pid: usize) -> Option<Arc<ProcessControlBlock>> {
@@ -50,6 +172,12 @@ pub fn pid2process(pid: usize) -> Option<Arc<ProcessControlBlock>> {
     map.get(&pid).map(Arc::clone)
 }
 
+/// All processes that have not exited.
+pub fn all_processes() -> Vec<Arc<ProcessControlBlock>> {
+    let map = PID2PCB.exclusive_access();
+    map.values().map(Arc::clone).collect()
+}
+
 pub fn insert_into_pid2process(pid: usize, process: Arc<ProcessControlBlock>) {
     PID2PCB.exclusive_access().insert(pid, process);
 }
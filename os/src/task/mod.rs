@@ -1,29 +1,40 @@
 mod context;
+mod cputime;
 mod id;
 mod manager;
 mod process;
 mod processor;
+mod scheduler;
 mod signal;
 mod switch;
 #[allow(clippy::module_inception)]
 mod task;
 
 use self::id::TaskUserRes;
-use crate::fs::{OpenFlags, open_file};
-use crate::sbi::shutdown;
+use crate::fs::{File, OpenFlags, open_file};
+use crate::hart::{hart_id, online_harts};
+use crate::mm::{ElfImage, FAULT_FRAMES, PageLoader, VirtPageNum, frame_alloc, frame_stats};
+use crate::sbi::{send_ipi, shutdown};
 use alloc::{sync::Arc, vec::Vec};
+use core::hint::spin_loop;
+use core::sync::atomic::Ordering;
 use lazy_static::*;
-use manager::fetch_task;
-use process::ProcessControlBlock;
+use manager::{all_processes, fetch_task};
 use switch::__switch;
 
 pub use context::TaskContext;
+pub use cputime::CpuTime;
 pub use id::{IDLE_PID, KernelStack, PidHandle, kstack_alloc, pid_alloc};
-pub use manager::{add_task, pid2process, remove_from_pid2process, wakeup_task};
+pub use manager::{
+    add_task, pid2process, remove_from_pid2process, take_need_resched, tick_task, wakeup_task,
+};
+pub use process::ProcessControlBlock;
 pub use processor::{
-    current_kstack_top, current_process, current_task, current_trap_cx, current_trap_cx_user_va,
-    current_user_token, run_tasks, schedule, take_current_task,
+    current_enter_kernel, current_kstack_top, current_leave_kernel, current_process, current_task,
+    current_trap_cx, current_trap_cx_user_va, current_user_token, current_user_token_on_hart,
+    run_tasks, schedule, set_current_in_syscall, take_current_task,
 };
+pub use scheduler::{ALL_HARTS, NICE_MAX, NICE_MIN, RT_PRIORITY_MAX, RT_PRIORITY_MIN, SchedPolicy};
 pub use signal::SignalFlags;
 pub use task::{TaskControlBlock, TaskStatus};
 
@@ -45,35 +56,61 @@ pub fn suspend_current_and_run_next() {
     schedule(task_cx_ptr);
 }
 
-/// This function must be followed by a schedule
+/// This function must be followed by a schedule.
+/// Block the task before releasing the lock its waker takes, a wakeup
+/// from another hart only finds blocked tasks.
 pub fn block_current_task() -> *mut TaskContext {
     let task = take_current_task().unwrap();
     let mut task_inner = task.inner_exclusive_access();
-    task_inner.task_status = TaskStatus::Blocked;
+    // a task woken since `prepare_block_current` is queued already
+    if task_inner.task_status == TaskStatus::Running {
+        task_inner.task_status = TaskStatus::Blocked;
+    }
     &mut task_inner.task_cx as *mut TaskContext
 }
 
-pub fn block_current_and_run_next() {
-    let task_cx_ptr = block_current_task();
-    schedule(task_cx_ptr);
+/// Mark the current task blocked before it registers with wakers and checks
+/// whether to wait at all, follow it by `block_current_task` or `cancel_block_current`.
+pub fn prepare_block_current() {
+    current_task().unwrap().inner_exclusive_access().task_status = TaskStatus::Blocked;
+}
+
+/// Keep running after `prepare_block_current`. If a wakeup queued the task
+/// meanwhile, return the context that must be followed by a schedule.
+pub fn cancel_block_current() -> Option<*mut TaskContext> {
+    let task = current_task().unwrap();
+    let mut task_inner = task.inner_exclusive_access();
+    if task_inner.task_status == TaskStatus::Blocked {
+        task_inner.task_status = TaskStatus::Running;
+        return None;
+    }
+    drop(task_inner);
+    take_current_task();
+    let task_cx_ptr = &mut task.inner_exclusive_access().task_cx as *mut TaskContext;
+    Some(task_cx_ptr)
 }
 
 /// Exit the current 'Running' task and run the next task in task list.
 pub fn exit_current_and_run_next(exit_code: i32) {
+    write_back_files_before_exit();
     let task = take_current_task().unwrap();
+    // an exiting syscall never returns
+    task.in_syscall.store(false, Ordering::Release);
     let mut task_inner = task.inner_exclusive_access();
-    let process = task.process.upgrade().unwrap();
-    let tid = task_inner.res.as_ref().unwrap().tid;
+    // the resources are gone already if the main thread exited first
+    let tid = task_inner.res.as_ref().map(|res| res.tid);
     // record exit code
     task_inner.exit_code = Some(exit_code);
     task_inner.res = None;
     // here we do not remove the thread since we are still using the kstack
     // it will be deallocated when sys_waittid is called
     drop(task_inner);
+    let process = task.process.upgrade();
     drop(task);
     // however, if this is the main thread of current process
     // the process should terminate at once
-    if tid == 0 {
+    if tid == Some(0) {
+        let process = process.as_ref().unwrap();
         let pid = process.getpid();
         if pid == IDLE_PID {
             println!(
@@ -89,6 +126,7 @@ pub fn exit_current_and_run_next(exit_code: i32) {
             }
         }
         remove_from_pid2process(pid);
+        wait_other_threads_off_cpu(process);
         let mut process_inner = process.inner_exclusive_access();
         // mark this process as a zombie process
         process_inner.is_zombie = true;
@@ -132,6 +170,8 @@ pub fn exit_current_and_run_next(exit_code: i32) {
         // of the main thread. This TCB, including its kstack, will be
         // deallocated when the process is reaped via waitpid.
         while process_inner.tasks.len() > 1 {
+            let tid = process_inner.tasks.len() - 1;
+            process_inner.remove_task(tid);
             process_inner.tasks.pop();
         }
     }
@@ -141,11 +181,61 @@ pub fn exit_current_and_run_next(exit_code: i32) {
     schedule(&mut _unused as *mut _);
 }
 
+/// If the main thread is exiting, stop the other threads and write the shared file
+/// mappings back, while the task can still block on the disk.
+fn write_back_files_before_exit() {
+    let task = current_task().unwrap();
+    let tid = task
+        .inner_exclusive_access()
+        .res
+        .as_ref()
+        .map(|res| res.tid);
+    if tid != Some(0) {
+        return;
+    }
+    let process = task.process.upgrade().unwrap();
+    wait_other_threads_off_cpu(&process);
+    let writeback = process.inner_exclusive_access().memory_set.file_writeback();
+    for page in writeback {
+        page.write();
+    }
+}
+
+/// Make the other threads of `process` leave their harts, before their
+/// trap contexts, user stacks and address space are freed under them.
+fn wait_other_threads_off_cpu(process: &ProcessControlBlock) {
+    process.exiting.store(true, Ordering::Release);
+    let others: Vec<Arc<TaskControlBlock>> = process
+        .inner_exclusive_access()
+        .tasks
+        .iter()
+        .skip(1)
+        .flatten()
+        .cloned()
+        .collect();
+    // a thread in user space traps on the IPI and exits on its way back
+    send_ipi(online_harts() & !(1 << hart_id()));
+    for task in others {
+        while task.on_cpu.load(Ordering::Acquire) {
+            spin_loop();
+        }
+    }
+}
+
+/// Whether the main thread of the current process exited, so the current
+/// thread must exit too instead of going back to user space.
+pub fn current_process_exiting() -> bool {
+    current_task()
+        .unwrap()
+        .process
+        .upgrade()
+        .is_none_or(|process| process.exiting.load(Ordering::Acquire))
+}
+
 lazy_static! {
     pub static ref INITPROC: Arc<ProcessControlBlock> = {
         let inode = open_file("initproc", OpenFlags::RDONLY).unwrap();
-        let v = inode.read_all();
-        ProcessControlBlock::new(v.as_slice())
+        ProcessControlBlock::new(ElfImage::new(inode.inode().unwrap()).unwrap())
     };
 }
 
@@ -159,6 +249,102 @@ pub fn check_signals_of_current() -> Option<(i32, &'static str)> {
     process_inner.signals.check_error()
 }
 
+/// Resolve a page fault at `vpn` if `token` is the address space of the current process.
+/// Return false for a real fault that should be signalled.
+/// `reclaim` may swap out other pages, only faults from user mode may ask for it,
+/// and only single-threaded processes do it since other threads may be in a syscall.
+/// Idle processes give up pages too when that is not enough.
+/// Pages of the elf and of mapped files are read from the file, which may block.
+pub fn handle_page_fault_of_current(
+    token: usize,
+    vpn: VirtPageNum,
+    write: bool,
+    reclaim: bool,
+) -> bool {
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    if process_inner.memory_set.token() != token {
+        return false;
+    }
+    if reclaim && process_inner.thread_count() == 1 {
+        process_inner.memory_set.reserve_frames(FAULT_FRAMES);
+    }
+    reclaim_from_idle_processes(&process, FAULT_FRAMES);
+    if let Some(loader) = process_inner.memory_set.file_page_fault(vpn, write) {
+        drop(process_inner);
+        return load_page_of_current(&process, token, vpn, loader);
+    }
+    process_inner.memory_set.handle_page_fault(vpn, write)
+}
+
+/// Fill page `vpn` with `loader` while no lock is held, then map it.
+/// Return false if no frame is left.
+fn load_page_of_current(
+    process: &ProcessControlBlock,
+    token: usize,
+    vpn: VirtPageNum,
+    loader: PageLoader,
+) -> bool {
+    let Some(frame) = frame_alloc() else {
+        return false;
+    };
+    loader.load_page(vpn, frame.ppn.get_bytes_array());
+    let mut process_inner = process.inner_exclusive_access();
+    // the access is tried again if the address space changed meanwhile
+    process_inner.memory_set.token() != token
+        || process_inner.memory_set.map_loaded_page(vpn, frame)
+}
+
+/// Load every page of the shared file mappings of the current process, so that
+/// a fork shares their frames. Return false if no frame is left.
+pub fn load_shared_file_pages_of_current() -> bool {
+    let process = current_process();
+    loop {
+        let process_inner = process.inner_exclusive_access();
+        let token = process_inner.memory_set.token();
+        let Some((vpn, loader)) = process_inner.memory_set.unloaded_shared_file_page() else {
+            return true;
+        };
+        drop(process_inner);
+        if !load_page_of_current(&process, token, vpn, loader) {
+            return false;
+        }
+    }
+}
+
+/// Swap out pages of other processes that are idle until `count` frames are free,
+/// a clock scan over each of them in turn.
+/// Busy processes are skipped, even when another hart only holds their lock,
+/// so this may be called with the lock of `current` held.
+fn reclaim_from_idle_processes(current: &Arc<ProcessControlBlock>, count: usize) {
+    if frame_stats().free >= count {
+        return;
+    }
+    for process in all_processes() {
+        if Arc::ptr_eq(&process, current) {
+            continue;
+        }
+        let Some(mut process_inner) = process.try_inner_exclusive_access() else {
+            continue;
+        };
+        // no thread can return to user space while the lock is held
+        if process_inner.is_idle() {
+            process_inner.memory_set.reserve_frames(count);
+        }
+        drop(process_inner);
+        if frame_stats().free >= count {
+            return;
+        }
+    }
+}
+
+/// Whether `vpn` is the guard page below one of the user stacks of the current process.
+pub fn is_stack_guard_of_current(vpn: VirtPageNum) -> bool {
+    let process = current_process();
+    let process_inner = process.inner_exclusive_access();
+    process_inner.memory_set.is_guard_page(vpn)
+}
+
 pub fn current_add_signal(signal: SignalFlags) {
     let process = current_process();
     let mut process_inner = process.inner_exclusive_access();
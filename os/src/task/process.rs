@@ -1,22 +1,25 @@
-use super::TaskControlBlock;
 use super::id::RecycleAllocator;
 use super::manager::insert_into_pid2process;
+use super::{CpuTime, TaskControlBlock};
 use super::{PidHandle, pid_alloc};
 use super::{SignalFlags, add_task};
-use crate::fs::{File, Stdin, Stdout};
-use crate::mm::{KERNEL_SPACE, MemorySet, translated_refmut};
-use crate::sync::{Condvar, Mutex, Semaphore, UPIntrFreeCell, UPIntrRefMut};
+use crate::fs::{FdFlags, FileDescriptor, Stdin, Stdout};
+use crate::mm::{ElfImage, KERNEL_SPACE, LoadError, MemorySet, copy_to_user};
+use crate::sync::{Condvar, Mutex, Semaphore, SpinNoIrqGuard, SpinNoIrqLock};
 use crate::trap::{TrapContext, trap_handler};
 use alloc::string::String;
 use alloc::sync::{Arc, Weak};
 use alloc::vec;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 pub struct ProcessControlBlock {
     // immutable
     pub pid: PidHandle,
+    /// set once the main thread exits, the other threads exit on their way back to user space
+    pub exiting: AtomicBool,
     // mutable
-    inner: UPIntrFreeCell<ProcessControlBlockInner>,
+    inner: SpinNoIrqLock<ProcessControlBlockInner>,
 }
 
 pub struct ProcessControlBlockInner {
@@ -25,13 +28,20 @@ pub struct ProcessControlBlockInner {
     pub parent: Option<Weak<ProcessControlBlock>>,
     pub children: Vec<Arc<ProcessControlBlock>>,
     pub exit_code: i32,
-    pub fd_table: Vec<Option<Arc<dyn File + Send + Sync>>>,
+    pub cwd: String,
+    pub heap_bottom: usize,
+    pub program_brk: usize,
+    pub fd_table: Vec<Option<FileDescriptor>>,
     pub signals: SignalFlags,
     pub tasks: Vec<Option<Arc<TaskControlBlock>>>,
     pub task_res_allocator: RecycleAllocator,
     pub mutex_list: Vec<Option<Arc<dyn Mutex>>>,
     pub semaphore_list: Vec<Option<Arc<Semaphore>>>,
     pub condvar_list: Vec<Option<Arc<Condvar>>>,
+    /// CPU time of the threads that are gone
+    pub exited_cpu_time: CpuTime,
+    /// CPU time of the reaped children, including their own reaped children
+    pub children_cpu_time: CpuTime,
 }
 
 impl ProcessControlBlockInner {
@@ -61,37 +71,73 @@ impl ProcessControlBlockInner {
         self.tasks.len()
     }
 
+    /// Whether the user pages may be swapped out now: no thread runs on a hart,
+    /// where it may use them, or is in a syscall, which may hold a slice of them.
+    pub fn is_idle(&self) -> bool {
+        self.tasks.iter().flatten().all(|task| {
+            !task.on_cpu.load(Ordering::Acquire) && !task.in_syscall.load(Ordering::Acquire)
+        })
+    }
+
     pub fn get_task(&self, tid: usize) -> Arc<TaskControlBlock> {
         self.tasks[tid].as_ref().unwrap().clone()
     }
+
+    /// Remove thread `tid`, keeping its CPU time.
+    pub fn remove_task(&mut self, tid: usize) {
+        if let Some(task) = self.tasks[tid].take() {
+            self.exited_cpu_time += task.inner_exclusive_access().cpu_clock.time();
+        }
+    }
+
+    /// CPU time of all threads of the process, past and present.
+    pub fn cpu_time(&self) -> CpuTime {
+        let mut time = self.exited_cpu_time;
+        for task in self.tasks.iter().flatten() {
+            time += task.inner_exclusive_access().cpu_clock.time();
+        }
+        time
+    }
 }
 
 impl ProcessControlBlock {
-    pub fn inner_exclusive_access(&self) -> UPIntrRefMut<'_, ProcessControlBlockInner> {
+    pub fn inner_exclusive_access(&self) -> SpinNoIrqGuard<'_, ProcessControlBlockInner> {
         self.inner.exclusive_access()
     }
 
-    pub fn new(elf_data: &[u8]) -> Arc<Self> {
+    /// Return `None` if another hart is using the process.
+    pub fn try_inner_exclusive_access(
+        &self,
+    ) -> Option<SpinNoIrqGuard<'_, ProcessControlBlockInner>> {
+        self.inner.try_exclusive_access_now()
+    }
+
+    pub fn new(image: ElfImage) -> Arc<Self> {
         // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, ustack_base, entry_point) = MemorySet::from_elf(elf_data);
+        let (memory_set, ustack_base, heap_bottom, entry_point, _) =
+            MemorySet::from_elf(&image, None).unwrap();
         // allocate a pid
         let pid_handle = pid_alloc();
         let process = Arc::new(Self {
             pid: pid_handle,
+            exiting: AtomicBool::new(false),
             inner: unsafe {
-                UPIntrFreeCell::new(ProcessControlBlockInner {
+                SpinNoIrqLock::new(ProcessControlBlockInner {
                     is_zombie: false,
                     memory_set,
                     parent: None,
                     children: Vec::new(),
                     exit_code: 0,
+                    cwd: String::from("/"),
+                    heap_bottom,
+                    program_brk: heap_bottom,
                     fd_table: vec![
                         // 0 -> stdin
-                        Some(Arc::new(Stdin)),
+                        Some(FileDescriptor::new(Arc::new(Stdin), FdFlags::empty())),
                         // 1 -> stdout
-                        Some(Arc::new(Stdout)),
+                        Some(FileDescriptor::new(Arc::new(Stdout), FdFlags::empty())),
                         // 2 -> stderr
-                        Some(Arc::new(Stdout)),
+                        Some(FileDescriptor::new(Arc::new(Stdout), FdFlags::empty())),
                     ],
                     signals: SignalFlags::empty(),
                     tasks: Vec::new(),
@@ -99,6 +145,8 @@ impl ProcessControlBlock {
                     mutex_list: Vec::new(),
                     semaphore_list: Vec::new(),
                     condvar_list: Vec::new(),
+                    exited_cpu_time: CpuTime::default(),
+                    children_cpu_time: CpuTime::default(),
                 })
             },
         });
@@ -132,13 +180,39 @@ impl ProcessControlBlock {
     }
 
     /// Only support processes with a single thread.
-    pub fn exec(self: &Arc<Self>, elf_data: &[u8], args: Vec<String>) {
+    /// `interp_data` is the dynamic loader the program asks for.
+    /// Keep the old program if the new one cannot be loaded.
+    pub fn exec(
+        self: &Arc<Self>,
+        image: ElfImage,
+        interp: Option<ElfImage>,
+        args: Vec<String>,
+    ) -> Result<(), LoadError> {
         assert_eq!(self.inner_exclusive_access().thread_count(), 1);
         // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, ustack_base, entry_point) = MemorySet::from_elf(elf_data);
+        let (memory_set, ustack_base, heap_bottom, entry_point, auxv) =
+            MemorySet::from_elf(&image, interp.as_ref())?;
         let new_token = memory_set.token();
         // substitute memory_set
-        self.inner_exclusive_access().memory_set = memory_set;
+        let mut inner = self.inner_exclusive_access();
+        let writeback = inner.memory_set.file_writeback();
+        inner.memory_set = memory_set;
+        inner.heap_bottom = heap_bottom;
+        inner.program_brk = heap_bottom;
+        drop(inner);
+        // writing the file may block, the process is no longer locked
+        for page in writeback {
+            page.write();
+        }
+        // close the file descriptors marked close-on-exec
+        for fd in self.inner_exclusive_access().fd_table.iter_mut() {
+            if fd
+                .as_ref()
+                .is_some_and(|fd| fd.flags.contains(FdFlags::CLOEXEC))
+            {
+                *fd = None;
+            }
+        }
         // then we alloc user resource for main thread again
         // since memory_set has been changed
         let task = self.inner_exclusive_access().get_task(0);
@@ -150,27 +224,30 @@ impl ProcessControlBlock {
         let mut user_sp = task_inner.res.as_mut().unwrap().ustack_top();
         user_sp -= (args.len() + 1) * core::mem::size_of::<usize>();
         let argv_base = user_sp;
-        let mut argv: Vec<_> = (0..=args.len())
-            .map(|arg| {
-                translated_refmut(
-                    new_token,
-                    (argv_base + arg * core::mem::size_of::<usize>()) as *mut usize,
-                )
-            })
-            .collect();
-        *argv[args.len()] = 0;
+        let argv = |i: usize| (argv_base + i * core::mem::size_of::<usize>()) as *mut usize;
+        copy_to_user(new_token, argv(args.len()), &0).unwrap();
         for i in 0..args.len() {
             user_sp -= args[i].len() + 1;
-            *argv[i] = user_sp;
+            copy_to_user(new_token, argv(i), &user_sp).unwrap();
             let mut p = user_sp;
-            for c in args[i].as_bytes() {
-                *translated_refmut(new_token, p as *mut u8) = *c;
+            for c in args[i].bytes().chain([0]) {
+                copy_to_user(new_token, p as *mut u8, &c).unwrap();
                 p += 1;
             }
-            *translated_refmut(new_token, p as *mut u8) = 0;
         }
         // make the user_sp aligned to 8B for k210 platform
         user_sp -= user_sp % core::mem::size_of::<usize>();
+        // push the auxiliary vector ending with AT_NULL
+        user_sp -= (auxv.len() + 1) * 2 * core::mem::size_of::<usize>();
+        let auxv_base = user_sp;
+        let auxv_words = auxv
+            .into_iter()
+            .chain([(0, 0)])
+            .flat_map(|(key, value)| [key, value]);
+        for (i, word) in auxv_words.enumerate() {
+            let p = auxv_base + i * core::mem::size_of::<usize>();
+            copy_to_user(new_token, p as *mut usize, &word).unwrap();
+        }
         // initialize trap_cx
         let mut trap_cx = TrapContext::app_init_context(
             entry_point,
@@ -181,36 +258,36 @@ impl ProcessControlBlock {
         );
         trap_cx.x[10] = args.len();
         trap_cx.x[11] = argv_base;
+        trap_cx.x[12] = auxv_base;
         *task_inner.get_trap_cx() = trap_cx;
+        Ok(())
     }
 
     /// Only support processes with a single thread.
-    pub fn fork(self: &Arc<Self>) -> Arc<Self> {
+    /// Return `None` if no frame is left for the new address space.
+    pub fn fork(self: &Arc<Self>) -> Option<Arc<Self>> {
         let mut parent = self.inner_exclusive_access();
         assert_eq!(parent.thread_count(), 1);
-        // clone parent's memory_set completely including trampoline/ustacks/trap_cxs
-        let memory_set = MemorySet::from_existed_user(&parent.memory_set);
+        // share parent's memory_set copy-on-write including trampoline/ustacks, copy trap_cxs
+        let memory_set = MemorySet::from_existed_user(&mut parent.memory_set)?;
         // alloc a pid
         let pid = pid_alloc();
         // copy fd table
-        let mut new_fd_table: Vec<Option<Arc<dyn File + Send + Sync>>> = Vec::new();
-        for fd in parent.fd_table.iter() {
-            if let Some(file) = fd {
-                new_fd_table.push(Some(file.clone()));
-            } else {
-                new_fd_table.push(None);
-            }
-        }
+        let new_fd_table = parent.fd_table.clone();
         // create child process pcb
         let child = Arc::new(Self {
             pid,
+            exiting: AtomicBool::new(false),
             inner: unsafe {
-                UPIntrFreeCell::new(ProcessControlBlockInner {
+                SpinNoIrqLock::new(ProcessControlBlockInner {
                     is_zombie: false,
                     memory_set,
                     parent: Some(Arc::downgrade(self)),
                     children: Vec::new(),
                     exit_code: 0,
+                    cwd: parent.cwd.clone(),
+                    heap_bottom: parent.heap_bottom,
+                    program_brk: parent.program_brk,
                     fd_table: new_fd_table,
                     signals: SignalFlags::empty(),
                     tasks: Vec::new(),
@@ -218,6 +295,8 @@ impl ProcessControlBlock {
                     mutex_list: Vec::new(),
                     semaphore_list: Vec::new(),
                     condvar_list: Vec::new(),
+                    exited_cpu_time: CpuTime::default(),
+                    children_cpu_time: CpuTime::default(),
                 })
             },
         });
@@ -237,6 +316,9 @@ impl ProcessControlBlock {
             // but mention that we allocate a new kstack here
             false,
         ));
+        // the child keeps the niceness and affinity of its parent
+        task.inner_exclusive_access().sched =
+            parent.get_task(0).inner_exclusive_access().sched.inherit();
         // attach task to child process
         let mut child_inner = child.inner_exclusive_access();
         child_inner.tasks.push(Some(Arc::clone(&task)));
@@ -249,7 +331,7 @@ impl ProcessControlBlock {
         insert_into_pid2process(child.getpid(), Arc::clone(&child));
         // add this thread to scheduler
         add_task(task);
-        child
+        Some(child)
     }
 
     pub fn getpid(&self) -> usize {
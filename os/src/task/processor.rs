@@ -1,10 +1,16 @@
 use super::__switch;
 use super::{ProcessControlBlock, TaskContext, TaskControlBlock};
 use super::{TaskStatus, fetch_task};
-use crate::sync::UPIntrFreeCell;
+use crate::config::MAX_HARTS;
+use crate::hart::hart_id;
+use crate::sync::SpinNoIrqLock;
 use crate::trap::TrapContext;
 use alloc::sync::Arc;
+use core::arch::asm;
+use core::hint::spin_loop;
+use core::sync::atomic::Ordering;
 use lazy_static::*;
+use riscv::register::sstatus;
 
 pub struct Processor {
     current: Option<Arc<TaskControlBlock>>,
@@ -30,38 +36,57 @@ impl Processor {
 }
 
 lazy_static! {
-    pub static ref PROCESSOR: UPIntrFreeCell<Processor> =
-        unsafe { UPIntrFreeCell::new(Processor::new()) };
+    static ref PROCESSORS: [SpinNoIrqLock<Processor>; MAX_HARTS] =
+        core::array::from_fn(|_| unsafe { SpinNoIrqLock::new(Processor::new()) });
+}
+
+/// The processor of the running hart.
+fn processor() -> &'static SpinNoIrqLock<Processor> {
+    &PROCESSORS[hart_id()]
 }
 
 pub fn run_tasks() {
     loop {
-        let mut processor = PROCESSOR.exclusive_access();
+        let mut processor = processor().exclusive_access();
         if let Some(task) = fetch_task() {
+            // the hart that ran the task last may still be saving its context
+            while task.on_cpu.swap(true, Ordering::Acquire) {
+                spin_loop();
+            }
             let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
             // access coming task TCB exclusively
             let next_task_cx_ptr = task.inner.exclusive_session(|task_inner| {
                 task_inner.task_status = TaskStatus::Running;
+                task_inner.cpu_clock.switch_in();
                 &task_inner.task_cx as *const TaskContext
             });
-            processor.current = Some(task);
+            processor.current = Some(Arc::clone(&task));
             // release processor manually
             drop(processor);
             unsafe {
                 __switch(idle_task_cx_ptr, next_task_cx_ptr);
             }
+            // the task switched back here, so its context is saved
+            task.inner_exclusive_access().cpu_clock.switch_out();
+            task.on_cpu.store(false, Ordering::Release);
         } else {
-            println!("no tasks available in run_tasks");
+            drop(processor);
+            // sleep until an interrupt, which may make a task ready
+            unsafe {
+                sstatus::set_sie();
+                asm!("wfi");
+                sstatus::clear_sie();
+            }
         }
     }
 }
 
 pub fn take_current_task() -> Option<Arc<TaskControlBlock>> {
-    PROCESSOR.exclusive_access().take_current()
+    processor().exclusive_access().take_current()
 }
 
 pub fn current_task() -> Option<Arc<TaskControlBlock>> {
-    PROCESSOR.exclusive_access().current()
+    processor().exclusive_access().current()
 }
 
 pub fn current_process() -> Arc<ProcessControlBlock> {
@@ -73,6 +98,31 @@ pub fn current_user_token() -> usize {
     task.get_user_token()
 }
 
+/// Token of the current user space, which this hart is about to return to.
+pub fn current_user_token_on_hart() -> usize {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    inner.memory_set.token_on_hart()
+}
+
+/// Charge the time the current task ran in user space, on a trap from there.
+pub fn current_enter_kernel() {
+    let task = current_task().unwrap();
+    task.inner_exclusive_access().cpu_clock.enter_kernel();
+}
+
+/// Charge the time the current task ran in the kernel, on its way back to user space.
+pub fn current_leave_kernel() {
+    let task = current_task().unwrap();
+    task.inner_exclusive_access().cpu_clock.leave_kernel();
+}
+
+/// Note whether the current task is in a syscall, its user pages are not swapped out meanwhile.
+pub fn set_current_in_syscall(in_syscall: bool) {
+    let task = current_task().unwrap();
+    task.in_syscall.store(in_syscall, Ordering::Release);
+}
+
 pub fn current_trap_cx() -> &'static mut TrapContext {
     current_task()
         .unwrap()
@@ -96,7 +146,7 @@ pub fn current_kstack_top() -> usize {
 
 pub fn schedule(switched_task_cx_ptr: *mut TaskContext) {
     let idle_task_cx_ptr =
-        PROCESSOR.exclusive_session(|processor| processor.get_idle_task_cx_ptr());
+        processor().exclusive_session(|processor| processor.get_idle_task_cx_ptr());
     unsafe {
         __switch(switched_task_cx_ptr, idle_task_cx_ptr);
     }
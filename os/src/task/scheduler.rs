@@ -0,0 +1,393 @@
+use super::TaskControlBlock;
+use crate::config::MAX_HARTS;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+
+/// The niceness of a task, as taken by `setpriority(2)`.
+pub const NICE_MIN: isize = -20;
+pub const NICE_MAX: isize = 19;
+
+/// Every hart a task may run on.
+pub const ALL_HARTS: usize = (1 << MAX_HARTS) - 1;
+
+/// Scheduling policies as taken by `sched_setscheduler(2)`.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum SchedPolicy {
+    Normal = 0,
+    /// real-time, runs until it blocks, yields or a higher priority comes
+    Fifo = 1,
+    /// real-time, like `Fifo` but takes turns with tasks of the same priority
+    RoundRobin = 2,
+}
+
+impl SchedPolicy {
+    pub fn from_raw(policy: usize) -> Option<Self> {
+        match policy {
+            0 => Some(Self::Normal),
+            1 => Some(Self::Fifo),
+            2 => Some(Self::RoundRobin),
+            _ => None,
+        }
+    }
+}
+
+/// Priorities of real-time tasks, higher ones run first.
+pub const RT_PRIORITY_MIN: usize = 1;
+pub const RT_PRIORITY_MAX: usize = 99;
+
+/// Scheduling state of a task, only touched by the scheduler while the task is not queued.
+#[derive(Copy, Clone)]
+pub struct SchedEntity {
+    pub nice: isize,
+    /// harts the task may run on, one bit each
+    pub affinity: usize,
+    /// hart whose run queue the task was fetched from last
+    pub hart: usize,
+    pub policy: SchedPolicy,
+    /// priority of a real-time task, 0 for normal ones
+    pub rt_priority: usize,
+    /// virtual time used by stride scheduling
+    pass: u64,
+    /// queue of the multi-level feedback queue
+    level: usize,
+    /// ticks run on `level`, or in the time slice of a round robin task
+    ticks: usize,
+    /// boost of the multi-level feedback queue `level` belongs to
+    epoch: usize,
+}
+
+impl Default for SchedEntity {
+    fn default() -> Self {
+        Self {
+            nice: 0,
+            affinity: ALL_HARTS,
+            hart: 0,
+            policy: SchedPolicy::Normal,
+            rt_priority: 0,
+            pass: 0,
+            level: 0,
+            ticks: 0,
+            epoch: 0,
+        }
+    }
+}
+
+impl SchedEntity {
+    /// State of a new task forked or spawned from this one,
+    /// which keeps nice, affinity and policy.
+    pub fn inherit(&self) -> Self {
+        Self {
+            nice: self.nice,
+            affinity: self.affinity,
+            policy: self.policy,
+            rt_priority: self.rt_priority,
+            ..Self::default()
+        }
+    }
+    /// Take the state charged by a tick from `ticked`, but keep nice, affinity and policy,
+    /// which may have been set since `ticked` was copied.
+    pub fn update_from(&mut self, ticked: &Self) {
+        *self = Self {
+            nice: self.nice,
+            affinity: self.affinity,
+            policy: self.policy,
+            rt_priority: self.rt_priority,
+            ..*ticked
+        };
+    }
+    pub fn is_rt(&self) -> bool {
+        self.policy != SchedPolicy::Normal
+    }
+    /// Tasks of a higher rank preempt those of a lower one, normal tasks are all 0.
+    pub fn rank(&self) -> usize {
+        if self.is_rt() { self.rt_priority } else { 0 }
+    }
+    pub fn allows(&self, hart: usize) -> bool {
+        self.affinity & (1 << hart) != 0
+    }
+}
+
+fn allowed_on(task: &TaskControlBlock, hart: usize) -> bool {
+    task.inner_exclusive_access().sched.allows(hart)
+}
+
+/// Position of the last task in `queue` that may run on `hart`.
+fn last_allowed(queue: &VecDeque<Arc<TaskControlBlock>>, hart: usize) -> Option<usize> {
+    queue.iter().rposition(|task| allowed_on(task, hart))
+}
+
+/// A run queue of one hart.
+pub trait Scheduler {
+    /// Queue a ready task.
+    fn add(&mut self, task: Arc<TaskControlBlock>);
+    /// Take the next task to run.
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>>;
+    /// Take a task allowed on `hart` for an idle hart.
+    fn steal(&mut self, hart: usize) -> Option<Arc<TaskControlBlock>>;
+    /// Number of queued tasks.
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Charge a timer tick to the running task, return whether it should give up the hart.
+    fn tick(&mut self, _sched: &mut SchedEntity) -> bool {
+        true
+    }
+}
+
+/// Pick the scheduler from `SCHED` in the Makefile.
+pub fn new_scheduler() -> Box<dyn Scheduler> {
+    match option_env!("SCHED") {
+        Some("fifo") => Box::new(FifoScheduler::new()),
+        Some("stride") => Box::new(StrideScheduler::new()),
+        _ => Box::new(MlfqScheduler::new()),
+    }
+}
+
+/// Round robin, every task runs for one tick.
+struct FifoScheduler {
+    ready_queue: VecDeque<Arc<TaskControlBlock>>,
+}
+
+impl FifoScheduler {
+    fn new() -> Self {
+        Self {
+            ready_queue: VecDeque::new(),
+        }
+    }
+}
+
+impl Scheduler for FifoScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        self.ready_queue.push_back(task);
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        self.ready_queue.pop_front()
+    }
+    fn steal(&mut self, hart: usize) -> Option<Arc<TaskControlBlock>> {
+        let pos = last_allowed(&self.ready_queue, hart)?;
+        self.ready_queue.remove(pos)
+    }
+    fn len(&self) -> usize {
+        self.ready_queue.len()
+    }
+}
+
+/// `sched_prio_to_weight` of Linux, a step of nice changes the CPU share by about 10%.
+const NICE_WEIGHTS: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87,
+    70, 56, 45, 36, 29, 23, 18, 15,
+];
+const NICE_0_WEIGHT: u64 = 1024;
+const BIG_STRIDE: u64 = 1 << 30;
+
+fn weight(nice: isize) -> u64 {
+    NICE_WEIGHTS[(nice - NICE_MIN) as usize]
+}
+
+fn stride(nice: isize) -> u64 {
+    BIG_STRIDE / weight(nice)
+}
+
+/// Stride scheduling, tasks get the hart in proportion to the weight of their nice.
+struct StrideScheduler {
+    /// keyed by pass, then by arrival to keep equal passes in order
+    ready_queue: BTreeMap<(u64, usize), Arc<TaskControlBlock>>,
+    /// pass of the task fetched last
+    min_pass: u64,
+    arrival: usize,
+}
+
+impl StrideScheduler {
+    fn new() -> Self {
+        Self {
+            ready_queue: BTreeMap::new(),
+            min_pass: 0,
+            arrival: 0,
+        }
+    }
+}
+
+impl Scheduler for StrideScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let mut task_inner = task.inner_exclusive_access();
+        let sched = &mut task_inner.sched;
+        // new and woken tasks can not make up for the time they did not run
+        sched.pass = sched.pass.max(self.min_pass);
+        let key = (sched.pass, self.arrival);
+        drop(task_inner);
+        self.arrival += 1;
+        self.ready_queue.insert(key, task);
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let (_, task) = self.ready_queue.pop_first()?;
+        let mut task_inner = task.inner_exclusive_access();
+        let sched = &mut task_inner.sched;
+        self.min_pass = sched.pass;
+        sched.pass += stride(sched.nice);
+        drop(task_inner);
+        Some(task)
+    }
+    fn steal(&mut self, hart: usize) -> Option<Arc<TaskControlBlock>> {
+        let key = *self
+            .ready_queue
+            .iter()
+            .rev()
+            .find(|(_, task)| allowed_on(task, hart))?
+            .0;
+        self.ready_queue.remove(&key)
+    }
+    fn len(&self) -> usize {
+        self.ready_queue.len()
+    }
+}
+
+const MLFQ_LEVELS: usize = 4;
+/// every this many ticks all tasks are moved back to the top queue
+const MLFQ_BOOST_TICKS: usize = 100;
+
+/// ticks a task may run on `level` before it is moved down, in proportion to the
+/// weight of its nice, so tasks on the same level share the hart by their nice
+fn time_slice(level: usize, nice: isize) -> usize {
+    (((1 << level) * weight(nice) / NICE_0_WEIGHT) as usize).max(1)
+}
+
+/// Multi-level feedback queue. Tasks that use up their time slice sink to longer slices,
+/// tasks that block stay on top and preempt the ones below.
+struct MlfqScheduler {
+    queues: [VecDeque<Arc<TaskControlBlock>>; MLFQ_LEVELS],
+    ticks: usize,
+    epoch: usize,
+}
+
+impl MlfqScheduler {
+    fn new() -> Self {
+        Self {
+            queues: Default::default(),
+            ticks: 0,
+            epoch: 0,
+        }
+    }
+    /// Bring `sched` to the top queue if a boost happened since it was queued.
+    fn catch_up(&self, sched: &mut SchedEntity) {
+        if sched.epoch != self.epoch {
+            sched.epoch = self.epoch;
+            sched.level = 0;
+            sched.ticks = 0;
+        }
+    }
+    fn boost(&mut self) {
+        self.epoch += 1;
+        for level in 1..MLFQ_LEVELS {
+            while let Some(task) = self.queues[level].pop_front() {
+                self.catch_up(&mut task.inner_exclusive_access().sched);
+                self.queues[0].push_back(task);
+            }
+        }
+    }
+}
+
+impl Scheduler for MlfqScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let mut task_inner = task.inner_exclusive_access();
+        self.catch_up(&mut task_inner.sched);
+        let level = task_inner.sched.level;
+        drop(task_inner);
+        self.queues[level].push_back(task);
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        self.queues.iter_mut().find_map(|queue| queue.pop_front())
+    }
+    fn steal(&mut self, hart: usize) -> Option<Arc<TaskControlBlock>> {
+        self.queues.iter_mut().rev().find_map(|queue| {
+            let pos = last_allowed(queue, hart)?;
+            queue.remove(pos)
+        })
+    }
+    fn len(&self) -> usize {
+        self.queues.iter().map(VecDeque::len).sum()
+    }
+    fn tick(&mut self, sched: &mut SchedEntity) -> bool {
+        self.ticks += 1;
+        if self.ticks % MLFQ_BOOST_TICKS == 0 {
+            self.boost();
+        }
+        self.catch_up(sched);
+        sched.ticks += 1;
+        if sched.ticks >= time_slice(sched.level, sched.nice) {
+            sched.level = (sched.level + 1).min(MLFQ_LEVELS - 1);
+            sched.ticks = 0;
+            return true;
+        }
+        // a task woken on a higher queue runs at once
+        self.queues[..sched.level]
+            .iter()
+            .any(|queue| !queue.is_empty())
+    }
+}
+
+/// ticks a round robin task runs before the next one of its priority
+const RR_TIME_SLICE: usize = 10;
+
+/// Real-time tasks, by priority and then in arrival order.
+pub struct RtScheduler {
+    queues: BTreeMap<usize, VecDeque<Arc<TaskControlBlock>>>,
+}
+
+impl RtScheduler {
+    pub fn new() -> Self {
+        Self {
+            queues: BTreeMap::new(),
+        }
+    }
+    /// Priority of the next task to run, 0 if there is none.
+    fn top_priority(&self) -> usize {
+        self.queues
+            .last_key_value()
+            .map_or(0, |(&priority, _)| priority)
+    }
+}
+
+impl Scheduler for RtScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let priority = task.inner_exclusive_access().sched.rt_priority;
+        self.queues.entry(priority).or_default().push_back(task);
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let mut queue = self.queues.last_entry()?;
+        let task = queue.get_mut().pop_front();
+        if queue.get().is_empty() {
+            queue.remove();
+        }
+        task
+    }
+    /// The most urgent task allowed on `hart`, it waits for a hart while this one is idle.
+    fn steal(&mut self, hart: usize) -> Option<Arc<TaskControlBlock>> {
+        let (priority, pos) = self.queues.iter().rev().find_map(|(&priority, queue)| {
+            let pos = queue.iter().position(|task| allowed_on(task, hart))?;
+            Some((priority, pos))
+        })?;
+        let queue = self.queues.get_mut(&priority).unwrap();
+        let task = queue.remove(pos);
+        if queue.is_empty() {
+            self.queues.remove(&priority);
+        }
+        task
+    }
+    fn len(&self) -> usize {
+        self.queues.values().map(VecDeque::len).sum()
+    }
+    fn tick(&mut self, sched: &mut SchedEntity) -> bool {
+        let top = self.top_priority();
+        if sched.policy == SchedPolicy::RoundRobin {
+            sched.ticks += 1;
+            if sched.ticks >= RR_TIME_SLICE {
+                sched.ticks = 0;
+                return top >= sched.rt_priority;
+            }
+        }
+        top > sched.rt_priority
+    }
+}
@@ -7,6 +7,8 @@ bitflags! {
         const SIGABRT   = 1 << 6;
         const SIGFPE    = 1 << 8;
         const SIGSEGV   = 1 << 11;
+        /// Not a signal, tells that the SIGSEGV came from a stack guard page.
+        const STACK_OVERFLOW = 1 << 31;
     }
 }
 
@@ -20,6 +22,8 @@ impl SignalFlags {
             Some((-6, "Aborted, SIGABRT=6"))
         } else if self.contains(Self::SIGFPE) {
             Some((-8, "Erroneous Arithmetic Operation, SIGFPE=8"))
+        } else if self.contains(Self::SIGSEGV | Self::STACK_OVERFLOW) {
+            Some((-11, "Stack Overflow, SIGSEGV=11"))
         } else if self.contains(Self::SIGSEGV) {
             Some((-11, "Segmentation Fault, SIGSEGV=11"))
         } else {
@@ -1,22 +1,29 @@
+use super::cputime::CpuClock;
 use super::id::TaskUserRes;
+use super::scheduler::SchedEntity;
 use super::{KernelStack, ProcessControlBlock, TaskContext, kstack_alloc};
 use crate::trap::TrapContext;
 use crate::{
     mm::PhysPageNum,
-    sync::{UPIntrFreeCell, UPIntrRefMut},
+    sync::{SpinNoIrqGuard, SpinNoIrqLock},
 };
 use alloc::sync::{Arc, Weak};
+use core::sync::atomic::AtomicBool;
 
 pub struct TaskControlBlock {
     // immutable
     pub process: Weak<ProcessControlBlock>,
     pub kstack: KernelStack,
+    /// set while a hart runs the task, until its context is saved
+    pub on_cpu: AtomicBool,
+    /// set while the task is in a syscall, which may hold slices of its user pages
+    pub in_syscall: AtomicBool,
     // mutable
-    pub inner: UPIntrFreeCell<TaskControlBlockInner>,
+    pub inner: SpinNoIrqLock<TaskControlBlockInner>,
 }
 
 impl TaskControlBlock {
-    pub fn inner_exclusive_access(&self) -> UPIntrRefMut<'_, TaskControlBlockInner> {
+    pub fn inner_exclusive_access(&self) -> SpinNoIrqGuard<'_, TaskControlBlockInner> {
         self.inner.exclusive_access()
     }
 
@@ -33,6 +40,8 @@ pub struct TaskControlBlockInner {
     pub task_cx: TaskContext,
     pub task_status: TaskStatus,
     pub exit_code: Option<i32>,
+    pub sched: SchedEntity,
+    pub cpu_clock: CpuClock,
 }
 
 impl TaskControlBlockInner {
@@ -59,13 +68,17 @@ impl TaskControlBlock {
         Self {
             process: Arc::downgrade(&process),
             kstack,
+            on_cpu: AtomicBool::new(false),
+            in_syscall: AtomicBool::new(false),
             inner: unsafe {
-                UPIntrFreeCell::new(TaskControlBlockInner {
+                SpinNoIrqLock::new(TaskControlBlockInner {
                     res: Some(res),
                     trap_cx_ppn,
                     task_cx: TaskContext::goto_trap_return(kstack_top),
                     task_status: TaskStatus::Ready,
                     exit_code: None,
+                    sched: SchedEntity::default(),
+                    cpu_clock: CpuClock::default(),
                 })
             },
         }
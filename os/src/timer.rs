@@ -2,7 +2,7 @@ use core::cmp::Ordering;
 
 use crate::config::CLOCK_FREQ;
 use crate::sbi::set_timer;
-use crate::sync::UPIntrFreeCell;
+use crate::sync::SpinNoIrqLock;
 use crate::task::{TaskControlBlock, wakeup_task};
 use alloc::collections::BinaryHeap;
 use alloc::sync::Arc;
@@ -20,6 +20,17 @@ pub fn get_time_ms() -> usize {
     time::read() / (CLOCK_FREQ / MSEC_PER_SEC)
 }
 
+/// Clock ticks of `times(2)` in `cycles`, which come at the rate of the timer interrupt.
+pub fn cycles_to_ticks(cycles: usize) -> usize {
+    cycles / (CLOCK_FREQ / TICKS_PER_SEC)
+}
+
+/// Microseconds in `cycles`, going through milliseconds since `CLOCK_FREQ` is not a
+/// multiple of a million.
+pub fn cycles_to_us(cycles: usize) -> usize {
+    cycles * MSEC_PER_SEC / (CLOCK_FREQ / MSEC_PER_SEC)
+}
+
 pub fn set_next_trigger() {
     set_timer(get_time() + CLOCK_FREQ / TICKS_PER_SEC);
 }
@@ -50,8 +61,8 @@ impl Ord for TimerCondVar {
 }
 
 lazy_static! {
-    static ref TIMERS: UPIntrFreeCell<BinaryHeap<TimerCondVar>> =
-        unsafe { UPIntrFreeCell::new(BinaryHeap::<TimerCondVar>::new()) };
+    static ref TIMERS: SpinNoIrqLock<BinaryHeap<TimerCondVar>> =
+        unsafe { SpinNoIrqLock::new(BinaryHeap::<TimerCondVar>::new()) };
 }
 
 pub fn add_timer(expire_ms: usize, task: Arc<TaskControlBlock>) {
@@ -59,6 +70,10 @@ pub fn add_timer(expire_ms: usize, task: Arc<TaskControlBlock>) {
     timers.push(TimerCondVar { expire_ms, task });
 }
 
+pub fn remove_timer(task: &Arc<TaskControlBlock>) {
+    TIMERS.exclusive_session(|timers| timers.retain(|timer| !Arc::ptr_eq(&timer.task, task)));
+}
+
 pub fn check_timer() {
     let current_ms = get_time_ms();
     TIMERS.exclusive_session(|timers| {
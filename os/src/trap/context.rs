@@ -9,6 +9,11 @@ pub struct TrapContext {
     pub kernel_satp: usize,
     pub kernel_sp: usize,
     pub trap_handler: usize,
+    /// hart the task returns to user space on, loaded into `tp` on the next trap
+    pub hart_id: usize,
+    /// nonzero if the user space shares the ASID of the kernel space,
+    /// the TLB is flushed when entering the kernel space then
+    pub flush_tlb: usize,
 }
 
 impl TrapContext {
@@ -32,6 +37,8 @@ impl TrapContext {
             kernel_satp,
             kernel_sp,
             trap_handler,
+            hart_id: 0,
+            flush_tlb: 0,
         };
         cx.set_sp(sp);
         cx
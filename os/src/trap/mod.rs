@@ -1,18 +1,22 @@
 mod context;
 
 use crate::config::TRAMPOLINE;
+use crate::hart::hart_id;
+use crate::mm::{VirtAddr, shares_kernel_asid};
 use crate::syscall::syscall;
 use crate::task::{
-    SignalFlags, check_signals_of_current, current_add_signal, current_trap_cx,
-    current_trap_cx_user_va, current_user_token, exit_current_and_run_next,
-    suspend_current_and_run_next,
+    SignalFlags, check_signals_of_current, current_add_signal, current_enter_kernel,
+    current_leave_kernel, current_process_exiting, current_task, current_trap_cx,
+    current_trap_cx_user_va, current_user_token, current_user_token_on_hart,
+    exit_current_and_run_next, handle_page_fault_of_current, is_stack_guard_of_current,
+    set_current_in_syscall, suspend_current_and_run_next, take_need_resched, tick_task,
 };
 use crate::timer::{check_timer, set_next_trigger};
 use core::arch::{asm, global_asm};
 use riscv::register::{
     mtvec::TrapMode,
     scause::{self, Exception, Interrupt, Trap},
-    sie, sscratch, sstatus, stval, stvec,
+    sie, sip, sscratch, sstatus, stval, stvec,
 };
 
 global_asm!(include_str!("trap.S"));
@@ -45,6 +49,13 @@ pub fn enable_timer_interrupt() {
     }
 }
 
+/// Take IPIs, which other harts send to preempt the task of this one.
+pub fn enable_software_interrupt() {
+    unsafe {
+        sie::set_ssoft();
+    }
+}
+
 fn enable_supervisor_interrupt() {
     unsafe {
         sstatus::set_sie();
@@ -60,6 +71,7 @@ fn disable_supervisor_interrupt() {
 #[unsafe(no_mangle)]
 pub fn trap_handler() -> ! {
     set_kernel_trap_entry();
+    current_enter_kernel();
     let scause = scause::read();
     let stval = stval::read();
     // println!("into {:?}", scause.cause());
@@ -72,17 +84,37 @@ pub fn trap_handler() -> ! {
             enable_supervisor_interrupt();
 
             // get system call return value
-            let result = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12]]);
+            set_current_in_syscall(true);
+            let result = syscall(
+                cx.x[17],
+                [cx.x[10], cx.x[11], cx.x[12], cx.x[13], cx.x[14], cx.x[15]],
+            );
+            set_current_in_syscall(false);
+            // the trap context is gone if the main thread exited meanwhile
+            if current_process_exiting() {
+                exit_current_and_run_next(0);
+            }
             // cx is changed during sys_exec, so we have to call it again
             cx = current_trap_cx();
             cx.x[10] = result as usize;
         }
-        Trap::Exception(Exception::StoreFault)
-        | Trap::Exception(Exception::StorePageFault)
-        | Trap::Exception(Exception::InstructionFault)
+        Trap::Exception(Exception::StorePageFault)
         | Trap::Exception(Exception::InstructionPageFault)
-        | Trap::Exception(Exception::LoadFault)
         | Trap::Exception(Exception::LoadPageFault) => {
+            // copy-on-write pages fault on the first store
+            let write = matches!(scause.cause(), Trap::Exception(Exception::StorePageFault));
+            let vpn = VirtAddr::from(stval).floor();
+            if !handle_page_fault_of_current(current_user_token(), vpn, write, true) {
+                if is_stack_guard_of_current(vpn) {
+                    current_add_signal(SignalFlags::SIGSEGV | SignalFlags::STACK_OVERFLOW);
+                } else {
+                    current_add_signal(SignalFlags::SIGSEGV);
+                }
+            }
+        }
+        Trap::Exception(Exception::StoreFault)
+        | Trap::Exception(Exception::InstructionFault)
+        | Trap::Exception(Exception::LoadFault) => {
             /*
             println!(
                 "[kernel] {:?} in application, bad addr = {:#x}, bad instruction = {:#x}, kernel killed it.",
@@ -99,11 +131,19 @@ pub fn trap_handler() -> ! {
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
             set_next_trigger();
             check_timer();
-            suspend_current_and_run_next();
+            if tick_task(&current_task().unwrap()) {
+                suspend_current_and_run_next();
+            }
         }
         Trap::Interrupt(Interrupt::SupervisorExternal) => {
             crate::board::irq_handler();
         }
+        Trap::Interrupt(Interrupt::SupervisorSoft) => {
+            // the reason is in NEED_RESCHED
+            unsafe {
+                sip::clear_ssoft();
+            }
+        }
         _ => {
             panic!(
                 "Unsupported trap {:?}, stval = {:#x}!",
@@ -112,6 +152,13 @@ pub fn trap_handler() -> ! {
             );
         }
     }
+    // a real-time task woke up with a higher priority
+    if take_need_resched() {
+        suspend_current_and_run_next();
+    }
+    if current_process_exiting() {
+        exit_current_and_run_next(0);
+    }
     // check signals
     if let Some((errno, msg)) = check_signals_of_current() {
         println!("[kernel] {}", msg);
@@ -125,10 +172,19 @@ pub fn trap_handler() -> ! {
 /// set the reg a0 = trap_cx_ptr, reg a1 = phy addr of usr page table,
 /// finally, jump to new addr of __restore asm function
 pub fn trap_return() -> ! {
+    // a new thread may start after the main thread exited
+    if current_process_exiting() {
+        exit_current_and_run_next(0);
+    }
     disable_supervisor_interrupt();
+    current_leave_kernel();
     set_user_trap_entry();
+    let user_satp = current_user_token_on_hart();
+    let flush_tlb = usize::from(shares_kernel_asid(user_satp));
+    let trap_cx = current_trap_cx();
+    trap_cx.hart_id = hart_id();
+    trap_cx.flush_tlb = flush_tlb;
     let trap_cx_user_va = current_trap_cx_user_va();
-    let user_satp = current_user_token();
     unsafe extern "C" {
         unsafe fn __alltraps();
         unsafe fn __restore();
@@ -142,6 +198,7 @@ pub fn trap_return() -> ! {
             restore_va = in(reg) restore_va,
             in("a0") trap_cx_user_va,
             in("a1") user_satp,
+            in("a2") flush_tlb,
             options(noreturn)
         );
     }
@@ -160,6 +217,12 @@ pub fn trap_from_kernel(_trap_cx: &TrapContext) {
             check_timer();
             // do not schedule now
         }
+        Trap::Interrupt(Interrupt::SupervisorSoft) => {
+            // the task of this hart is preempted on its way back to user space
+            unsafe {
+                sip::clear_ssoft();
+            }
+        }
         _ => {
             panic!(
                 "Unsupported trap from kernel: {:?}, stval = {:#x}!",
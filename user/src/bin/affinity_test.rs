@@ -0,0 +1,61 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::hint::black_box;
+use user_lib::{
+    Errno, exit, get_time, gettid, sched_getaffinity, sched_setaffinity, thread_create, waittid,
+};
+
+const THREADS: usize = 4;
+const SPIN_MS: isize = 500;
+
+/// Pin the calling thread to hart `hart` and spin there for a while.
+fn bench(hart: usize) -> ! {
+    let tid = gettid() as usize;
+    let mask = 1 << hart;
+    if sched_setaffinity(tid, mask) < 0 {
+        // fewer harts than threads
+        exit(-1);
+    }
+    let deadline = get_time() + SPIN_MS;
+    let mut rounds = 0usize;
+    while get_time() < deadline {
+        rounds = black_box(rounds + 1);
+    }
+    assert_eq!(sched_getaffinity(tid), mask as isize);
+    println!("thread#{} on hart {}: {} rounds", tid, hart, rounds);
+    exit(0)
+}
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let tid = gettid() as usize;
+    let all = sched_getaffinity(tid);
+    assert!(all & 1 != 0);
+    assert_eq!(sched_setaffinity(tid, 0), Errno::EINVAL.as_ret());
+    assert_eq!(sched_setaffinity(tid + 100, 1), Errno::ESRCH.as_ret());
+    assert_eq!(sched_getaffinity(tid + 100), Errno::ESRCH.as_ret());
+    // new threads start with the affinity of their creator
+    assert_eq!(sched_setaffinity(tid, 1), 0);
+    let child = thread_create(bench_inherited as usize, 0) as usize;
+    assert_eq!(waittid(child), 1);
+    assert_eq!(sched_setaffinity(tid, all as usize), 0);
+    let threads: Vec<usize> = (0..THREADS)
+        .map(|hart| thread_create(bench as usize, hart) as usize)
+        .collect();
+    for tid in threads {
+        let exit_code = waittid(tid);
+        assert!(exit_code == 0 || exit_code == -1);
+    }
+    println!("affinity_test passed!");
+    0
+}
+
+fn bench_inherited() -> ! {
+    exit(sched_getaffinity(gettid() as usize) as i32)
+}
@@ -0,0 +1,32 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{brk, sbrk};
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    // the break moves up and down and the memory in between is usable
+    let start = brk(0);
+    assert!(start > 0);
+    assert_eq!(sbrk(4096 * 2), start);
+    let buf = unsafe { core::slice::from_raw_parts_mut(start as *mut u8, 4096 * 2) };
+    buf.fill(0x5a);
+    assert!(buf.iter().all(|&b| b == 0x5a));
+    assert_eq!(brk(start as usize), start);
+    // the break can not go below where it started
+    assert_eq!(brk(start as usize - 4096), start);
+    // the allocator grows far beyond the initial 32 KiB
+    let mut v: Vec<usize> = Vec::new();
+    for i in 0..256 * 1024 {
+        v.push(i);
+    }
+    assert!(v.iter().enumerate().all(|(i, &x)| i == x));
+    assert!(brk(0) > start);
+    println!("brk_test passed!");
+    0
+}
@@ -0,0 +1,42 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, fork, pipe, read, wait, write};
+
+const LEN: usize = 4096 * 4;
+static mut DATA: [u8; LEN] = [1; LEN];
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let data = unsafe { &mut *core::ptr::addr_of_mut!(DATA) };
+    let mut pipe_fd = [0usize; 2];
+    pipe(&mut pipe_fd);
+    let pid = fork();
+    if pid == 0 {
+        // writes of the child stay private to it
+        close(pipe_fd[1]);
+        data.fill(2);
+        assert!(data.iter().all(|&b| b == 2));
+        // the kernel writes into a shared page too
+        let mut buf = [0u8; 4];
+        assert_eq!(read(pipe_fd[0], &mut buf), 4);
+        assert_eq!(&buf, b"ping");
+        close(pipe_fd[0]);
+        0
+    } else {
+        close(pipe_fd[0]);
+        assert_eq!(write(pipe_fd[1], b"ping"), 4);
+        close(pipe_fd[1]);
+        let mut exit_code: i32 = 0;
+        assert_eq!(wait(&mut exit_code), pid);
+        assert_eq!(exit_code, 0);
+        assert!(data.iter().all(|&b| b == 1));
+        data.fill(3);
+        assert!(data.iter().all(|&b| b == 3));
+        println!("cow_test passed!");
+        0
+    }
+}
@@ -0,0 +1,82 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::hint::black_box;
+use user_lib::{
+    CLOCKS_PER_SEC, RUSAGE_CHILDREN, RUSAGE_SELF, RUSAGE_THREAD, RUsage, Tms, exit, fork, get_time,
+    getpid, getrusage, times, waitpid_rusage,
+};
+
+const SPIN_MS: isize = 300;
+
+/// Spin in user space for `SPIN_MS`.
+fn spin_user() {
+    let deadline = get_time() + SPIN_MS;
+    let mut rounds = 0usize;
+    while get_time() < deadline {
+        for _ in 0..1000 {
+            rounds = black_box(rounds + 1);
+        }
+    }
+}
+
+/// Spin in the kernel for `SPIN_MS`, through cheap system calls.
+fn spin_kernel() {
+    let deadline = get_time() + SPIN_MS;
+    while get_time() < deadline {
+        getpid();
+    }
+}
+
+fn usage(who: isize) -> RUsage {
+    let mut usage = RUsage::default();
+    assert_eq!(getrusage(who, &mut usage), 0);
+    usage
+}
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let before = usage(RUSAGE_SELF);
+    spin_user();
+    let after = usage(RUSAGE_SELF);
+    let user_us = after.ru_utime.as_us() - before.ru_utime.as_us();
+    println!("user spin: {}us user", user_us);
+    assert!(user_us > 0);
+    assert!(usage(RUSAGE_THREAD).ru_utime.as_us() <= usage(RUSAGE_SELF).ru_utime.as_us());
+    assert!(getrusage(7, &mut RUsage::default()) < 0);
+
+    // the child spins in the kernel, its time shows up on waitpid and in the children
+    let pid = fork();
+    if pid == 0 {
+        spin_kernel();
+        exit(0);
+    }
+    let mut exit_code = 0;
+    let mut child = RUsage::default();
+    assert_eq!(
+        waitpid_rusage(pid as usize, &mut exit_code, &mut child),
+        pid
+    );
+    println!(
+        "child: {}us user, {}us sys",
+        child.ru_utime.as_us(),
+        child.ru_stime.as_us()
+    );
+    assert!(child.ru_stime.as_us() > 0);
+    let children = usage(RUSAGE_CHILDREN);
+    assert_eq!(children.ru_stime.as_us(), child.ru_stime.as_us());
+
+    let mut tms = Tms::default();
+    let ticks = times(&mut tms);
+    assert!(ticks > 0);
+    assert!(tms.tms_cstime as isize <= ticks);
+    println!(
+        "times: {} ticks since boot at {} per second, {} user ticks",
+        ticks, CLOCKS_PER_SEC, tms.tms_utime
+    );
+    println!("cputime_test passed!");
+    0
+}
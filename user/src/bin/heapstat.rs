@@ -0,0 +1,24 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{HeapStats, heap_stats};
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let mut stats = HeapStats::default();
+    if heap_stats(&mut stats) != 0 {
+        println!("heapstat: failed to read kernel heap stats");
+        return -1;
+    }
+    println!(
+        "kernel heap: {} KiB total, {} KiB in use, {} KiB peak, {} failed allocations",
+        stats.total / 1024,
+        stats.in_use / 1024,
+        stats.peak / 1024,
+        stats.failed
+    );
+    0
+}
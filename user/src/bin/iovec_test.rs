@@ -0,0 +1,36 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{IoVec, IoVecMut, OpenFlags, close, open, readv, writev};
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let header = b"HDR:";
+    let payload = b"hello, iovec!";
+    let fd = open("iovec_test.txt\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd >= 0);
+    let fd = fd as usize;
+    // header and payload go out in one call without being copied together
+    let iov = [IoVec::new(header), IoVec::new(payload)];
+    assert_eq!(writev(fd, &iov), (header.len() + payload.len()) as isize);
+    close(fd);
+
+    let fd = open("iovec_test.txt\0", OpenFlags::RDONLY);
+    assert!(fd >= 0);
+    let fd = fd as usize;
+    let mut header_buf = [0u8; 4];
+    let mut payload_buf = [0u8; 13];
+    let mut iov = [
+        IoVecMut::new(&mut header_buf),
+        IoVecMut::new(&mut payload_buf),
+    ];
+    assert_eq!(readv(fd, &mut iov), 17);
+    close(fd);
+    assert_eq!(&header_buf, header);
+    assert_eq!(&payload_buf, payload);
+    println!("iovec test passed!");
+    0
+}
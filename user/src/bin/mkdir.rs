@@ -0,0 +1,21 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::mkdir;
+
+#[unsafe(no_mangle)]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc != 2 {
+        println!("Usage: mkdir <dir>");
+        return 1;
+    }
+    let dir_name = argv[1];
+    if mkdir(dir_name) < 0 {
+        println!("mkdir: cannot create directory '{}'", dir_name);
+        return 1;
+    }
+    0
+}
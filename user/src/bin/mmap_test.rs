@@ -0,0 +1,58 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{MmapFlags, MmapProt, OpenFlags, close, mmap, munmap, open, read, write};
+
+const LEN: usize = 16 * 4096;
+static CONTENT: &[u8] = b"memory mapped file";
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    // anonymous memory is zeroed and writable
+    let addr = mmap(
+        LEN,
+        MmapProt::READ | MmapProt::WRITE,
+        MmapFlags::PRIVATE | MmapFlags::ANONYMOUS,
+        0,
+        0,
+    );
+    assert!(addr > 0);
+    let buf = unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, LEN) };
+    assert!(buf.iter().all(|&b| b == 0));
+    for (i, b) in buf.iter_mut().enumerate() {
+        *b = i as u8;
+    }
+    assert!(buf.iter().enumerate().all(|(i, &b)| b == i as u8));
+    assert_eq!(munmap(addr as usize, LEN), 0);
+
+    // a shared file mapping is written back on munmap
+    let fd = open("mmap_test.txt\0", OpenFlags::CREATE | OpenFlags::RDWR);
+    assert!(fd >= 0);
+    let fd = fd as usize;
+    assert_eq!(write(fd, CONTENT), CONTENT.len() as isize);
+    let addr = mmap(
+        CONTENT.len(),
+        MmapProt::READ | MmapProt::WRITE,
+        MmapFlags::SHARED,
+        fd,
+        0,
+    );
+    assert!(addr > 0);
+    let mapped = unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, CONTENT.len()) };
+    assert_eq!(mapped, CONTENT);
+    mapped[0] = b'M';
+    assert_eq!(munmap(addr as usize, CONTENT.len()), 0);
+    close(fd);
+
+    let fd = open("mmap_test.txt\0", OpenFlags::RDONLY);
+    assert!(fd >= 0);
+    let mut buffer = [0u8; 32];
+    let len = read(fd as usize, &mut buffer) as usize;
+    close(fd as usize);
+    assert_eq!(&buffer[..len], b"Memory mapped file");
+    println!("mmap test passed!");
+    0
+}
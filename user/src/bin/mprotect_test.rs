@@ -0,0 +1,86 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::hint::black_box;
+use user_lib::{MmapFlags, MmapProt, exit, fork, mmap, mprotect, sbrk, wait};
+
+const LEN: usize = 4096 * 4;
+
+/// Run `f` in a child and return its exit code.
+fn run_child(f: impl FnOnce()) -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        f();
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(wait(&mut exit_code), pid);
+    exit_code
+}
+
+fn recurse(depth: usize) -> usize {
+    let frame = black_box([depth; 64]);
+    frame[0] + recurse(depth + 1)
+}
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let addr = mmap(
+        LEN,
+        MmapProt::READ | MmapProt::WRITE,
+        MmapFlags::PRIVATE | MmapFlags::ANONYMOUS,
+        0,
+        0,
+    );
+    assert!(addr > 0);
+    let page = |i: usize| (addr as usize + i * 4096) as *mut u8;
+    for i in 0..LEN / 4096 {
+        unsafe { page(i).write_volatile(7) };
+    }
+    // the second page becomes read-only
+    assert_eq!(mprotect(page(1) as usize, 4096, MmapProt::READ), 0);
+    assert_eq!(unsafe { page(1).read_volatile() }, 7);
+    assert_eq!(run_child(|| unsafe { page(1).write_volatile(0) }), -11);
+    // the last page becomes inaccessible, its content is kept
+    assert_eq!(mprotect(page(3) as usize, 4096, MmapProt::empty()), 0);
+    assert_eq!(
+        run_child(|| unsafe {
+            black_box(page(3).read_volatile());
+        }),
+        -11
+    );
+    assert_eq!(
+        mprotect(addr as usize, LEN, MmapProt::READ | MmapProt::WRITE),
+        0
+    );
+    let buf = unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, LEN) };
+    assert!((0..LEN / 4096).all(|i| buf[i * 4096] == 7));
+    buf.fill(8);
+    // pages not mapped by mmap can not be changed
+    assert!(mprotect(0, 4096, MmapProt::READ) < 0);
+    // the heap can be protected too, and still moves with the break
+    let start = sbrk(4096 * 3);
+    assert!(start > 0);
+    let heap_page = ((start as usize + 4095) & !4095) as *mut u8;
+    assert_eq!(mprotect(heap_page as usize, 4096, MmapProt::READ), 0);
+    assert_eq!(run_child(|| unsafe { heap_page.write_volatile(0) }), -11);
+    assert!(sbrk(4096) > 0);
+    assert_eq!(
+        mprotect(heap_page as usize, 4096, MmapProt::READ | MmapProt::WRITE),
+        0
+    );
+    unsafe { heap_page.write_volatile(1) };
+    assert!(sbrk(-4096 * 4) > 0);
+    // running off a user stack hits its guard page
+    assert_eq!(
+        run_child(|| {
+            recurse(0);
+        }),
+        -11
+    );
+    println!("mprotect_test passed!");
+    0
+}
@@ -0,0 +1,66 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::hint::black_box;
+use user_lib::{
+    exit, fork, get_time, getpid, getpriority, gettid, nice, sched_setaffinity, setpriority, wait,
+    waitpid,
+};
+
+const SPIN_MS: isize = 1000;
+
+/// Spin on hart 0 with niceness `nice` until `deadline` and exit with how far it got.
+fn spin(nice: isize, deadline: isize) -> ! {
+    assert_eq!(setpriority(0, nice), 0);
+    // compete with the other children for a single hart
+    assert_eq!(sched_setaffinity(gettid() as usize, 1), 0);
+    let mut rounds = 0usize;
+    while get_time() < deadline {
+        for _ in 0..1000 {
+            rounds = black_box(rounds + 1);
+        }
+    }
+    println!("nice {:>3}: {} rounds", nice, rounds);
+    exit((rounds / 1000) as i32);
+}
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    assert_eq!(getpriority(0), 20);
+    assert_eq!(setpriority(0, 5), 0);
+    assert_eq!(getpriority(getpid() as usize), 15);
+    assert_eq!(nice(-3), 0);
+    assert_eq!(getpriority(0), 18);
+    // out of range values are clamped
+    assert_eq!(setpriority(0, 100), 0);
+    assert_eq!(getpriority(0), 1);
+    assert!(setpriority(usize::MAX, 0) < 0);
+    // children inherit the niceness
+    let pid = fork();
+    if pid == 0 {
+        exit(getpriority(0) as i32);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(wait(&mut exit_code), pid);
+    assert_eq!(exit_code, 1);
+    assert_eq!(setpriority(0, 0), 0);
+    // the share of the hart follows the niceness, except with SCHED=fifo
+    let deadline = get_time() + SPIN_MS;
+    let pids = [0, 5, 10].map(|nice| {
+        let pid = fork();
+        if pid == 0 {
+            spin(nice, deadline);
+        }
+        pid as usize
+    });
+    let rounds = pids.map(|pid| {
+        assert_eq!(waitpid(pid, &mut exit_code), pid as isize);
+        exit_code
+    });
+    assert!(rounds[0] > rounds[1] && rounds[1] > rounds[2]);
+    println!("nice_test passed!");
+    0
+}
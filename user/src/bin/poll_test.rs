@@ -0,0 +1,43 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{PollEvents, PollFd, close, fork, pipe, poll, read, sleep, wait, write};
+
+static STR: &str = "Hello, poll!";
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let mut pipe_fd = [0usize; 2];
+    pipe(&mut pipe_fd);
+    let mut fds = [PollFd::new(pipe_fd[0], PollEvents::IN)];
+    // nothing has been written yet
+    assert_eq!(poll(&mut fds, 50), 0);
+    if fork() == 0 {
+        // child process, write after a while and exit
+        close(pipe_fd[0]);
+        sleep(100);
+        assert_eq!(write(pipe_fd[1], STR.as_bytes()), STR.len() as isize);
+        close(pipe_fd[1]);
+        0
+    } else {
+        close(pipe_fd[1]);
+        // block until the child writes
+        assert_eq!(poll(&mut fds, -1), 1);
+        assert!(fds[0].revents().contains(PollEvents::IN));
+        let mut buffer = [0u8; 32];
+        let len_read = read(pipe_fd[0], &mut buffer) as usize;
+        assert_eq!(core::str::from_utf8(&buffer[..len_read]).unwrap(), STR);
+        let mut child_exit_code: i32 = 0;
+        wait(&mut child_exit_code);
+        assert_eq!(child_exit_code, 0);
+        // all write ends are closed now
+        assert_eq!(poll(&mut fds, -1), 1);
+        assert!(fds[0].revents().contains(PollEvents::HUP));
+        close(pipe_fd[0]);
+        println!("polltest passed!");
+        0
+    }
+}
@@ -0,0 +1,75 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::hint::black_box;
+use core::sync::atomic::{AtomicBool, Ordering};
+use user_lib::{
+    Errno, SCHED_FIFO, SCHED_NORMAL, SCHED_RR, exit, get_time, gettid, sched_getparam,
+    sched_getscheduler, sched_setaffinity, sched_setscheduler, sleep, thread_create, waittid,
+};
+
+const SPINNERS: usize = 3;
+const ROUNDS: usize = 20;
+const PERIOD_MS: usize = 10;
+/// a sleep ends on the next timer tick, one more tick is slack
+const MAX_LATENCY_MS: isize = 20;
+
+const EINVAL: isize = Errno::EINVAL.as_ret();
+const ESRCH: isize = Errno::ESRCH.as_ret();
+
+static STOP: AtomicBool = AtomicBool::new(false);
+
+fn spinner() -> ! {
+    let mut rounds = 0usize;
+    while !STOP.load(Ordering::Relaxed) {
+        rounds = black_box(rounds + 1);
+    }
+    exit(0)
+}
+
+/// Wake up every `PERIOD_MS` and return the worst lateness.
+fn periodic() -> ! {
+    let tid = gettid() as usize;
+    assert_eq!(sched_setscheduler(tid, SCHED_FIFO, 50), 0);
+    assert_eq!(sched_getscheduler(tid), SCHED_FIFO as isize);
+    assert_eq!(sched_getparam(tid), 50);
+    let mut worst = 0;
+    for _ in 0..ROUNDS {
+        let start = get_time();
+        sleep(PERIOD_MS);
+        worst = worst.max(get_time() - start - PERIOD_MS as isize);
+    }
+    exit(worst as i32)
+}
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let tid = gettid() as usize;
+    assert_eq!(sched_getscheduler(tid), SCHED_NORMAL as isize);
+    assert_eq!(sched_getparam(tid), 0);
+    assert_eq!(sched_setscheduler(tid, SCHED_RR, 0), EINVAL);
+    assert_eq!(sched_setscheduler(tid, SCHED_FIFO, 100), EINVAL);
+    assert_eq!(sched_setscheduler(tid, SCHED_NORMAL, 1), EINVAL);
+    assert_eq!(sched_setscheduler(tid, 7, 0), EINVAL);
+    assert_eq!(sched_setscheduler(tid + 100, SCHED_RR, 1), ESRCH);
+    // keep every thread on one hart so the spinners compete with the periodic thread
+    assert_eq!(sched_setaffinity(tid, 1), 0);
+    let spinners: Vec<usize> = (0..SPINNERS)
+        .map(|_| thread_create(spinner as usize, 0) as usize)
+        .collect();
+    let periodic = thread_create(periodic as usize, 0) as usize;
+    let worst = waittid(periodic);
+    STOP.store(true, Ordering::Relaxed);
+    for tid in spinners {
+        assert_eq!(waittid(tid), 0);
+    }
+    println!("worst wakeup latency: {}ms", worst);
+    assert!(worst <= MAX_LATENCY_MS);
+    println!("rt_test passed!");
+    0
+}
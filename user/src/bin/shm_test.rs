@@ -0,0 +1,53 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{IPC_RMID, ShmFlags, exit, fork, munmap, shmat, shmctl, shmdt, shmget, wait};
+
+const KEY: usize = 0x5348;
+const LEN: usize = 4096 * 16;
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let id = shmget(KEY, LEN, ShmFlags::IPC_CREAT);
+    assert!(id > 0);
+    assert!(shmget(KEY, LEN, ShmFlags::IPC_CREAT | ShmFlags::IPC_EXCL) < 0);
+    let pid = fork();
+    if pid == 0 {
+        // the child attaches by key and fills the buffer
+        let id = shmget(KEY, LEN, ShmFlags::empty());
+        assert!(id > 0);
+        let addr = shmat(id as usize, ShmFlags::empty());
+        assert!(addr > 0);
+        let buf = unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, LEN) };
+        for (i, b) in buf.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        assert_eq!(shmdt(addr as usize), 0);
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(wait(&mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    // the parent sees what the child wrote without any copy
+    let addr = shmat(id as usize, ShmFlags::SHM_RDONLY);
+    assert!(addr > 0);
+    let buf = unsafe { core::slice::from_raw_parts(addr as *const u8, LEN) };
+    assert!(buf.iter().enumerate().all(|(i, &b)| b == i as u8));
+    // what is left after unmapping the head still maps the right pages
+    let second = shmat(id as usize, ShmFlags::empty());
+    assert!(second > 0);
+    assert_eq!(munmap(second as usize, 4096), 0);
+    let tail = unsafe { core::slice::from_raw_parts((second as usize + 4096) as *const u8, 4096) };
+    assert!(tail.iter().enumerate().all(|(i, &b)| b == (4096 + i) as u8));
+    assert_eq!(shmdt(second as usize), 0);
+    // removing only forgets the key, the attached segment stays usable
+    assert_eq!(shmctl(id as usize, IPC_RMID), 0);
+    assert!(shmget(KEY, LEN, ShmFlags::empty()) < 0);
+    assert_eq!(buf[1], 1);
+    assert_eq!(shmdt(addr as usize), 0);
+    println!("shm_test passed!");
+    0
+}
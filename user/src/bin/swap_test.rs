@@ -0,0 +1,27 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::sbrk;
+
+const PAGE_SIZE: usize = 4096;
+/// more than the 128 MiB of the qemu machine, the rest has to be swapped out
+const SIZE: usize = 160 * 1024 * 1024;
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let start = sbrk(SIZE as isize);
+    assert!(start > 0);
+    let start = start as usize;
+    for (i, addr) in (start..start + SIZE).step_by(PAGE_SIZE).enumerate() {
+        unsafe { (addr as *mut usize).write_volatile(i) };
+    }
+    println!("swap_test: pages written");
+    for (i, addr) in (start..start + SIZE).step_by(PAGE_SIZE).enumerate() {
+        assert_eq!(unsafe { (addr as *const usize).read_volatile() }, i);
+    }
+    println!("swap_test passed!");
+    0
+}
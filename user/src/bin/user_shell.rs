@@ -16,7 +16,9 @@ const LINE_START: &str = ">> ";
 use alloc::string::String;
 use alloc::vec::Vec;
 use user_lib::console::getchar;
-use user_lib::{OpenFlags, close, dup, exec, fork, open, pipe, waitpid};
+use user_lib::{
+    OpenFlags, RUsage, chdir, close, dup2, exec, fork, get_time, getcwd, open, pipe, waitpid_rusage,
+};
 
 #[derive(Debug)]
 struct ProcessArguments {
@@ -74,6 +76,42 @@ impl ProcessArguments {
     }
 }
 
+/// Run `cd` and `pwd` inside the shell itself, since a child process
+/// cannot change the working directory of its parent.
+fn run_builtin(args: &[String]) -> bool {
+    match args.first().map(|arg| arg.as_str()) {
+        Some("cd\0") => {
+            let path = args.get(1).map_or("/\0", |arg| arg.as_str());
+            if chdir(path) < 0 {
+                println!("cd: {}: No such directory", path.trim_end_matches('\0'));
+            }
+            true
+        }
+        Some("pwd\0") => {
+            let mut buf = [0u8; 256];
+            let len = getcwd(&mut buf);
+            if len < 0 {
+                println!("pwd: cannot get current directory");
+            } else {
+                println!("{}", core::str::from_utf8(&buf[..len as usize - 1]).unwrap());
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Print `us` microseconds under `name`, like the `time` of bash.
+fn print_time(name: &str, us: usize) {
+    println!(
+        "{}\t{}m{}.{:03}s",
+        name,
+        us / 60_000_000,
+        us / 1_000_000 % 60,
+        us / 1000 % 1000
+    );
+}
+
 #[unsafe(no_mangle)]
 pub fn main() -> i32 {
     println!("Rust user shell");
@@ -85,7 +123,13 @@ pub fn main() -> i32 {
             LF | CR => {
                 println!("");
                 if !line.is_empty() {
-                    let splited: Vec<_> = line.as_str().split('|').collect();
+                    // `time cmd` reports the real and CPU time of cmd when it is done
+                    let (timed, command) = match line.strip_prefix("time ") {
+                        Some(command) => (true, command),
+                        None => (false, line.as_str()),
+                    };
+                    let start = get_time();
+                    let splited: Vec<_> = command.split('|').collect();
                     let process_arguments_list: Vec<_> = splited
                         .iter()
                         .map(|&cmd| ProcessArguments::new(cmd))
@@ -108,7 +152,11 @@ pub fn main() -> i32 {
                     if process_arguments_list.len() == 1 {
                         valid = true;
                     }
-                    if !valid {
+                    if process_arguments_list.len() == 1
+                        && run_builtin(&process_arguments_list[0].args_copy)
+                    {
+                        // builtin command has been done by the shell
+                    } else if !valid {
                         println!("Invalid command: Inputs/Outputs cannot be correctly binded!");
                     } else {
                         // create pipes
@@ -132,13 +180,12 @@ pub fn main() -> i32 {
                                 // redirect input
                                 if !input.is_empty() {
                                     let input_fd = open(input.as_str(), OpenFlags::RDONLY);
-                                    if input_fd == -1 {
+                                    if input_fd < 0 {
                                         println!("Error when opening file {}", input);
                                         return -4;
                                     }
                                     let input_fd = input_fd as usize;
-                                    close(0);
-                                    assert_eq!(dup(input_fd), 0);
+                                    assert_eq!(dup2(input_fd, 0), 0);
                                     close(input_fd);
                                 }
                                 // redirect output
@@ -147,41 +194,37 @@ pub fn main() -> i32 {
                                         output.as_str(),
                                         OpenFlags::CREATE | OpenFlags::WRONLY,
                                     );
-                                    if output_fd == -1 {
+                                    if output_fd < 0 {
                                         println!("Error when opening file {}", output);
                                         return -4;
                                     }
                                     let output_fd = output_fd as usize;
-                                    close(1);
-                                    assert_eq!(dup(output_fd), 1);
+                                    assert_eq!(dup2(output_fd, 1), 1);
                                     close(output_fd);
                                 }
                                 // 当前进程不是第一个进程时，重定向输入到前一个管道的读端
                                 if i > 0 {
-                                    // 关闭当前进程的标准输入文件描述符
-                                    close(0);
-                                    // 重定向标准输入到前一个管道的读端
                                     let read_end = pipes_fd.get(i - 1).unwrap()[0];
-                                    // 将管道的读端设置到进程的标准输入文件描述符上，
-                                    // 在调用代码中会获取到一个新的文件描述符，
-                                    // 此时获取到的描述符为上方代码中调用 close 释放掉的标准IO
-                                    assert_eq!(dup(read_end), 0);
+                                    // 将管道的读端直接放到标准输入文件描述符上，原有的标准输入会被关闭
+                                    assert_eq!(dup2(read_end, 0), 0);
                                 }
                                 // 当前进程不是最后一个进程时，重定向输出到当前管道的写端
                                 if i < process_arguments_list.len() - 1 {
-                                    // 关闭当前进程的标准输出文件描述符
-                                    close(1);
                                     // 效果与上方类似，重定向标准输出到当前管道的写端
                                     let write_end = pipes_fd.get(i).unwrap()[1];
-                                    assert_eq!(dup(write_end), 1);
+                                    assert_eq!(dup2(write_end, 1), 1);
                                 }
                                 // 管道的两端都已经重定向完毕，关闭原始的管道文件描述符
                                 for pipe_fd in pipes_fd.iter() {
                                     close(pipe_fd[0]);
                                     close(pipe_fd[1]);
                                 }
-                                // 执行子进程
-                                if exec(args_copy[0].as_str(), args_addr.as_slice()) == -1 {
+                                // 执行子进程，当前目录下找不到时回退到存放所有应用的根目录
+                                let mut root_path = String::from("/");
+                                root_path.push_str(args_copy[0].as_str());
+                                if exec(args_copy[0].as_str(), args_addr.as_slice()) < 0
+                                    && exec(root_path.as_str(), args_addr.as_slice()) < 0
+                                {
                                     println!("Error when executing!");
                                     return -4;
                                 }
@@ -195,10 +238,21 @@ pub fn main() -> i32 {
                             close(pipe_fd[1]);
                         }
                         let mut exit_code: i32 = 0;
+                        let (mut user_us, mut sys_us) = (0, 0);
                         for pid in children.into_iter() {
-                            let exit_pid = waitpid(pid as usize, &mut exit_code);
+                            let mut rusage = RUsage::default();
+                            let exit_pid =
+                                waitpid_rusage(pid as usize, &mut exit_code, &mut rusage);
                             assert_eq!(pid, exit_pid);
                             //println!("Shell: Process {} exited with code {}", pid, exit_code);
+                            user_us += rusage.ru_utime.as_us();
+                            sys_us += rusage.ru_stime.as_us();
+                        }
+                        if timed {
+                            println!("");
+                            print_time("real", (get_time() - start) as usize * 1000);
+                            print_time("user", user_us);
+                            print_time("sys", sys_us);
                         }
                     }
                     line.clear();
@@ -0,0 +1,57 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exec, fork, waitpid};
+
+// every test prints "... passed!" and exits with 0 on success
+static TESTS: &[&str] = &[
+    "affinity_test\0",
+    "brk_test\0",
+    "condsync_condvar\0",
+    "cow_test\0",
+    "cputime_test\0",
+    "iovec_test\0",
+    "mmap_test\0",
+    "mprotect_test\0",
+    "mpsc_sem\0",
+    "nice_test\0",
+    "pipe_test\0",
+    "poll_test\0",
+    "rt_test\0",
+    "shm_test\0",
+    "swap_test\0",
+    "sync_sem\0",
+];
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let mut failed = 0;
+    for test in TESTS {
+        let name = test.trim_end_matches('\0');
+        println!("usertests: running {}", name);
+        let pid = fork();
+        if pid == 0 {
+            if exec(test, &[core::ptr::null::<u8>()]) == -1 {
+                println!("usertests: error when executing {}", name);
+                return -4;
+            }
+            unreachable!();
+        }
+        let mut exit_code: i32 = 0;
+        let wait_pid = waitpid(pid as usize, &mut exit_code);
+        assert_eq!(pid, wait_pid);
+        if exit_code != 0 {
+            println!("usertests: {} failed with exit_code {}", name, exit_code);
+            failed += 1;
+        }
+    }
+    if failed != 0 {
+        println!("usertests: {}/{} tests failed!", failed, TESTS.len());
+        return -1;
+    }
+    println!("usertests passed!");
+    0
+}
@@ -0,0 +1,97 @@
+use core::fmt::{self, Debug, Display, Formatter};
+
+/// Linux-compatible error number of a failed syscall.
+/// The kernel returns it negated, e.g. `open` returns `-2` for ENOENT.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Errno(pub isize);
+
+impl Errno {
+    pub const EPERM: Self = Self(1);
+    pub const ENOENT: Self = Self(2);
+    pub const ESRCH: Self = Self(3);
+    pub const EINTR: Self = Self(4);
+    pub const EIO: Self = Self(5);
+    pub const E2BIG: Self = Self(7);
+    pub const ENOEXEC: Self = Self(8);
+    pub const EBADF: Self = Self(9);
+    pub const ECHILD: Self = Self(10);
+    pub const EAGAIN: Self = Self(11);
+    pub const ENOMEM: Self = Self(12);
+    pub const EACCES: Self = Self(13);
+    pub const EFAULT: Self = Self(14);
+    pub const EBUSY: Self = Self(16);
+    pub const EEXIST: Self = Self(17);
+    pub const ENODEV: Self = Self(19);
+    pub const ENOTDIR: Self = Self(20);
+    pub const EISDIR: Self = Self(21);
+    pub const EINVAL: Self = Self(22);
+    pub const EMFILE: Self = Self(24);
+    pub const ENOSPC: Self = Self(28);
+    pub const ESPIPE: Self = Self(29);
+    pub const EPIPE: Self = Self(32);
+    pub const ERANGE: Self = Self(34);
+    pub const EDEADLK: Self = Self(35);
+    pub const ENAMETOOLONG: Self = Self(36);
+    pub const ENOSYS: Self = Self(38);
+    pub const EADDRINUSE: Self = Self(98);
+
+    /// Split the return value of a syscall into a result.
+    pub fn from_ret(ret: isize) -> Result<usize, Self> {
+        if ret < 0 {
+            Err(Self(-ret))
+        } else {
+            Ok(ret as usize)
+        }
+    }
+
+    /// The value returned by the kernel for this error.
+    pub const fn as_ret(self) -> isize {
+        -self.0
+    }
+
+    pub fn description(&self) -> &'static str {
+        match *self {
+            Self::EPERM => "Operation not permitted",
+            Self::ENOENT => "No such file or directory",
+            Self::ESRCH => "No such process",
+            Self::EINTR => "Interrupted system call",
+            Self::EIO => "I/O error",
+            Self::E2BIG => "Argument list too long",
+            Self::ENOEXEC => "Exec format error",
+            Self::EBADF => "Bad file number",
+            Self::ECHILD => "No child processes",
+            Self::EAGAIN => "Try again",
+            Self::ENOMEM => "Out of memory",
+            Self::EACCES => "Permission denied",
+            Self::EFAULT => "Bad address",
+            Self::EBUSY => "Device or resource busy",
+            Self::EEXIST => "File exists",
+            Self::ENODEV => "No such device",
+            Self::ENOTDIR => "Not a directory",
+            Self::EISDIR => "Is a directory",
+            Self::EINVAL => "Invalid argument",
+            Self::EMFILE => "Too many open files",
+            Self::ENOSPC => "No space left on device",
+            Self::ESPIPE => "Illegal seek",
+            Self::EPIPE => "Broken pipe",
+            Self::ERANGE => "Math result not representable",
+            Self::EDEADLK => "Resource deadlock would occur",
+            Self::ENAMETOOLONG => "File name too long",
+            Self::ENOSYS => "Function not implemented",
+            Self::EADDRINUSE => "Address already in use",
+            _ => "Unknown error",
+        }
+    }
+}
+
+impl Debug for Errno {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!("Errno({}: {})", self.0, self.description()))
+    }
+}
+
+impl Display for Errno {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
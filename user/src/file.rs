@@ -1,4 +1,5 @@
 use super::*;
+use core::marker::PhantomData;
 
 bitflags! {
     pub struct OpenFlags: u32 {
@@ -7,12 +8,109 @@ bitflags! {
         const RDWR = 1 << 1;
         const CREATE = 1 << 9;
         const TRUNC = 1 << 10;
+        const NONBLOCK = 1 << 11;
+        const CLOEXEC = 1 << 19;
     }
 }
 
+bitflags! {
+    pub struct PollEvents: i16 {
+        const IN = 1 << 0;
+        const OUT = 1 << 2;
+        const ERR = 1 << 3;
+        const HUP = 1 << 4;
+        const NVAL = 1 << 5;
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct PollFd {
+    pub fd: i32,
+    pub events: i16,
+    pub revents: i16,
+}
+
+impl PollFd {
+    pub fn new(fd: usize, events: PollEvents) -> Self {
+        Self {
+            fd: fd as i32,
+            events: events.bits(),
+            revents: 0,
+        }
+    }
+    pub fn revents(&self) -> PollEvents {
+        PollEvents::from_bits_truncate(self.revents)
+    }
+}
+
+/// One buffer of a vectored write.
+#[repr(C)]
+pub struct IoVec<'a> {
+    base: *const u8,
+    len: usize,
+    _marker: PhantomData<&'a [u8]>,
+}
+
+impl<'a> IoVec<'a> {
+    /// A buffer to be written out by `writev`.
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self {
+            base: buf.as_ptr(),
+            len: buf.len(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// One buffer of a vectored read, laid out like `IoVec`.
+#[repr(C)]
+pub struct IoVecMut<'a> {
+    base: *mut u8,
+    len: usize,
+    _marker: PhantomData<&'a mut [u8]>,
+}
+
+impl<'a> IoVecMut<'a> {
+    /// A buffer to be filled by `readv`.
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self {
+            base: buf.as_mut_ptr(),
+            len: buf.len(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+#[repr(C)]
+pub struct TimeSpec {
+    pub sec: usize,
+    pub nsec: usize,
+}
+
+pub const F_GETFD: usize = 1;
+pub const F_SETFD: usize = 2;
+pub const F_GETFL: usize = 3;
+pub const F_SETFL: usize = 4;
+pub const FD_CLOEXEC: usize = 1;
+
 pub fn dup(fd: usize) -> isize {
     sys_dup(fd)
 }
+pub fn dup2(old_fd: usize, new_fd: usize) -> isize {
+    if old_fd == new_fd {
+        // dup3 rejects equal fds, dup2 only checks that old_fd is open
+        let ret = fcntl(old_fd, F_GETFD, 0);
+        return if ret < 0 { ret } else { new_fd as isize };
+    }
+    dup3(old_fd, new_fd, OpenFlags::empty())
+}
+pub fn dup3(old_fd: usize, new_fd: usize, flags: OpenFlags) -> isize {
+    sys_dup3(old_fd, new_fd, flags.bits)
+}
+pub fn fcntl(fd: usize, cmd: usize, arg: usize) -> isize {
+    sys_fcntl(fd, cmd, arg)
+}
 pub fn open(path: &str, flags: OpenFlags) -> isize {
     sys_open(path, flags.bits)
 }
@@ -28,3 +126,33 @@ pub fn read(fd: usize, buf: &mut [u8]) -> isize {
 pub fn write(fd: usize, buf: &[u8]) -> isize {
     sys_write(fd, buf)
 }
+pub fn mkdir(path: &str) -> isize {
+    sys_mkdir(path)
+}
+pub fn chdir(path: &str) -> isize {
+    sys_chdir(path)
+}
+pub fn getcwd(buf: &mut [u8]) -> isize {
+    sys_getcwd(buf)
+}
+pub fn readv(fd: usize, iov: &mut [IoVecMut]) -> isize {
+    sys_readv(fd, iov)
+}
+pub fn writev(fd: usize, iov: &[IoVec]) -> isize {
+    sys_writev(fd, iov)
+}
+pub fn ppoll(fds: &mut [PollFd], timeout: Option<&TimeSpec>) -> isize {
+    let timeout = timeout.map_or(core::ptr::null(), |timeout| timeout as *const _);
+    sys_ppoll(fds, timeout)
+}
+/// Wait at most `timeout_ms` for one of `fds` to be ready, forever if negative.
+pub fn poll(fds: &mut [PollFd], timeout_ms: isize) -> isize {
+    if timeout_ms < 0 {
+        return ppoll(fds, None);
+    }
+    let timeout = TimeSpec {
+        sec: timeout_ms as usize / 1000,
+        nsec: timeout_ms as usize % 1000 * 1_000_000,
+    };
+    ppoll(fds, Some(&timeout))
+}
@@ -4,9 +4,11 @@
 
 #[macro_use]
 pub mod console;
+mod errno;
 mod file;
 mod io;
 mod lang_items;
+mod mm;
 mod net;
 mod sync;
 mod syscall;
@@ -16,26 +18,71 @@ extern crate alloc;
 #[macro_use]
 extern crate bitflags;
 
-use core::ptr::addr_of_mut;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{NonNull, addr_of_mut, null_mut};
 
 use alloc::vec::Vec;
 use buddy_system_allocator::LockedHeap;
+pub use errno::*;
 pub use file::*;
 pub use io::*;
+pub use mm::*;
 pub use net::*;
 pub use sync::*;
 use syscall::*;
 pub use task::*;
 
 const USER_HEAP_SIZE: usize = 32768;
+const PAGE_SIZE: usize = 4096;
+/// The heap grows by at least this many bytes at a time.
+const HEAP_GROW_SIZE: usize = 4096 * 16;
 
 static mut HEAP_SPACE: [u8; USER_HEAP_SIZE] = [0; USER_HEAP_SIZE];
 
+/// Starts with `HEAP_SPACE` and grows with `sbrk` when it runs out.
+struct GrowableHeap(LockedHeap);
+
+impl GrowableHeap {
+    /// Move the program break up so that `layout` fits, return false if it can not move.
+    fn grow(&self, layout: &Layout) -> bool {
+        // buddy blocks are aligned to their size, twice the block always holds one
+        let block = layout.size().max(layout.align()).next_power_of_two();
+        let size = (block * 2).max(HEAP_GROW_SIZE).next_multiple_of(PAGE_SIZE);
+        let start = sbrk(size as isize);
+        if start < 0 {
+            return false;
+        }
+        let start = start as usize;
+        unsafe {
+            self.0.lock().add_to_heap(start, start + size);
+        }
+        true
+    }
+}
+
+unsafe impl GlobalAlloc for GrowableHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        loop {
+            if let Ok(ptr) = self.0.lock().alloc(layout) {
+                return ptr.as_ptr();
+            }
+            if !self.grow(&layout) {
+                return null_mut();
+            }
+        }
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0
+            .lock()
+            .dealloc(unsafe { NonNull::new_unchecked(ptr) }, layout);
+    }
+}
+
 #[global_allocator]
-static HEAP: LockedHeap = LockedHeap::empty();
+static HEAP: GrowableHeap = GrowableHeap(LockedHeap::empty());
 
 #[alloc_error_handler]
-pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
+pub fn handle_alloc_error(layout: Layout) -> ! {
     panic!("Heap allocation error, layout = {:?}", layout);
 }
 
@@ -43,7 +90,8 @@ pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
 #[unsafe(link_section = ".text.entry")]
 pub extern "C" fn _start(argc: usize, argv: usize) -> ! {
     unsafe {
-        HEAP.lock()
+        HEAP.0
+            .lock()
             .init(addr_of_mut!(HEAP_SPACE) as usize, USER_HEAP_SIZE);
     }
     let mut v: Vec<&'static str> = Vec::new();
@@ -0,0 +1,84 @@
+use super::*;
+
+bitflags! {
+    pub struct MmapProt: usize {
+        const READ = 1 << 0;
+        const WRITE = 1 << 1;
+        const EXEC = 1 << 2;
+    }
+}
+
+bitflags! {
+    pub struct MmapFlags: usize {
+        const SHARED = 1 << 0;
+        const PRIVATE = 1 << 1;
+        const ANONYMOUS = 1 << 5;
+    }
+}
+
+/// Move the program break to `addr`, return the new break.
+/// The break is left unchanged when it can not be moved, `brk(0)` queries it.
+pub fn brk(addr: usize) -> isize {
+    sys_brk(addr)
+}
+/// Grow the program break by `increment` bytes, return the old break or -1.
+pub fn sbrk(increment: isize) -> isize {
+    let old_brk = sys_brk(0);
+    let new_brk = old_brk + increment;
+    if sys_brk(new_brk as usize) != new_brk {
+        return -1;
+    }
+    old_brk
+}
+
+/// Return the start address of the mapping, or a negative errno.
+pub fn mmap(len: usize, prot: MmapProt, flags: MmapFlags, fd: usize, offset: usize) -> isize {
+    sys_mmap(0, len, prot.bits, flags.bits, fd, offset)
+}
+pub fn munmap(addr: usize, len: usize) -> isize {
+    sys_munmap(addr, len)
+}
+/// Change the protection of pages mapped by `mmap`, `MmapProt::empty()` makes them inaccessible.
+pub fn mprotect(addr: usize, len: usize, prot: MmapProt) -> isize {
+    sys_mprotect(addr, len, prot.bits)
+}
+
+pub const IPC_PRIVATE: usize = 0;
+pub const IPC_RMID: usize = 0;
+
+bitflags! {
+    pub struct ShmFlags: usize {
+        const IPC_CREAT = 0o1000;
+        const IPC_EXCL = 0o2000;
+        const SHM_RDONLY = 0o10000;
+    }
+}
+
+/// Return the id of the segment named `key`, or a negative errno.
+pub fn shmget(key: usize, size: usize, flags: ShmFlags) -> isize {
+    sys_shmget(key, size, flags.bits)
+}
+/// Return the address the segment is mapped at, or a negative errno.
+pub fn shmat(id: usize, flags: ShmFlags) -> isize {
+    sys_shmat(id, 0, flags.bits)
+}
+pub fn shmdt(addr: usize) -> isize {
+    sys_shmdt(addr)
+}
+pub fn shmctl(id: usize, cmd: usize) -> isize {
+    sys_shmctl(id, cmd, 0)
+}
+
+/// Usage of the kernel heap, in bytes except `failed`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct HeapStats {
+    pub total: usize,
+    pub in_use: usize,
+    pub peak: usize,
+    pub failed: usize,
+}
+
+pub fn heap_stats(stats: &mut HeapStats) -> isize {
+    sys_heap_stats(stats)
+}
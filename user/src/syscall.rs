@@ -1,20 +1,48 @@
+use super::{HeapStats, IoVec, IoVecMut, PollFd, RUsage, SchedParam, TimeSpec, Tms};
+
+const SYSCALL_GETCWD: usize = 17;
 const SYSCALL_DUP: usize = 24;
+const SYSCALL_FCNTL: usize = 25;
+/// 24 in Linux, which stays `dup` here for existing binaries
+const SYSCALL_DUP3: usize = 26;
 const SYSCALL_CONNECT: usize = 29;
 const SYSCALL_LISTEN: usize = 30;
 const SYSCALL_ACCEPT: usize = 31;
+const SYSCALL_MKDIR: usize = 34;
+const SYSCALL_CHDIR: usize = 49;
 const SYSCALL_OPEN: usize = 56;
 const SYSCALL_CLOSE: usize = 57;
 const SYSCALL_PIPE: usize = 59;
 const SYSCALL_READ: usize = 63;
 const SYSCALL_WRITE: usize = 64;
+const SYSCALL_READV: usize = 65;
+const SYSCALL_WRITEV: usize = 66;
+const SYSCALL_PPOLL: usize = 73;
 const SYSCALL_EXIT: usize = 93;
 const SYSCALL_SLEEP: usize = 101;
+const SYSCALL_SCHED_SETSCHEDULER: usize = 119;
+const SYSCALL_SCHED_GETSCHEDULER: usize = 120;
+const SYSCALL_SCHED_GETPARAM: usize = 121;
+const SYSCALL_SCHED_SETAFFINITY: usize = 122;
+const SYSCALL_SCHED_GETAFFINITY: usize = 123;
 const SYSCALL_YIELD: usize = 124;
 const SYSCALL_KILL: usize = 129;
+const SYSCALL_SETPRIORITY: usize = 140;
+const SYSCALL_GETPRIORITY: usize = 141;
+const SYSCALL_TIMES: usize = 153;
+const SYSCALL_GETRUSAGE: usize = 165;
 const SYSCALL_GET_TIME: usize = 169;
 const SYSCALL_GETPID: usize = 172;
+const SYSCALL_SHMGET: usize = 194;
+const SYSCALL_SHMCTL: usize = 195;
+const SYSCALL_SHMAT: usize = 196;
+const SYSCALL_SHMDT: usize = 197;
+const SYSCALL_BRK: usize = 214;
+const SYSCALL_MUNMAP: usize = 215;
 const SYSCALL_FORK: usize = 220;
 const SYSCALL_EXEC: usize = 221;
+const SYSCALL_MMAP: usize = 222;
+const SYSCALL_MPROTECT: usize = 226;
 const SYSCALL_WAITPID: usize = 260;
 const SYSCALL_THREAD_CREATE: usize = 1000;
 const SYSCALL_GETTID: usize = 1001;
@@ -34,6 +62,7 @@ const SYSCALL_EVENT_GET: usize = 3000;
 const SYSCALL_KEY_PRESSED: usize = 3001;
 const SYSCALL_CURSOR_UPDATE: usize = 3002;
 const SYSCALL_CURSOR_POS_GET: usize = 3003;
+const SYSCALL_HEAP_STATS: usize = 4000;
 
 fn syscall(id: usize, args: [usize; 3]) -> isize {
     let mut ret: isize;
@@ -49,10 +78,42 @@ fn syscall(id: usize, args: [usize; 3]) -> isize {
     ret
 }
 
+fn syscall6(id: usize, args: [usize; 6]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+        "ecall",
+        inlateout("x10") args[0] => ret,
+        in("x11") args[1],
+        in("x12") args[2],
+        in("x13") args[3],
+        in("x14") args[4],
+        in("x15") args[5],
+        in("x17") id
+        );
+    }
+    ret
+}
+
+pub fn sys_getcwd(buffer: &mut [u8]) -> isize {
+    syscall(
+        SYSCALL_GETCWD,
+        [buffer.as_mut_ptr() as usize, buffer.len(), 0],
+    )
+}
+
 pub fn sys_dup(fd: usize) -> isize {
     syscall(SYSCALL_DUP, [fd, 0, 0])
 }
 
+pub fn sys_dup3(old_fd: usize, new_fd: usize, flags: u32) -> isize {
+    syscall(SYSCALL_DUP3, [old_fd, new_fd, flags as usize])
+}
+
+pub fn sys_fcntl(fd: usize, cmd: usize, arg: usize) -> isize {
+    syscall(SYSCALL_FCNTL, [fd, cmd, arg])
+}
+
 pub fn sys_connect(dest: u32, sport: u16, dport: u16) -> isize {
     syscall(
         SYSCALL_CONNECT,
@@ -69,6 +130,14 @@ pub fn sys_accept(socket_fd: usize) -> isize {
     syscall(SYSCALL_ACCEPT, [socket_fd, 0, 0])
 }
 
+pub fn sys_mkdir(path: &str) -> isize {
+    syscall(SYSCALL_MKDIR, [path.as_ptr() as usize, 0, 0])
+}
+
+pub fn sys_chdir(path: &str) -> isize {
+    syscall(SYSCALL_CHDIR, [path.as_ptr() as usize, 0, 0])
+}
+
 pub fn sys_open(path: &str, flags: u32) -> isize {
     syscall(SYSCALL_OPEN, [path.as_ptr() as usize, flags as usize, 0])
 }
@@ -92,6 +161,21 @@ pub fn sys_write(fd: usize, buffer: &[u8]) -> isize {
     syscall(SYSCALL_WRITE, [fd, buffer.as_ptr() as usize, buffer.len()])
 }
 
+pub fn sys_readv(fd: usize, iov: &mut [IoVecMut]) -> isize {
+    syscall(SYSCALL_READV, [fd, iov.as_ptr() as usize, iov.len()])
+}
+
+pub fn sys_writev(fd: usize, iov: &[IoVec]) -> isize {
+    syscall(SYSCALL_WRITEV, [fd, iov.as_ptr() as usize, iov.len()])
+}
+
+pub fn sys_ppoll(fds: &mut [PollFd], timeout: *const TimeSpec) -> isize {
+    syscall(
+        SYSCALL_PPOLL,
+        [fds.as_mut_ptr() as usize, fds.len(), timeout as usize],
+    )
+}
+
 pub fn sys_exit(exit_code: i32) -> ! {
     syscall(SYSCALL_EXIT, [exit_code as usize, 0, 0]);
     panic!("sys_exit never returns!");
@@ -101,6 +185,35 @@ pub fn sys_sleep(sleep_ms: usize) -> isize {
     syscall(SYSCALL_SLEEP, [sleep_ms, 0, 0])
 }
 
+pub fn sys_sched_setscheduler(tid: usize, policy: usize, param: &SchedParam) -> isize {
+    syscall(
+        SYSCALL_SCHED_SETSCHEDULER,
+        [tid, policy, param as *const _ as usize],
+    )
+}
+
+pub fn sys_sched_getscheduler(tid: usize) -> isize {
+    syscall(SYSCALL_SCHED_GETSCHEDULER, [tid, 0, 0])
+}
+
+pub fn sys_sched_getparam(tid: usize, param: &mut SchedParam) -> isize {
+    syscall(SYSCALL_SCHED_GETPARAM, [tid, param as *mut _ as usize, 0])
+}
+
+pub fn sys_sched_setaffinity(tid: usize, mask: &usize) -> isize {
+    syscall(
+        SYSCALL_SCHED_SETAFFINITY,
+        [tid, size_of::<usize>(), mask as *const _ as usize],
+    )
+}
+
+pub fn sys_sched_getaffinity(tid: usize, mask: &mut usize) -> isize {
+    syscall(
+        SYSCALL_SCHED_GETAFFINITY,
+        [tid, size_of::<usize>(), mask as *mut _ as usize],
+    )
+}
+
 pub fn sys_yield() -> isize {
     syscall(SYSCALL_YIELD, [0, 0, 0])
 }
@@ -109,6 +222,25 @@ pub fn sys_kill(pid: usize, signal: i32) -> isize {
     syscall(SYSCALL_KILL, [pid, signal as usize, 0])
 }
 
+pub fn sys_setpriority(which: usize, who: usize, nice: isize) -> isize {
+    syscall(SYSCALL_SETPRIORITY, [which, who, nice as usize])
+}
+
+pub fn sys_getpriority(which: usize, who: usize) -> isize {
+    syscall(SYSCALL_GETPRIORITY, [which, who, 0])
+}
+
+pub fn sys_times(buf: &mut Tms) -> isize {
+    syscall(SYSCALL_TIMES, [buf as *mut _ as usize, 0, 0])
+}
+
+pub fn sys_getrusage(who: isize, usage: &mut RUsage) -> isize {
+    syscall(
+        SYSCALL_GETRUSAGE,
+        [who as usize, usage as *mut _ as usize, 0],
+    )
+}
+
 pub fn sys_get_time() -> isize {
     syscall(SYSCALL_GET_TIME, [0, 0, 0])
 }
@@ -117,6 +249,45 @@ pub fn sys_getpid() -> isize {
     syscall(SYSCALL_GETPID, [0, 0, 0])
 }
 
+pub fn sys_shmget(key: usize, size: usize, flags: usize) -> isize {
+    syscall(SYSCALL_SHMGET, [key, size, flags])
+}
+
+pub fn sys_shmctl(id: usize, cmd: usize, buf: usize) -> isize {
+    syscall(SYSCALL_SHMCTL, [id, cmd, buf])
+}
+
+pub fn sys_shmat(id: usize, addr: usize, flags: usize) -> isize {
+    syscall(SYSCALL_SHMAT, [id, addr, flags])
+}
+
+pub fn sys_shmdt(addr: usize) -> isize {
+    syscall(SYSCALL_SHMDT, [addr, 0, 0])
+}
+
+pub fn sys_brk(addr: usize) -> isize {
+    syscall(SYSCALL_BRK, [addr, 0, 0])
+}
+
+pub fn sys_mmap(
+    addr: usize,
+    len: usize,
+    prot: usize,
+    flags: usize,
+    fd: usize,
+    offset: usize,
+) -> isize {
+    syscall6(SYSCALL_MMAP, [addr, len, prot, flags, fd, offset])
+}
+
+pub fn sys_munmap(addr: usize, len: usize) -> isize {
+    syscall(SYSCALL_MUNMAP, [addr, len, 0])
+}
+
+pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> isize {
+    syscall(SYSCALL_MPROTECT, [addr, len, prot])
+}
+
 pub fn sys_fork() -> isize {
     syscall(SYSCALL_FORK, [0, 0, 0])
 }
@@ -128,8 +299,11 @@ pub fn sys_exec(path: &str, args: &[*const u8]) -> isize {
     )
 }
 
-pub fn sys_waitpid(pid: isize, exit_code: *mut i32) -> isize {
-    syscall(SYSCALL_WAITPID, [pid as usize, exit_code as usize, 0])
+pub fn sys_waitpid(pid: isize, exit_code: *mut i32, rusage: *mut RUsage) -> isize {
+    syscall(
+        SYSCALL_WAITPID,
+        [pid as usize, exit_code as usize, rusage as usize],
+    )
 }
 
 pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
@@ -203,3 +377,7 @@ pub fn sys_cursor_update(pos_x: usize, pos_y: usize) -> isize {
 pub fn sys_cursor_pos_get() -> isize {
     syscall(SYSCALL_CURSOR_POS_GET, [0, 0, 0])
 }
+
+pub fn sys_heap_stats(stats: &mut HeapStats) -> isize {
+    syscall(SYSCALL_HEAP_STATS, [stats as *mut _ as usize, 0, 0])
+}
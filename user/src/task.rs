@@ -1,5 +1,7 @@
 use super::*;
 
+const EAGAIN: isize = Errno::EAGAIN.as_ret();
+
 pub fn exit(exit_code: i32) -> ! {
     sys_exit(exit_code);
 }
@@ -20,11 +22,11 @@ pub fn exec(path: &str, args: &[*const u8]) -> isize {
 }
 pub fn wait(exit_code: &mut i32) -> isize {
     loop {
-        match sys_waitpid(-1, exit_code as *mut _) {
-            -2 => {
+        match sys_waitpid(-1, exit_code as *mut _, core::ptr::null_mut()) {
+            EAGAIN => {
                 yield_();
             }
-            // -1 or a real pid
+            // ECHILD or a real pid
             exit_pid => return exit_pid,
         }
     }
@@ -32,18 +34,31 @@ pub fn wait(exit_code: &mut i32) -> isize {
 
 pub fn waitpid(pid: usize, exit_code: &mut i32) -> isize {
     loop {
-        match sys_waitpid(pid as isize, exit_code as *mut _) {
-            -2 => {
+        match sys_waitpid(pid as isize, exit_code as *mut _, core::ptr::null_mut()) {
+            EAGAIN => {
                 yield_();
             }
-            // -1 or a real pid
+            // ECHILD or a real pid
+            exit_pid => return exit_pid,
+        }
+    }
+}
+
+/// Like `waitpid`, and fill `rusage` with the CPU time of the child and its reaped children.
+pub fn waitpid_rusage(pid: usize, exit_code: &mut i32, rusage: &mut RUsage) -> isize {
+    loop {
+        match sys_waitpid(pid as isize, exit_code as *mut _, rusage as *mut _) {
+            EAGAIN => {
+                yield_();
+            }
+            // ECHILD or a real pid
             exit_pid => return exit_pid,
         }
     }
 }
 
 pub fn waitpid_nb(pid: usize, exit_code: &mut i32) -> isize {
-    sys_waitpid(pid as isize, exit_code as *mut _)
+    sys_waitpid(pid as isize, exit_code as *mut _, core::ptr::null_mut())
 }
 
 bitflags! {
@@ -60,6 +75,120 @@ pub fn kill(pid: usize, signal: i32) -> isize {
     sys_kill(pid, signal)
 }
 
+const PRIO_PROCESS: usize = 0;
+
+/// Set the niceness of process `pid`, or of the current one if `pid` is 0.
+/// Values out of -20..=19 are clamped.
+pub fn setpriority(pid: usize, nice: isize) -> isize {
+    sys_setpriority(PRIO_PROCESS, pid, nice)
+}
+/// Return `20 - nice` of process `pid` as the kernel does, so errors stay negative.
+pub fn getpriority(pid: usize) -> isize {
+    sys_getpriority(PRIO_PROCESS, pid)
+}
+/// Add `inc` to the niceness of the current process.
+pub fn nice(inc: isize) -> isize {
+    let prio = getpriority(0);
+    if prio < 0 {
+        return prio;
+    }
+    setpriority(0, 20 - prio + inc)
+}
+
+pub const SCHED_NORMAL: usize = 0;
+/// Real-time, runs until it blocks or yields, or a higher priority wakes up.
+pub const SCHED_FIFO: usize = 1;
+/// Real-time, like `SCHED_FIFO` but takes turns with threads of the same priority.
+pub const SCHED_RR: usize = 2;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SchedParam {
+    /// 1..=99 for real-time policies, higher runs first, 0 for `SCHED_NORMAL`
+    pub sched_priority: i32,
+}
+
+/// Set the policy and priority of thread `tid` of this process.
+pub fn sched_setscheduler(tid: usize, policy: usize, priority: i32) -> isize {
+    let param = SchedParam {
+        sched_priority: priority,
+    };
+    sys_sched_setscheduler(tid, policy, &param)
+}
+pub fn sched_getscheduler(tid: usize) -> isize {
+    sys_sched_getscheduler(tid)
+}
+/// Return the real-time priority of thread `tid`, or an error.
+pub fn sched_getparam(tid: usize) -> isize {
+    let mut param = SchedParam::default();
+    match sys_sched_getparam(tid, &mut param) {
+        err if err < 0 => err,
+        _ => param.sched_priority as isize,
+    }
+}
+
+/// Let thread `tid` of this process run only on the harts in `mask`, one bit each.
+pub fn sched_setaffinity(tid: usize, mask: usize) -> isize {
+    sys_sched_setaffinity(tid, &mask)
+}
+/// Return the harts thread `tid` may run on, or an error.
+pub fn sched_getaffinity(tid: usize) -> isize {
+    let mut mask = 0usize;
+    match sys_sched_getaffinity(tid, &mut mask) {
+        err if err < 0 => err,
+        _ => mask as isize,
+    }
+}
+
+/// Clock ticks per second of `times`.
+pub const CLOCKS_PER_SEC: isize = 100;
+
+/// CPU time in clock ticks.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Tms {
+    pub tms_utime: usize,
+    pub tms_stime: usize,
+    /// of the reaped children, including their own reaped children
+    pub tms_cutime: usize,
+    pub tms_cstime: usize,
+}
+
+/// Fill `buf` with the CPU time of this process, return the clock ticks since boot.
+pub fn times(buf: &mut Tms) -> isize {
+    sys_times(buf)
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TimeVal {
+    pub sec: usize,
+    pub usec: usize,
+}
+
+impl TimeVal {
+    pub fn as_us(&self) -> usize {
+        self.sec * 1_000_000 + self.usec
+    }
+}
+
+/// `struct rusage` of Linux, only the CPU times are counted.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RUsage {
+    pub ru_utime: TimeVal,
+    pub ru_stime: TimeVal,
+    pub ru_others: [isize; 14],
+}
+
+pub const RUSAGE_SELF: isize = 0;
+pub const RUSAGE_CHILDREN: isize = -1;
+pub const RUSAGE_THREAD: isize = 1;
+
+pub fn getrusage(who: isize, usage: &mut RUsage) -> isize {
+    sys_getrusage(who, usage)
+}
+
 pub fn sleep(sleep_ms: usize) {
     sys_sleep(sleep_ms);
 }
@@ -73,7 +202,7 @@ pub fn gettid() -> isize {
 pub fn waittid(tid: usize) -> isize {
     loop {
         match sys_waittid(tid) {
-            -2 => {
+            EAGAIN => {
                 yield_();
             }
             exit_code => return exit_code,